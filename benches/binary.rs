@@ -0,0 +1,60 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use json_monkey_rs::binary;
+use json_monkey_rs::jir::JirParser;
+
+/// Rule programs of the size services store, each a few dozen rules.
+fn corpus() -> Vec<String> {
+    (0..20)
+        .map(|program| {
+            let rules = (0..40)
+                .map(|rule| {
+                    format!(
+                        r#"["$let", "rule_{program}_{rule}", ["$fn", [["order", "any"], ["limit", "number", {limit}]],
+                            ["$if", ["$and",
+                                    ["$gte", ["$getIn", ["$ref", "order"], ["$quote", ["customer", "age"]]], 18],
+                                    ["$eq", ["$getIn", ["$ref", "order"], ["$quote", ["country"]]], "KR"],
+                                    ["$lt", ["$getIn", ["$ref", "order"], ["$quote", ["total"]]], ["$ref", "limit"]]],
+                                ["$quasiquote", {{"rule": "rule {rule} of program {program}", "discount": {discount},
+                                    "total": ["$unquote", ["$add", ["$getIn", ["$ref", "order"], ["$quote", ["total"]]], -{fee}]]}}],
+                                null]]]"#,
+                        program = program,
+                        rule = rule,
+                        limit = 1000 * (rule + 1),
+                        discount = 0.05 * (rule % 7) as f64,
+                        fee = rule * 3 + 1,
+                    )
+                })
+                .collect::<Vec<_>>();
+            format!(r#"["$do", {}]"#, rules.join(", "))
+        })
+        .collect()
+}
+
+fn decode(c: &mut Criterion) {
+    let texts = corpus();
+    let encoded = texts
+        .iter()
+        .map(|text| binary::encode(&JirParser::parse(text).expect("a valid program")))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("decode");
+    group.bench_function("parse_json", |b| {
+        b.iter(|| {
+            for text in &texts {
+                black_box(JirParser::parse_json(black_box(text)).unwrap());
+            }
+        })
+    });
+    group.bench_function("binary::decode", |b| {
+        b.iter(|| {
+            for bytes in &encoded {
+                black_box(binary::decode(black_box(bytes)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -1,10 +1,119 @@
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::forms::CustomForm;
+use crate::span::Span;
+use crate::typecheck::Type;
 use crate::value::Value;
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct Ident(pub String);
 
+/// A `$struct` field with an optional type annotation and default value.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub ty: Option<Type>,
+    pub default: Option<AstNode>,
+}
+
+/// An `$enum` variant and the names of the values it carries.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Param>,
+}
+
+/// A `$match` arm: the body runs when the pattern matches the scrutinee.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: AstNode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `"_"`, matches anything.
+    Wildcard,
+    /// Matches a variant by tag. When bindings are given there must be one per
+    /// carried value, and `None` (written `"_"`) ignores that value.
+    Variant(String, Vec<Option<Ident>>),
+}
+
+/// A function parameter with an optional type annotation.
 #[derive(Debug, Clone)]
-pub enum AstNode {
+pub struct Param {
+    pub ident: Ident,
+    pub ty: Option<Type>,
+    /// Evaluated when the argument is omitted, after the parameters before it are
+    /// bound.
+    pub default: Option<AstNode>,
+    /// A rest parameter (`"...name"`) collects the remaining positional arguments
+    /// into an array, and its annotation is the type of their elements. Only the
+    /// last parameter may be one.
+    pub rest: bool,
+}
+
+/// A node of the syntax tree and the source range it was parsed from. Nodes built
+/// in code have no span.
+#[derive(Clone)]
+pub struct AstNode {
+    pub kind: AstKind,
+    pub span: Option<Span>,
+    pub id: NodeId,
+}
+
+impl AstNode {
+    pub fn new(kind: AstKind, span: Option<Span>) -> Self {
+        Self {
+            kind,
+            span,
+            id: NodeId::next(),
+        }
+    }
+}
+
+/// Identifies a node for as long as the process runs: ids are never reused, even
+/// once the node is dropped. A clone keeps its original's id, as it is the same code.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct NodeId(u64);
+
+impl NodeId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl From<AstKind> for AstNode {
+    fn from(kind: AstKind) -> Self {
+        Self::new(kind, None)
+    }
+}
+
+impl From<AstKind> for Box<AstNode> {
+    fn from(kind: AstKind) -> Self {
+        Box::new(kind.into())
+    }
+}
+
+impl From<AstKind> for Rc<AstNode> {
+    fn from(kind: AstKind) -> Self {
+        Rc::new(kind.into())
+    }
+}
+
+/// Leaves out spans, so that the same tree parsed from different sources, or built
+/// in code, prints the same.
+impl fmt::Debug for AstNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AstKind {
     Literal(Value),
     Ident(Ident),
 
@@ -16,10 +125,119 @@ pub enum AstNode {
     Not(Box<AstNode>),
 
     Eq(Box<AstNode>, Box<AstNode>),
+    /// `$eq` with more than two operands: true when every operand is equal.
+    EqAll(Vec<AstNode>),
     NotEq(Box<AstNode>, Box<AstNode>),
+    Lt(Box<AstNode>, Box<AstNode>),
+    Lte(Box<AstNode>, Box<AstNode>),
+    Gt(Box<AstNode>, Box<AstNode>),
+    Gte(Box<AstNode>, Box<AstNode>),
 
     If(Box<AstNode>, Box<AstNode>, Option<Box<AstNode>>),
     While(Box<AstNode>, Box<AstNode>),
 
+    /// Fails with `EvalError::AssertionFailed` unless the condition holds. Keeps the
+    /// condition's source JSON for the report.
+    Assert(Box<AstNode>, Option<Box<AstNode>>, String),
+
     Bind(Ident, Box<AstNode>),
+    Let(Ident, Option<Type>, Box<AstNode>),
+    Do(Vec<AstNode>),
+
+    Array(Vec<AstNode>),
+    /// An object built by `$quasiquote` with `$unquote`d values.
+    Object(Vec<(String, AstNode)>),
+    Fn(Vec<Param>, Rc<AstNode>),
+    /// Positional arguments, then named arguments given as a trailing JSON object.
+    Call(Box<AstNode>, Vec<AstNode>, Vec<(String, AstNode)>),
+
+    Map(Box<AstNode>, Box<AstNode>),
+    Filter(Box<AstNode>, Box<AstNode>),
+    Reduce(Box<AstNode>, Box<AstNode>, Box<AstNode>),
+    Some(Box<AstNode>, Box<AstNode>),
+    Every(Box<AstNode>, Box<AstNode>),
+
+    /// Orders items by `Value::total_cmp`, of the optional key function's results if
+    /// one is given. The sort is stable.
+    Sort(Box<AstNode>, Option<Box<AstNode>>),
+    /// `[key, items]` pairs ordered by key.
+    GroupBy(Box<AstNode>, Box<AstNode>),
+    Unique(Box<AstNode>),
+    Union(Box<AstNode>, Box<AstNode>),
+    Intersect(Box<AstNode>, Box<AstNode>),
+    Difference(Box<AstNode>, Box<AstNode>),
+
+    Range(Box<AstNode>, Option<Box<AstNode>>, Option<Box<AstNode>>),
+    Take(Box<AstNode>, Box<AstNode>),
+    TakeWhile(Box<AstNode>, Box<AstNode>),
+    Collect(Box<AstNode>),
+    Gen(Vec<Param>, Rc<AstNode>),
+    /// Only valid in statement position of a `$gen` body, which `JirParser` checks.
+    Yield(Box<AstNode>),
+
+    StructDecl(String, Vec<Field>),
+    New(String, Vec<(String, AstNode)>),
+    GetField(Box<AstNode>, String),
+    With(Box<AstNode>, Vec<(String, AstNode)>),
+
+    /// The first argument that isn't `null`. Later arguments are only evaluated when
+    /// needed, and an argument that refers to an unbound identifier counts as `null`.
+    Coalesce(Vec<AstNode>),
+    /// Optional chaining: follows keys and indices through objects, arrays and
+    /// structs, giving `null` as soon as a step is missing.
+    SafeGet(Box<AstNode>, Vec<AstNode>),
+    /// Whether the identifier is bound in the current scope or an enclosing one.
+    Defined(Ident),
+    /// Reads a nested value. Missing paths give `null`, or an error when the optional
+    /// flag is true.
+    GetIn(Box<AstNode>, Box<AstNode>, Option<Box<AstNode>>),
+    /// Runs a `crate::query` string against a value, returning the matches.
+    Query(Box<AstNode>, Box<AstNode>),
+    SetIn(Box<AstNode>, Box<AstNode>, Box<AstNode>),
+    UpdateIn(Box<AstNode>, Box<AstNode>, Box<AstNode>),
+
+    EnumDecl(String, Vec<Variant>),
+    NewVariant(String, String, Vec<AstNode>),
+    Tag(Box<AstNode>),
+    Match(Box<AstNode>, Vec<MatchArm>),
+
+    Now,
+    DateTime(Box<AstNode>),
+    FormatDateTime(Box<AstNode>, Option<Box<AstNode>>),
+    DateAdd(Box<AstNode>, Box<AstNode>, DateUnit),
+    DateDiff(Box<AstNode>, Box<AstNode>, DateUnit),
+    DatePart(Box<AstNode>, DateComponent),
+
+    /// A host form from a `crate::forms::FormRegistry`, with its parsed operands.
+    Custom(CustomForm, Vec<AstNode>),
+
+    /// Stands in for an expression that failed to parse, in trees from
+    /// `JirParser::parse_all`. Fails when evaluated.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateUnit {
+    Years,
+    Months,
+    Weeks,
+    Days,
+    Hours,
+    Minutes,
+    Seconds,
+    Milliseconds,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateComponent {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Millisecond,
+    /// ISO weekday, Monday is 1 and Sunday is 7.
+    Weekday,
+    DayOfYear,
 }
@@ -0,0 +1,377 @@
+//! A compact binary encoding of JIR, for storing compiled programs and sending them
+//! between services. It is quicker to decode than JSON text is to parse.
+//!
+//! An encoding starts with a header, the bytes `JIR` and a format version, followed
+//! by the program's JIR as a single MessagePack value. Arrays, maps, strings, `nil`
+//! and booleans stand for their JSON equivalents, and integers and floats for
+//! numbers. Other MessagePack types are rejected.
+//!
+//! Decoding reads the JIR back into the same `Json` trees as the text syntaxes, so
+//! programs are checked by `JirParser` as usual and host forms are parsed by their
+//! own parsers. Spans of decoded values are byte ranges of the encoding, all on line
+//! 1 with the byte offset as their column.
+//!
+//! The format is JIR rather than a serialization of `AstNode`, so decoding re-parses
+//! it. JIR is the interchange format the rest of the crate already keeps stable,
+//! whereas `AstNode` changes with every new form, and a host form's node can only be
+//! rebuilt by its parser anyway. Re-parsing costs little next to reading text, which
+//! is the part the binary format saves: `benches/binary.rs` compares the two.
+
+use std::convert::TryFrom;
+
+use rmp::encode;
+use rmp::Marker;
+use serde_json::Value as JsonValue;
+
+use crate::ast::AstNode;
+use crate::emit::to_jir;
+use crate::jir::{JirParser, ParseError};
+use crate::json::{Json, JsonKind, MAX_DEPTH};
+use crate::span::{Located, Span};
+
+/// The bytes every encoding starts with, before the version.
+pub const MAGIC: &[u8] = b"JIR";
+
+/// The version of the format written by `encode`, and the only one `read` accepts.
+pub const VERSION: u8 = 1;
+
+/// Encodes the canonical JIR of a tree, as written by `crate::emit::to_jir`.
+pub fn encode(node: &AstNode) -> Vec<u8> {
+    encode_jir(&to_jir(node))
+}
+
+/// Encodes JIR as it is, for programs that `to_jir` can't write, such as ones with
+/// host forms that parse their own operands.
+pub fn encode_jir(jir: &JsonValue) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    write(jir, &mut out);
+    out
+}
+
+/// Decodes a program with the built-in forms. Use `JirParser::read_binary` for
+/// host forms.
+pub fn decode(bytes: &[u8]) -> Result<AstNode, Located<ParseError>> {
+    JirParser::default().read_binary(bytes)
+}
+
+/// Reads an encoding into the JIR it stands for.
+pub fn read(bytes: &[u8]) -> Result<Json, Located<ParseError>> {
+    let mut decoder = Decoder {
+        bytes,
+        pos: 0,
+        depth: 0,
+    };
+    if !bytes.starts_with(MAGIC) {
+        return Err(decoder.error("a JIR header"));
+    }
+    decoder.pos = MAGIC.len();
+    let version = decoder.take(1, "a version")?[0];
+    if version != VERSION {
+        return Err(Located::new(
+            ParseError::UnsupportedBinaryVersion(version),
+            Some(decoder.span(MAGIC.len())),
+        ));
+    }
+    let value = decoder.value()?;
+    if decoder.pos < bytes.len() {
+        return Err(decoder.error("end of input"));
+    }
+    Ok(value)
+}
+
+fn write(jir: &JsonValue, out: &mut Vec<u8>) {
+    const WRITE: &str = "writing to a Vec can't fail";
+    match jir {
+        JsonValue::Null => encode::write_nil(out).expect(WRITE),
+        JsonValue::Bool(b) => encode::write_bool(out, *b).expect(WRITE),
+        JsonValue::Number(n) => {
+            if let Some(n) = n.as_i64() {
+                encode::write_sint(out, n).expect(WRITE);
+            } else if let Some(n) = n.as_u64() {
+                encode::write_uint(out, n).expect(WRITE);
+            } else {
+                encode::write_f64(out, n.as_f64().unwrap_or(f64::NAN)).expect(WRITE);
+            }
+        }
+        JsonValue::String(s) => encode::write_str(out, s).expect(WRITE),
+        JsonValue::Array(items) => {
+            encode::write_array_len(out, length(items.len())).expect(WRITE);
+            for item in items {
+                write(item, out);
+            }
+        }
+        JsonValue::Object(entries) => {
+            encode::write_map_len(out, length(entries.len())).expect(WRITE);
+            for (key, value) in entries {
+                encode::write_str(out, key).expect(WRITE);
+                write(value, out);
+            }
+        }
+    }
+}
+
+fn length(len: usize) -> u32 {
+    u32::try_from(len).expect("at most 2^32 - 1 items")
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn value(&mut self) -> Result<Json, Located<ParseError>> {
+        let start = self.pos;
+        let marker = Marker::from_u8(self.take(1, "a value")?[0]);
+        let kind = match marker {
+            Marker::Null => JsonKind::Null,
+            Marker::True => JsonKind::Bool(true),
+            Marker::False => JsonKind::Bool(false),
+            Marker::FixPos(n) => JsonKind::Number(n.to_string()),
+            Marker::FixNeg(n) => JsonKind::Number(n.to_string()),
+            Marker::U8 => JsonKind::Number(self.uint(1)?.to_string()),
+            Marker::U16 => JsonKind::Number(self.uint(2)?.to_string()),
+            Marker::U32 => JsonKind::Number(self.uint(4)?.to_string()),
+            Marker::U64 => JsonKind::Number(self.uint(8)?.to_string()),
+            Marker::I8 => JsonKind::Number((self.uint(1)? as u8 as i8).to_string()),
+            Marker::I16 => JsonKind::Number((self.uint(2)? as u16 as i16).to_string()),
+            Marker::I32 => JsonKind::Number((self.uint(4)? as u32 as i32).to_string()),
+            Marker::I64 => JsonKind::Number((self.uint(8)? as i64).to_string()),
+            Marker::F32 => float(f32::from_bits(self.uint(4)? as u32).into()),
+            Marker::F64 => float(f64::from_bits(self.uint(8)?)),
+            Marker::FixStr(len) => self.string(len.into())?,
+            Marker::Str8 => self.sized(1, Self::string)?,
+            Marker::Str16 => self.sized(2, Self::string)?,
+            Marker::Str32 => self.sized(4, Self::string)?,
+            Marker::FixArray(len) => self.nested(start, len.into(), Self::array)?,
+            Marker::Array16 => self.sized(2, |d, len| d.nested(start, len, Self::array))?,
+            Marker::Array32 => self.sized(4, |d, len| d.nested(start, len, Self::array))?,
+            Marker::FixMap(len) => self.nested(start, len.into(), Self::map)?,
+            Marker::Map16 => self.sized(2, |d, len| d.nested(start, len, Self::map))?,
+            Marker::Map32 => self.sized(4, |d, len| d.nested(start, len, Self::map))?,
+            _ => {
+                self.pos = start;
+                return Err(self.error("a JIR value"));
+            }
+        };
+        Ok(Json {
+            kind,
+            span: Span {
+                end: self.pos,
+                ..self.span(start)
+            },
+        })
+    }
+
+    /// Reads a `bytes`-long length, then what it's the length of.
+    fn sized(
+        &mut self,
+        bytes: usize,
+        read: impl FnOnce(&mut Self, usize) -> Result<JsonKind, Located<ParseError>>,
+    ) -> Result<JsonKind, Located<ParseError>> {
+        let len = self.uint(bytes)? as usize;
+        read(self, len)
+    }
+
+    fn string(&mut self, len: usize) -> Result<JsonKind, Located<ParseError>> {
+        let start = self.pos;
+        let bytes = self.take(len, "a string")?;
+        match std::str::from_utf8(bytes) {
+            Ok(s) => Ok(JsonKind::String(s.into())),
+            Err(e) => {
+                self.pos = start + e.valid_up_to();
+                Err(self.error("UTF-8"))
+            }
+        }
+    }
+
+    fn nested(
+        &mut self,
+        start: usize,
+        len: usize,
+        read: fn(&mut Self, usize) -> Result<JsonKind, Located<ParseError>>,
+    ) -> Result<JsonKind, Located<ParseError>> {
+        if self.depth == MAX_DEPTH {
+            self.pos = start;
+            return Err(self.error(&format!("at most {} levels of nesting", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let kind = read(self, len);
+        self.depth -= 1;
+        kind
+    }
+
+    fn array(&mut self, len: usize) -> Result<JsonKind, Located<ParseError>> {
+        // Every item takes at least a byte, which bounds what a bad length can
+        // allocate.
+        let mut items = Vec::with_capacity(len.min(self.remaining()));
+        for _ in 0..len {
+            items.push(self.value()?);
+        }
+        Ok(JsonKind::Array(items))
+    }
+
+    fn map(&mut self, len: usize) -> Result<JsonKind, Located<ParseError>> {
+        let mut entries = Vec::with_capacity(len.min(self.remaining() / 2));
+        for _ in 0..len {
+            let key = self.value()?;
+            let key = match key.kind {
+                JsonKind::String(key) => key,
+                _ => {
+                    return Err(Located::new(
+                        ParseError::InvalidBinary("expected a string key".into()),
+                        Some(key.span),
+                    ))
+                }
+            };
+            entries.push((key, self.value()?));
+        }
+        Ok(JsonKind::Object(entries))
+    }
+
+    /// A big-endian unsigned integer of `bytes` bytes.
+    fn uint(&mut self, bytes: usize) -> Result<u64, Located<ParseError>> {
+        let bytes = self.take(bytes, "a number")?;
+        Ok(bytes.iter().fold(0, |n, &b| n << 8 | u64::from(b)))
+    }
+
+    fn take(&mut self, len: usize, expected: &str) -> Result<&'a [u8], Located<ParseError>> {
+        if len > self.remaining() {
+            self.pos = self.bytes.len();
+            return Err(self.error(expected));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: (start + 1).min(self.bytes.len()).max(start),
+            line: 1,
+            column: start + 1,
+            source: 0,
+        }
+    }
+
+    fn error(&self, expected: &str) -> Located<ParseError> {
+        Located::new(
+            ParseError::InvalidBinary(format!("expected {}", expected)),
+            Some(self.span(self.pos)),
+        )
+    }
+}
+
+/// The JSON lexeme of a float. Infinities and NaN are written as Rust prints them,
+/// which `JirParser` rejects like any other unsupported number.
+fn float(n: f64) -> JsonKind {
+    let lexeme = match serde_json::Number::from_f64(n) {
+        Some(n) => n.to_string(),
+        None => n.to_string(),
+    };
+    JsonKind::Number(lexeme)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::forms::{Form, FormRegistry};
+    use crate::json;
+    use crate::value::Value;
+
+    #[test]
+    fn it_decodes_what_it_encodes() -> Result<(), Located<ParseError>> {
+        let programs = [
+            r#"["$let", "inc", ["$fn", ["n", ["by", "number", 1]],
+                ["$add", ["$ref", "n"], ["$ref", "by"], -2.5, 300, -70000, 1e300]]]"#,
+            r#"["$if", ["$and", ["$gt", ["$ref", "age"], 17], ["$not", null]],
+                ["$quasiquote", {"name": ["$unquote", ["$ref", "name"]], "tags": ["a", "ü"]}],
+                ["$quote", [true, false, {}, []]]]"#,
+            r#"["$match", ["$ref", "shape"], [["Circle", "r"], ["$ref", "r"]], ["_", 0]]"#,
+        ];
+        for program in programs.iter() {
+            let node = JirParser::parse(program)?;
+            let bytes = encode(&node);
+            assert!(bytes.starts_with(b"JIR\x01"));
+            assert!(bytes.len() < json::parse(program)?.to_string().len());
+            assert_eq!(format!("{:?}", decode(&bytes)?), format!("{:?}", node));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_jir_as_encoded() -> Result<(), Located<ParseError>> {
+        let jir = json!(["$call", ["$ref", "f"], 0, -1, 255, u64::MAX, 0.1, "", {"k": null}]);
+        assert_eq!(read(&encode_jir(&jir))?.to_string(), jir.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_host_forms_with_the_readers_forms() -> Result<(), Located<ParseError>> {
+        let mut forms = FormRegistry::builtin();
+        forms.register(Form::custom("$double", 1, Some(1), |interpreter, args| {
+            Ok(Value::Number(
+                interpreter.eval(&args[0])?.to_number()? * 2.0,
+            ))
+        }));
+        let bytes = encode_jir(&json!(["$double", 21]));
+        assert!(matches!(
+            decode(&bytes).map_err(|e| e.error),
+            Err(ParseError::UnknownForm(name)) if name == "$double"
+        ));
+        let node = JirParser::new(forms.into()).read_binary(&bytes)?;
+        assert_eq!(crate::emit::to_jir(&node), json!(["$double", 21]));
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_other_versions_and_malformed_input() {
+        let bytes = encode_jir(&json!(["$ref", "x"]));
+        let error = |bytes: &[u8]| read(bytes).expect_err("malformed").error;
+
+        assert!(matches!(error(b"{}"), ParseError::InvalidBinary(_)));
+        assert!(matches!(error(b"JIR"), ParseError::InvalidBinary(_)));
+        assert!(matches!(
+            error(&[b"JIR\x02", &bytes[4..]].concat()),
+            ParseError::UnsupportedBinaryVersion(2)
+        ));
+        assert!(matches!(
+            error(&bytes[..bytes.len() - 1]),
+            ParseError::InvalidBinary(_)
+        ));
+        assert!(matches!(
+            error(&[&bytes[..], &[0xc0]].concat()),
+            ParseError::InvalidBinary(_)
+        ));
+        // A map with a number key, then a huge array length with nothing after it.
+        let located = read(b"JIR\x01\x81\x01\xc0").expect_err("number key");
+        assert_eq!(located.span.map(|s| (s.start, s.end)), Some((5, 6)));
+        assert!(read(b"JIR\x01\xdd\xff\xff\xff\xff").is_err());
+        assert!(read(&[b"JIR\x01".to_vec(), vec![0x91; 200], vec![0xc0]].concat()).is_err());
+    }
+
+    #[test]
+    fn it_decodes_no_tree_from_a_wrong_version_or_a_truncated_program(
+    ) -> Result<(), Located<ParseError>> {
+        let bytes = encode(&JirParser::parse(
+            r#"["$do", ["$let", "xs", ["$quote", [1, 2.5, "three"]]], ["$ref", "xs"]]"#,
+        )?);
+        assert!(matches!(
+            decode(&[b"JIR\x00", &bytes[4..]].concat()).map_err(|e| e.error),
+            Err(ParseError::UnsupportedBinaryVersion(0))
+        ));
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "decoded {} bytes", len);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Months, NaiveDate, SecondsFormat, Timelike, Utc,
+};
+
+use crate::ast::{DateComponent, DateUnit};
+use crate::interpreter::EvalError;
+
+/// Source of the current time for `$now`.
+///
+/// The interpreter uses `SystemClock` by default; tests can inject a `FixedClock`
+/// through `Interpreter::with_clock`.
+pub trait Clock {
+    fn now(&self) -> DateTime<FixedOffset>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        Utc::now().into()
+    }
+}
+
+pub struct FixedClock(pub DateTime<FixedOffset>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        self.0
+    }
+}
+
+/// Parses an ISO-8601 date-time such as `2021-06-01T09:30:00+09:00`.
+/// A bare date (`2021-06-01`) is read as midnight UTC.
+pub fn parse(s: &str) -> Result<DateTime<FixedOffset>, EvalError> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt);
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|naive| naive.and_utc().fixed_offset())
+        .ok_or_else(|| EvalError::InvalidDateTime(s.into()))
+}
+
+pub fn format(dt: &DateTime<FixedOffset>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Formats with a strftime-style pattern, rejecting patterns chrono can't render.
+pub fn format_with(dt: &DateTime<FixedOffset>, pattern: &str) -> Result<String, EvalError> {
+    let items = StrftimeItems::new(pattern).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(EvalError::InvalidDateFormat(pattern.into()));
+    }
+    Ok(dt.format_with_items(items.into_iter()).to_string())
+}
+
+pub fn add(
+    dt: &DateTime<FixedOffset>,
+    amount: f64,
+    unit: DateUnit,
+) -> Result<DateTime<FixedOffset>, EvalError> {
+    let result = match unit {
+        DateUnit::Years | DateUnit::Months => {
+            if amount.fract() != 0.0 {
+                return Err(EvalError::InvalidDateArithmetic);
+            }
+            let months = if unit == DateUnit::Years {
+                amount * 12.0
+            } else {
+                amount
+            };
+            if months.abs() > u32::MAX as f64 {
+                return Err(EvalError::InvalidDateArithmetic);
+            }
+            let magnitude = Months::new(months.abs() as u32);
+            if months < 0.0 {
+                dt.checked_sub_months(magnitude)
+            } else {
+                dt.checked_add_months(magnitude)
+            }
+        }
+        _ => {
+            // Beyond the range of `i64`, where the cast would saturate.
+            let millis = amount * unit.millis() as f64;
+            if !millis.is_finite() || millis.abs() >= i64::MAX as f64 {
+                return Err(EvalError::InvalidDateArithmetic);
+            }
+            Duration::try_milliseconds(millis as i64)
+                .and_then(|duration| dt.checked_add_signed(duration))
+        }
+    };
+    result.ok_or(EvalError::InvalidDateArithmetic)
+}
+
+/// Returns `lhs - rhs` expressed in `unit`. Calendar units have no fixed length,
+/// so they are rejected.
+pub fn diff(
+    lhs: &DateTime<FixedOffset>,
+    rhs: &DateTime<FixedOffset>,
+    unit: DateUnit,
+) -> Result<f64, EvalError> {
+    match unit {
+        DateUnit::Years | DateUnit::Months => Err(EvalError::InvalidDateArithmetic),
+        _ => Ok((*lhs - *rhs).num_milliseconds() as f64 / unit.millis() as f64),
+    }
+}
+
+pub fn component(dt: &DateTime<FixedOffset>, component: DateComponent) -> f64 {
+    match component {
+        DateComponent::Year => dt.year() as f64,
+        DateComponent::Month => dt.month() as f64,
+        DateComponent::Day => dt.day() as f64,
+        DateComponent::Hour => dt.hour() as f64,
+        DateComponent::Minute => dt.minute() as f64,
+        DateComponent::Second => dt.second() as f64,
+        DateComponent::Millisecond => dt.timestamp_subsec_millis() as f64,
+        DateComponent::Weekday => dt.weekday().number_from_monday() as f64,
+        DateComponent::DayOfYear => dt.ordinal() as f64,
+    }
+}
+
+impl DateUnit {
+    fn millis(self) -> i64 {
+        match self {
+            DateUnit::Weeks => 7 * 24 * 60 * 60 * 1000,
+            DateUnit::Days => 24 * 60 * 60 * 1000,
+            DateUnit::Hours => 60 * 60 * 1000,
+            DateUnit::Minutes => 60 * 1000,
+            DateUnit::Seconds => 1000,
+            DateUnit::Milliseconds => 1,
+            DateUnit::Years | DateUnit::Months => {
+                unreachable!("calendar units have no fixed length")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_and_formats_iso_8601() -> Result<(), EvalError> {
+        let dt = parse("2021-06-01T09:30:00+09:00")?;
+        assert_eq!(format(&dt), "2021-06-01T09:30:00+09:00");
+        assert_eq!(format(&parse("2021-06-01")?), "2021-06-01T00:00:00Z");
+        assert!(parse("June 1st").is_err());
+        assert_eq!(format_with(&dt, "%Y/%m/%d")?, "2021/06/01");
+        assert!(format_with(&dt, "%Q").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_calendar_and_fixed_units() -> Result<(), EvalError> {
+        let dt = parse("2021-01-31T00:00:00Z")?;
+        assert_eq!(
+            format(&add(&dt, 1.0, DateUnit::Months)?),
+            "2021-02-28T00:00:00Z"
+        );
+        assert_eq!(
+            format(&add(&dt, -1.0, DateUnit::Years)?),
+            "2020-01-31T00:00:00Z"
+        );
+        assert_eq!(
+            format(&add(&dt, 36.0, DateUnit::Hours)?),
+            "2021-02-01T12:00:00Z"
+        );
+        assert!(add(&dt, 0.5, DateUnit::Months).is_err());
+        assert!(add(&dt, -1e300, DateUnit::Days).is_err());
+        assert!(add(&dt, f64::INFINITY, DateUnit::Seconds).is_err());
+        assert!(add(&dt, f64::NAN, DateUnit::Milliseconds).is_err());
+        assert!(add(&dt, 1e12, DateUnit::Years).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_extracts_components() -> Result<(), EvalError> {
+        let dt = parse("2021-06-01T09:30:15.250+09:00")?;
+        assert_eq!(component(&dt, DateComponent::Year), 2021.0);
+        assert_eq!(component(&dt, DateComponent::Hour), 9.0);
+        assert_eq!(component(&dt, DateComponent::Millisecond), 250.0);
+        assert_eq!(component(&dt, DateComponent::Weekday), 2.0);
+        assert_eq!(component(&dt, DateComponent::DayOfYear), 152.0);
+        Ok(())
+    }
+}
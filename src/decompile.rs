@@ -0,0 +1,533 @@
+//! Renders syntax trees as indented infix pseudo-code, for reading JIR without the
+//! array notation.
+//!
+//! ```text
+//! ["$if", ["$eq", ["$ref", "a"], 1], ["$bind", "a", ["$add", ["$ref", "a"], 1]]]
+//! ```
+//!
+//! becomes
+//!
+//! ```text
+//! if (a == 1) {
+//!     a = a + 1
+//! }
+//! ```
+//!
+//! Operators, `let`, assignment, `if`, `while`, functions, calls and field access
+//! are written in the `crate::monkey` syntax, which reads them back. Every other form
+//! is written as a call of its name without the `$`, such as `map(xs, f)`.
+//! Parentheses are only added where precedence requires them.
+
+use crate::ast::{AstKind, AstNode, DateComponent, DateUnit, Field, Param, Pattern};
+use crate::typecheck::Type;
+use crate::value::Value;
+
+const INDENT: &str = "    ";
+
+/// Binding strength, from loosest to tightest. An operand is parenthesized when it
+/// binds more loosely than its position requires.
+const STATEMENT: u8 = 0;
+const ASSIGN: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALS: u8 = 4;
+const COMPARE: u8 = 5;
+const SUM: u8 = 6;
+const PREFIX: u8 = 7;
+const POSTFIX: u8 = 8;
+
+/// The pseudo-code for a tree. A top-level `$do` is written as its statements.
+pub fn decompile(node: &AstNode) -> String {
+    match &node.kind {
+        AstKind::Do(statements) if !statements.is_empty() => self::statements(statements, 0),
+        _ => expr(node, 0),
+    }
+}
+
+fn precedence(kind: &AstKind) -> u8 {
+    match kind {
+        AstKind::Let(..) | AstKind::Yield(_) => STATEMENT,
+        AstKind::Bind(..)
+        | AstKind::If(..)
+        | AstKind::While(..)
+        | AstKind::Fn(..)
+        | AstKind::Gen(..)
+        | AstKind::Match(..)
+        | AstKind::StructDecl(..)
+        | AstKind::EnumDecl(..) => ASSIGN,
+        AstKind::Or(..) => OR,
+        AstKind::And(..) => AND,
+        AstKind::Eq(..) | AstKind::NotEq(..) => EQUALS,
+        AstKind::Lt(..) | AstKind::Lte(..) | AstKind::Gt(..) | AstKind::Gte(..) => COMPARE,
+        AstKind::Add(..) | AstKind::Sub(..) => SUM,
+        AstKind::Not(_) => PREFIX,
+        AstKind::Literal(Value::Number(n)) if *n < 0.0 => PREFIX,
+        _ => POSTFIX,
+    }
+}
+
+/// `node` in a position that needs at least `min` precedence.
+fn operand(node: &AstNode, min: u8, indent: usize) -> String {
+    if precedence(&node.kind) < min {
+        format!("({})", expr(node, indent))
+    } else {
+        expr(node, indent)
+    }
+}
+
+/// A left-associative binary operator: the right operand must bind more tightly.
+fn binary(op: &str, prec: u8, lhs: &AstNode, rhs: &AstNode, indent: usize) -> String {
+    format!(
+        "{} {} {}",
+        operand(lhs, prec, indent),
+        op,
+        operand(rhs, prec + 1, indent)
+    )
+}
+
+/// A call-like form, `name(arg, ...)`.
+fn call(name: &str, args: &[&AstNode], indent: usize) -> String {
+    format!("{}({})", name, list(args.iter().copied(), indent))
+}
+
+fn list<'a>(nodes: impl Iterator<Item = &'a AstNode>, indent: usize) -> String {
+    nodes
+        .map(|node| operand(node, ASSIGN, indent))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Leading operands followed by those of the optional trailing ones that are present.
+fn optional(
+    name: &str,
+    args: &[&AstNode],
+    trailing: &[&Option<Box<AstNode>>],
+    indent: usize,
+) -> String {
+    let mut args = args.to_vec();
+    args.extend(
+        trailing
+            .iter()
+            .map_while(|arg| arg.as_ref().map(AsRef::as_ref)),
+    );
+    call(name, &args, indent)
+}
+
+fn statements(statements: &[AstNode], indent: usize) -> String {
+    statements
+        .iter()
+        .map(|statement| format!("{}{}", INDENT.repeat(indent), expr(statement, indent)))
+        .collect::<Vec<_>>()
+        .join(";\n")
+}
+
+/// `{ ... }` with each statement of a `$do` body on its own line.
+fn block(body: &AstNode, indent: usize) -> String {
+    let body = match &body.kind {
+        AstKind::Do(stmts) if stmts.is_empty() => return "{}".into(),
+        AstKind::Do(stmts) => statements(stmts, indent + 1),
+        _ => statements(std::slice::from_ref(body), indent + 1),
+    };
+    format!("{{\n{}\n{}}}", body, INDENT.repeat(indent))
+}
+
+fn expr(node: &AstNode, indent: usize) -> String {
+    match &node.kind {
+        AstKind::Literal(value) => literal(value),
+        AstKind::Ident(ident) => ident.0.clone(),
+
+        AstKind::Add(lhs, rhs) => binary("+", SUM, lhs, rhs, indent),
+        AstKind::Sub(lhs, rhs) => binary("-", SUM, lhs, rhs, indent),
+        AstKind::And(lhs, rhs) => binary("&&", AND, lhs, rhs, indent),
+        AstKind::Or(lhs, rhs) => binary("||", OR, lhs, rhs, indent),
+        AstKind::Not(operand) => format!("!{}", self::operand(operand, PREFIX, indent)),
+
+        AstKind::Eq(lhs, rhs) => binary("==", EQUALS, lhs, rhs, indent),
+        AstKind::EqAll(operands) => format!("eq({})", list(operands.iter(), indent)),
+        AstKind::NotEq(lhs, rhs) => binary("!=", EQUALS, lhs, rhs, indent),
+        AstKind::Lt(lhs, rhs) => binary("<", COMPARE, lhs, rhs, indent),
+        AstKind::Lte(lhs, rhs) => binary("<=", COMPARE, lhs, rhs, indent),
+        AstKind::Gt(lhs, rhs) => binary(">", COMPARE, lhs, rhs, indent),
+        AstKind::Gte(lhs, rhs) => binary(">=", COMPARE, lhs, rhs, indent),
+
+        AstKind::If(cond, true_branch, false_branch) => {
+            let mut out = format!("if ({}) {}", expr(cond, indent), block(true_branch, indent));
+            match false_branch.as_deref() {
+                Some(
+                    else_if @ AstNode {
+                        kind: AstKind::If(..),
+                        ..
+                    },
+                ) => out += &format!(" else {}", expr(else_if, indent)),
+                Some(false_branch) => out += &format!(" else {}", block(false_branch, indent)),
+                None => {}
+            }
+            out
+        }
+        AstKind::While(cond, body) => {
+            format!("while ({}) {}", expr(cond, indent), block(body, indent))
+        }
+        AstKind::Assert(cond, message, _) => optional("assert", &[cond], &[message], indent),
+
+        AstKind::Bind(ident, value) => format!("{} = {}", ident.0, operand(value, ASSIGN, indent)),
+        AstKind::Let(ident, ty, value) => {
+            let ty = ty.as_ref().map_or(String::new(), |ty| format!(": {}", ty));
+            format!("let {}{} = {}", ident.0, ty, operand(value, ASSIGN, indent))
+        }
+        AstKind::Do(stmts) if stmts.is_empty() => "do {}".into(),
+        AstKind::Do(_) => format!("do {}", block(node, indent)),
+
+        AstKind::Array(items) => format!("[{}]", list(items.iter(), indent)),
+        AstKind::Object(entries) => format!("{{{}}}", entries_list(entries, indent)),
+        AstKind::Fn(params, body) => {
+            format!(
+                "fn({}) {}",
+                self::params(params, indent),
+                block(body, indent)
+            )
+        }
+        AstKind::Call(callee, args, named) => {
+            let mut args = args
+                .iter()
+                .map(|arg| operand(arg, ASSIGN, indent))
+                .collect::<Vec<_>>();
+            args.extend(
+                named
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, operand(value, ASSIGN, indent))),
+            );
+            format!("{}({})", operand(callee, POSTFIX, indent), args.join(", "))
+        }
+
+        AstKind::Map(items, callback) => call("map", &[items, callback], indent),
+        AstKind::Filter(items, callback) => call("filter", &[items, callback], indent),
+        AstKind::Reduce(items, callback, init) => call("reduce", &[items, callback, init], indent),
+        AstKind::Some(items, callback) => call("some", &[items, callback], indent),
+        AstKind::Every(items, callback) => call("every", &[items, callback], indent),
+
+        AstKind::Sort(items, key) => optional("sort", &[items], &[key], indent),
+        AstKind::GroupBy(items, key) => call("groupBy", &[items, key], indent),
+        AstKind::Unique(items) => call("unique", &[items], indent),
+        AstKind::Union(a, b) => call("union", &[a, b], indent),
+        AstKind::Intersect(a, b) => call("intersect", &[a, b], indent),
+        AstKind::Difference(a, b) => call("difference", &[a, b], indent),
+
+        AstKind::Range(start, None, Some(step)) => format!(
+            "range({}, null, {})",
+            operand(start, ASSIGN, indent),
+            operand(step, ASSIGN, indent)
+        ),
+        AstKind::Range(start, end, step) => optional("range", &[start], &[end, step], indent),
+        AstKind::Take(items, count) => call("take", &[items, count], indent),
+        AstKind::TakeWhile(items, predicate) => call("takeWhile", &[items, predicate], indent),
+        AstKind::Collect(items) => call("collect", &[items], indent),
+        AstKind::Gen(params, body) => {
+            format!(
+                "gen({}) {}",
+                self::params(params, indent),
+                block(body, indent)
+            )
+        }
+        AstKind::Yield(value) => format!("yield {}", operand(value, ASSIGN, indent)),
+
+        AstKind::StructDecl(name, fields) => format!(
+            "struct {} {{ {} }}",
+            name,
+            fields
+                .iter()
+                .map(|f| field(f, indent))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        AstKind::New(name, values) => format!("{} {{{}}}", name, entries_list(values, indent)),
+        AstKind::GetField(target, name) => format!("{}.{}", operand(target, POSTFIX, indent), name),
+        AstKind::With(target, values) => format!(
+            "{} with {{{}}}",
+            operand(target, POSTFIX, indent),
+            entries_list(values, indent)
+        ),
+
+        AstKind::Coalesce(operands) => format!("coalesce({})", list(operands.iter(), indent)),
+        AstKind::SafeGet(target, keys) => format!(
+            "safeGet({})",
+            list(std::iter::once(&**target).chain(keys), indent)
+        ),
+        AstKind::Defined(ident) => format!("defined({})", ident.0),
+        AstKind::GetIn(target, path, strict) => {
+            optional("getIn", &[target, path], &[strict], indent)
+        }
+        AstKind::Query(target, query) => call("query", &[target, query], indent),
+        AstKind::SetIn(target, path, value) => call("setIn", &[target, path, value], indent),
+        AstKind::UpdateIn(target, path, update) => {
+            call("updateIn", &[target, path, update], indent)
+        }
+
+        AstKind::EnumDecl(name, variants) => {
+            let variants = variants
+                .iter()
+                .map(|variant| {
+                    if variant.fields.is_empty() {
+                        variant.name.clone()
+                    } else {
+                        format!("{}({})", variant.name, params(&variant.fields, indent))
+                    }
+                })
+                .collect::<Vec<_>>();
+            format!("enum {} {{ {} }}", name, variants.join(", "))
+        }
+        AstKind::NewVariant(name, tag, values) if values.is_empty() => format!("{}.{}", name, tag),
+        AstKind::NewVariant(name, tag, values) => {
+            format!("{}.{}({})", name, tag, list(values.iter(), indent))
+        }
+        AstKind::Tag(value) => call("tag", &[value], indent),
+        AstKind::Match(scrutinee, arms) => {
+            let pad = INDENT.repeat(indent + 1);
+            let arms = arms
+                .iter()
+                .map(|arm| {
+                    let pattern = match &arm.pattern {
+                        Pattern::Wildcard => "_".to_string(),
+                        Pattern::Variant(name, bindings) if bindings.is_empty() => name.clone(),
+                        Pattern::Variant(name, bindings) => {
+                            let bindings = bindings
+                                .iter()
+                                .map(|binding| binding.as_ref().map_or("_", |ident| &ident.0))
+                                .collect::<Vec<_>>();
+                            format!("{}({})", name, bindings.join(", "))
+                        }
+                    };
+                    format!(
+                        "{}{} => {},\n",
+                        pad,
+                        pattern,
+                        operand(&arm.body, ASSIGN, indent + 1)
+                    )
+                })
+                .collect::<String>();
+            format!(
+                "match ({}) {{\n{}{}}}",
+                expr(scrutinee, indent),
+                arms,
+                INDENT.repeat(indent)
+            )
+        }
+
+        AstKind::Now => "now()".into(),
+        AstKind::DateTime(value) => call("dateTime", &[value], indent),
+        AstKind::FormatDateTime(value, pattern) => {
+            optional("formatDateTime", &[value], &[pattern], indent)
+        }
+        AstKind::DateAdd(value, amount, unit) => format!(
+            "dateAdd({}, {})",
+            list([&**value, &**amount].iter().copied(), indent),
+            date_unit(*unit)
+        ),
+        AstKind::DateDiff(a, b, unit) => format!(
+            "dateDiff({}, {})",
+            list([&**a, &**b].iter().copied(), indent),
+            date_unit(*unit)
+        ),
+        AstKind::DatePart(value, component) => format!(
+            "datePart({}, {})",
+            operand(value, ASSIGN, indent),
+            date_component(*component)
+        ),
+
+        AstKind::Custom(form, args) => format!(
+            "{}({})",
+            form.name.trim_start_matches('$'),
+            list(args.iter(), indent)
+        ),
+        AstKind::Error => "<error>".into(),
+    }
+}
+
+fn entries_list(entries: &[(String, AstNode)], indent: usize) -> String {
+    entries
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, operand(value, ASSIGN, indent)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `name`, `name: type`, `name: type = default` or `...name`.
+fn params(params: &[Param], indent: usize) -> String {
+    params
+        .iter()
+        .map(|param| {
+            let rest = if param.rest { "..." } else { "" };
+            let name = format!("{}{}", rest, param.ident.0);
+            annotated(name, &param.ty, &param.default, indent)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn field(field: &Field, indent: usize) -> String {
+    annotated(field.name.clone(), &field.ty, &field.default, indent)
+}
+
+fn annotated(
+    mut name: String,
+    ty: &Option<Type>,
+    default: &Option<AstNode>,
+    indent: usize,
+) -> String {
+    if let Some(ty) = ty {
+        name += &format!(": {}", ty);
+    }
+    if let Some(default) = default {
+        name += &format!(" = {}", operand(default, ASSIGN, indent));
+    }
+    name
+}
+
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Null => "null".into(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => serde_json::Value::String(s.clone()).to_string(),
+        Value::Array(items) => format!(
+            "[{}]",
+            items.iter().map(literal).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Object(entries) => format!(
+            "{{{}}}",
+            entries
+                .iter()
+                .map(|(key, value)| format!(
+                    "{}: {}",
+                    literal(&Value::String(key.clone())),
+                    literal(value)
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::DateTime(datetime) => format!("dateTime({:?})", datetime.to_rfc3339()),
+        Value::Function(_) => "<function>".into(),
+        Value::Sequence(_) => "<sequence>".into(),
+        Value::Struct(_) => "<struct>".into(),
+        Value::Variant(_) => "<variant>".into(),
+    }
+}
+
+fn date_unit(unit: DateUnit) -> &'static str {
+    match unit {
+        DateUnit::Years => "\"years\"",
+        DateUnit::Months => "\"months\"",
+        DateUnit::Weeks => "\"weeks\"",
+        DateUnit::Days => "\"days\"",
+        DateUnit::Hours => "\"hours\"",
+        DateUnit::Minutes => "\"minutes\"",
+        DateUnit::Seconds => "\"seconds\"",
+        DateUnit::Milliseconds => "\"milliseconds\"",
+    }
+}
+
+fn date_component(component: DateComponent) -> &'static str {
+    match component {
+        DateComponent::Year => "\"year\"",
+        DateComponent::Month => "\"month\"",
+        DateComponent::Day => "\"day\"",
+        DateComponent::Hour => "\"hour\"",
+        DateComponent::Minute => "\"minute\"",
+        DateComponent::Second => "\"second\"",
+        DateComponent::Millisecond => "\"millisecond\"",
+        DateComponent::Weekday => "\"weekday\"",
+        DateComponent::DayOfYear => "\"dayOfYear\"",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jir::{JirParser, ParseError};
+    use crate::monkey::MonkeyParser;
+
+    fn decompile_json(source: &str) -> Result<String, ParseError> {
+        JirParser::parse_json(source).map(|node| decompile(&node))
+    }
+
+    #[test]
+    fn it_writes_blocks_indented() -> Result<(), ParseError> {
+        let actual = decompile_json(
+            r#"["$do",
+                ["$let", "total", "number", 0],
+                ["$bind", "inc", ["$fn", ["n", ["by", "number", 1]], ["$add", ["$ref", "n"], ["$ref", "by"]]]],
+                ["$while", ["$lt", ["$ref", "total"], 10],
+                    ["$if", ["$eq", ["$ref", "total"], 5],
+                        ["$do", ["$bind", "total", ["$call", ["$ref", "inc"], ["$ref", "total"], {"by": 2}]]],
+                        ["$if", ["$gt", ["$ref", "total"], 7],
+                            ["$bind", "total", 10],
+                            ["$bind", "total", ["$call", ["$ref", "inc"], ["$ref", "total"]]]]]],
+                ["$match", ["$ref", "shape"],
+                    [["Circle", "r"], ["$field", ["$ref", "r"], "size"]],
+                    ["_", ["$map", ["$quote", [1, "a"]], ["$fn", ["x"], ["$ref", "x"]]]]]]"#,
+        )?;
+        let expected = r#"let total: number = 0;
+inc = fn(n, by: number = 1) {
+    n + by
+};
+while (total < 10) {
+    if (total == 5) {
+        total = inc(total, by: 2)
+    } else if (total > 7) {
+        total = 10
+    } else {
+        total = inc(total)
+    }
+};
+match (shape) {
+    Circle(r) => r.size,
+    _ => map([1, "a"], fn(x) {
+        x
+    }),
+}"#;
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_only_the_parentheses_precedence_needs() -> Result<(), ParseError> {
+        let cases = [
+            (r#"["$sub", ["$add", 1, 2], 3]"#, "1 + 2 - 3"),
+            (r#"["$sub", 1, ["$sub", 2, 3]]"#, "1 - (2 - 3)"),
+            (
+                r#"["$and", ["$or", true, false], ["$not", ["$eq", 1, -2]]]"#,
+                "(true || false) && !(1 == -2)",
+            ),
+            (
+                r#"["$or", ["$and", true, false], ["$lt", ["$add", 1, 2], 4]]"#,
+                "true && false || 1 + 2 < 4",
+            ),
+            (
+                r#"["$field", ["$call", ["$fn", [], 1]], "x"]"#,
+                "(fn() {\n    1\n})().x",
+            ),
+            (
+                r#"["$add", ["$if", true, 1, 2], ["$call", ["$ref", "f"], ["$bind", "x", 1]]]"#,
+                "(if (true) {\n    1\n} else {\n    2\n}) + f(x = 1)",
+            ),
+        ];
+        for (jir, expected) in cases.iter() {
+            assert_eq!(decompile_json(jir)?, *expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_is_read_back_by_the_monkey_parser() -> Result<(), ParseError> {
+        let source = r#"["$do",
+            ["$let", "x", ["$sub", 0, ["$ref", "y"]]],
+            ["$while", ["$notEq", ["$ref", "x"], 0],
+                ["$do", ["$bind", "x", ["$sub", ["$ref", "x"], 1]], ["$call", ["$ref", "log"], ["$ref", "x"]]]],
+            ["$if", ["$not", ["$or", ["$ref", "a"], ["$gte", ["$ref", "b"], -1.5]]],
+                ["$array", "s", null, ["$field", ["$ref", "p"], "q"]]]]"#;
+        let node = JirParser::parse_json(source)?;
+        let text = decompile(&node);
+        let reparsed = MonkeyParser::parse(&text).unwrap_or_else(|e| panic!("{}", e.render(&text)));
+        assert_eq!(format!("{:?}", reparsed), format!("{:?}", node));
+        Ok(())
+    }
+}
@@ -0,0 +1,759 @@
+//! Turns syntax trees back into JIR, so that programs built or rewritten in Rust can
+//! be saved, and expressions can be shown in messages.
+//!
+//! The output is canonical: every node is written the one way this module picks, so
+//! parsing it gives the same tree however the original was written. Chains of
+//! `$add`, `$sub`, `$and` and `$or` are flattened, optional operands are left out
+//! when absent, and integral numbers are written without a fraction.
+//!
+//! Some trees have no JIR. Runtime-only literal values (functions, sequences, struct
+//! and variant instances) and `AstKind::Error` placeholders are written as `null`. A
+//! host form is written as `[name, operand...]`, which only reads back for forms that
+//! use the default operand parser.
+
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::ast::{
+    AstKind, AstNode, DateComponent, DateUnit, Field, MatchArm, Param, Pattern, Variant,
+};
+use crate::value::Value;
+
+/// The canonical JIR for a tree.
+pub fn to_jir(node: &AstNode) -> JsonValue {
+    match &node.kind {
+        AstKind::Literal(value) => literal(value),
+        AstKind::Ident(ident) => json!(["$ref", ident.0]),
+
+        AstKind::Add(..) => fold("$add", node, |kind| match kind {
+            AstKind::Add(lhs, rhs) => Some((lhs.as_ref(), rhs.as_ref())),
+            _ => None,
+        }),
+        AstKind::Sub(..) => fold("$sub", node, |kind| match kind {
+            AstKind::Sub(lhs, rhs) => Some((lhs.as_ref(), rhs.as_ref())),
+            _ => None,
+        }),
+        AstKind::And(..) => fold("$and", node, |kind| match kind {
+            AstKind::And(lhs, rhs) => Some((lhs.as_ref(), rhs.as_ref())),
+            _ => None,
+        }),
+        AstKind::Or(..) => fold("$or", node, |kind| match kind {
+            AstKind::Or(lhs, rhs) => Some((lhs.as_ref(), rhs.as_ref())),
+            _ => None,
+        }),
+        AstKind::Not(operand) => form("$not", &[operand]),
+
+        AstKind::Eq(lhs, rhs) => form("$eq", &[lhs, rhs]),
+        AstKind::EqAll(operands) => form_all("$eq", vec![], operands),
+        AstKind::NotEq(lhs, rhs) => form("$notEq", &[lhs, rhs]),
+        AstKind::Lt(lhs, rhs) => form("$lt", &[lhs, rhs]),
+        AstKind::Lte(lhs, rhs) => form("$lte", &[lhs, rhs]),
+        AstKind::Gt(lhs, rhs) => form("$gt", &[lhs, rhs]),
+        AstKind::Gte(lhs, rhs) => form("$gte", &[lhs, rhs]),
+
+        AstKind::If(cond, true_branch, false_branch) => {
+            optional("$if", &[cond, true_branch], &[false_branch])
+        }
+        AstKind::While(cond, body) => form("$while", &[cond, body]),
+        AstKind::Assert(cond, message, _) => optional("$assert", &[cond], &[message]),
+
+        AstKind::Bind(ident, value) => json!(["$bind", ident.0, to_jir(value)]),
+        AstKind::Let(ident, None, value) => json!(["$let", ident.0, to_jir(value)]),
+        AstKind::Let(ident, Some(ty), value) => {
+            json!(["$let", ident.0, ty.to_string(), to_jir(value)])
+        }
+        AstKind::Do(statements) => form_all("$do", vec![], statements),
+
+        AstKind::Array(items) => form_all("$array", vec![], items),
+        AstKind::Object(entries) => {
+            // Only `$quasiquote` builds objects. Unquoting every value keeps literal
+            // values from being read back as part of a literal object.
+            let entries = entries
+                .iter()
+                .map(|(key, value)| (key.clone(), json!(["$unquote", to_jir(value)])))
+                .collect::<Map<String, JsonValue>>();
+            json!(["$quasiquote", entries])
+        }
+        AstKind::Fn(params, body) => json!(["$fn", self::params(params), to_jir(body)]),
+        AstKind::Call(callee, args, named) => {
+            let mut call = form_all("$call", vec![to_jir(callee)], args);
+            if !named.is_empty() {
+                push(&mut call, field_values(named));
+            }
+            call
+        }
+
+        AstKind::Map(items, callback) => form("$map", &[items, callback]),
+        AstKind::Filter(items, callback) => form("$filter", &[items, callback]),
+        AstKind::Reduce(items, callback, init) => form("$reduce", &[items, callback, init]),
+        AstKind::Some(items, callback) => form("$some", &[items, callback]),
+        AstKind::Every(items, callback) => form("$every", &[items, callback]),
+
+        AstKind::Sort(items, key) => optional("$sort", &[items], &[key]),
+        AstKind::GroupBy(items, key) => form("$groupBy", &[items, key]),
+        AstKind::Unique(items) => form("$unique", &[items]),
+        AstKind::Union(a, b) => form("$union", &[a, b]),
+        AstKind::Intersect(a, b) => form("$intersect", &[a, b]),
+        AstKind::Difference(a, b) => form("$difference", &[a, b]),
+
+        AstKind::Range(start, end, step) => {
+            let mut range = form("$range", &[start]);
+            match (end, step) {
+                (Some(end), step) => {
+                    push(&mut range, to_jir(end));
+                    if let Some(step) = step {
+                        push(&mut range, to_jir(step));
+                    }
+                }
+                (None, Some(step)) => {
+                    push(&mut range, JsonValue::Null);
+                    push(&mut range, to_jir(step));
+                }
+                (None, None) => {}
+            }
+            range
+        }
+        AstKind::Take(items, count) => form("$take", &[items, count]),
+        AstKind::TakeWhile(items, predicate) => form("$takeWhile", &[items, predicate]),
+        AstKind::Collect(items) => form("$collect", &[items]),
+        AstKind::Gen(params, body) => json!(["$gen", self::params(params), to_jir(body)]),
+        AstKind::Yield(value) => form("$yield", &[value]),
+
+        AstKind::StructDecl(name, fields) => {
+            json!([
+                "$struct",
+                name,
+                fields.iter().map(field).collect::<Vec<_>>()
+            ])
+        }
+        AstKind::New(name, values) if values.is_empty() => json!(["$new", name]),
+        AstKind::New(name, values) => json!(["$new", name, field_values(values)]),
+        AstKind::GetField(target, name) => json!(["$field", to_jir(target), name]),
+        AstKind::With(target, values) => json!(["$with", to_jir(target), field_values(values)]),
+
+        AstKind::Coalesce(operands) => form_all("$coalesce", vec![], operands),
+        AstKind::SafeGet(target, keys) => form_all("$safeGet", vec![to_jir(target)], keys),
+        AstKind::Defined(ident) => json!(["$defined", ident.0]),
+        AstKind::GetIn(target, path, strict) => optional("$getIn", &[target, path], &[strict]),
+        AstKind::Query(target, query) => form("$query", &[target, query]),
+        AstKind::SetIn(target, path, value) => form("$setIn", &[target, path, value]),
+        AstKind::UpdateIn(target, path, update) => form("$updateIn", &[target, path, update]),
+
+        AstKind::EnumDecl(name, variants) => {
+            json!([
+                "$enum",
+                name,
+                variants.iter().map(variant).collect::<Vec<_>>()
+            ])
+        }
+        AstKind::NewVariant(name, tag, values) => {
+            form_all("$variant", vec![json!(name), json!(tag)], values)
+        }
+        AstKind::Tag(value) => form("$tag", &[value]),
+        AstKind::Match(scrutinee, arms) => {
+            let mut jir = form("$match", &[scrutinee]);
+            for arm in arms {
+                push(&mut jir, match_arm(arm));
+            }
+            jir
+        }
+
+        AstKind::Now => json!(["$now"]),
+        AstKind::DateTime(value) => form("$dateTime", &[value]),
+        AstKind::FormatDateTime(value, pattern) => {
+            optional("$formatDateTime", &[value], &[pattern])
+        }
+        AstKind::DateAdd(value, amount, unit) => {
+            json!(["$dateAdd", to_jir(value), to_jir(amount), date_unit(*unit)])
+        }
+        AstKind::DateDiff(a, b, unit) => {
+            json!(["$dateDiff", to_jir(a), to_jir(b), date_unit(*unit)])
+        }
+        AstKind::DatePart(value, component) => {
+            json!(["$datePart", to_jir(value), date_component(*component)])
+        }
+
+        AstKind::Custom(form, operands) => form_all(&form.name, vec![], operands),
+        AstKind::Error => JsonValue::Null,
+    }
+}
+
+/// `[name, operand...]`.
+fn form(name: &str, operands: &[&AstNode]) -> JsonValue {
+    let mut jir = vec![json!(name)];
+    jir.extend(operands.iter().map(|operand| to_jir(operand)));
+    JsonValue::Array(jir)
+}
+
+/// `[name, leading..., operand...]`.
+fn form_all(name: &str, leading: Vec<JsonValue>, operands: &[AstNode]) -> JsonValue {
+    let mut jir = vec![json!(name)];
+    jir.extend(leading);
+    jir.extend(operands.iter().map(to_jir));
+    JsonValue::Array(jir)
+}
+
+/// A form whose trailing operands are optional. Each one is only written when it and
+/// the ones before it are present, which is how the parser reads them back.
+fn optional(name: &str, operands: &[&AstNode], trailing: &[&Option<Box<AstNode>>]) -> JsonValue {
+    let mut jir = form(name, operands);
+    for operand in trailing.iter().map_while(|operand| operand.as_ref()) {
+        push(&mut jir, to_jir(operand));
+    }
+    jir
+}
+
+/// Writes a left-nested chain of the same operator as one form, the way the parser
+/// desugars it.
+fn fold(
+    name: &str,
+    node: &AstNode,
+    operands: fn(&AstKind) -> Option<(&AstNode, &AstNode)>,
+) -> JsonValue {
+    let mut rest = Vec::new();
+    let mut lhs = node;
+    while let Some((inner, rhs)) = operands(&lhs.kind) {
+        rest.push(to_jir(rhs));
+        lhs = inner;
+    }
+    rest.push(to_jir(lhs));
+    rest.push(json!(name));
+    rest.reverse();
+    JsonValue::Array(rest)
+}
+
+fn push(form: &mut JsonValue, operand: JsonValue) {
+    if let JsonValue::Array(items) = form {
+        items.push(operand);
+    }
+}
+
+fn literal(value: &Value) -> JsonValue {
+    match value {
+        Value::Array(_) | Value::Object(_) => json!(["$quote", data(value)]),
+        Value::DateTime(datetime) => json!(["$dateTime", datetime.to_rfc3339()]),
+        _ => data(value),
+    }
+}
+
+/// A value as quoted JSON data.
+fn data(value: &Value) -> JsonValue {
+    match value {
+        Value::Null => JsonValue::Null,
+        Value::Boolean(b) => json!(b),
+        Value::Number(n) => number(*n),
+        Value::String(s) => json!(s),
+        Value::Array(items) => items.iter().map(data).collect(),
+        Value::Object(entries) => JsonValue::Object(
+            entries
+                .iter()
+                .map(|(key, value)| (key.clone(), data(value)))
+                .collect(),
+        ),
+        Value::DateTime(datetime) => json!(datetime.to_rfc3339()),
+        Value::Function(_) | Value::Sequence(_) | Value::Struct(_) | Value::Variant(_) => {
+            JsonValue::Null
+        }
+    }
+}
+
+/// Integral numbers that are exactly representable are written without a fraction.
+/// JIR has no non-finite numbers, so those are written as `null`.
+fn number(n: f64) -> JsonValue {
+    const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+    if n.fract() == 0.0 && n.abs() <= MAX_EXACT && !(n == 0.0 && n.is_sign_negative()) {
+        json!(n as i64)
+    } else {
+        serde_json::Number::from_f64(n).map_or(JsonValue::Null, JsonValue::Number)
+    }
+}
+
+fn params(params: &[Param]) -> JsonValue {
+    params.iter().map(param).collect()
+}
+
+/// A parameter with a default but no type is written with `any`, since the parser
+/// only reads defaults after a type.
+fn param(param: &Param) -> JsonValue {
+    let name = if param.rest {
+        format!("...{}", param.ident.0)
+    } else {
+        param.ident.0.clone()
+    };
+    match (&param.ty, &param.default) {
+        (None, None) => json!(name),
+        (Some(ty), None) => json!([name, ty.to_string()]),
+        (ty, Some(default)) => {
+            let ty = ty.as_ref().map_or("any".into(), ToString::to_string);
+            json!([name, ty, to_jir(default)])
+        }
+    }
+}
+
+fn field(field: &Field) -> JsonValue {
+    match (&field.ty, &field.default) {
+        (None, None) => json!(field.name),
+        (Some(ty), None) => json!([field.name, ty.to_string()]),
+        (ty, Some(default)) => {
+            let ty = ty.as_ref().map_or("any".into(), ToString::to_string);
+            json!([field.name, ty, to_jir(default)])
+        }
+    }
+}
+
+fn field_values(values: &[(String, AstNode)]) -> JsonValue {
+    JsonValue::Object(
+        values
+            .iter()
+            .map(|(name, value)| (name.clone(), to_jir(value)))
+            .collect(),
+    )
+}
+
+fn variant(variant: &Variant) -> JsonValue {
+    if variant.fields.is_empty() {
+        return json!(variant.name);
+    }
+    let mut spec = vec![json!(variant.name)];
+    spec.extend(variant.fields.iter().map(param));
+    JsonValue::Array(spec)
+}
+
+fn match_arm(arm: &MatchArm) -> JsonValue {
+    let pattern = match &arm.pattern {
+        Pattern::Wildcard => json!("_"),
+        Pattern::Variant(name, bindings) if bindings.is_empty() => json!(name),
+        Pattern::Variant(name, bindings) => {
+            let mut spec = vec![json!(name)];
+            spec.extend(bindings.iter().map(|binding| match binding {
+                Some(ident) => json!(ident.0),
+                None => json!("_"),
+            }));
+            JsonValue::Array(spec)
+        }
+    };
+    json!([pattern, to_jir(&arm.body)])
+}
+
+fn date_unit(unit: DateUnit) -> &'static str {
+    match unit {
+        DateUnit::Years => "years",
+        DateUnit::Months => "months",
+        DateUnit::Weeks => "weeks",
+        DateUnit::Days => "days",
+        DateUnit::Hours => "hours",
+        DateUnit::Minutes => "minutes",
+        DateUnit::Seconds => "seconds",
+        DateUnit::Milliseconds => "milliseconds",
+    }
+}
+
+fn date_component(component: DateComponent) -> &'static str {
+    match component {
+        DateComponent::Year => "year",
+        DateComponent::Month => "month",
+        DateComponent::Day => "day",
+        DateComponent::Hour => "hour",
+        DateComponent::Minute => "minute",
+        DateComponent::Second => "second",
+        DateComponent::Millisecond => "millisecond",
+        DateComponent::Weekday => "weekday",
+        DateComponent::DayOfYear => "dayOfYear",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use proptest::prelude::*;
+    use proptest::strategy::Union;
+
+    use super::*;
+    use crate::ast::Ident;
+    use crate::forms::{Form, FormRegistry};
+    use crate::jir::{JirParser, ParseError};
+    use crate::typecheck::Type;
+
+    #[test]
+    fn it_writes_canonical_jir() -> Result<(), ParseError> {
+        let node = JirParser::parse_json(
+            r#"["$do",
+                ["$let", "xs", "array<number>", ["$quote", [1.0, 2.5, {"b": null, "a": true}]]],
+                ["$add", ["$ref", "x"], 2, 3],
+                ["$range", 0, null, -1],
+                ["$call", ["$ref", "f"], 1, {"to": "$ref"}],
+                ["$quasiquote", {"id": ["$unquote", ["$ref", "id"]], "tags": ["a"]}]]"#,
+        )?;
+        assert_eq!(
+            to_jir(&node),
+            json!(["$do",
+            ["$let", "xs", "array<number>", ["$quote", [1, 2.5, {"a": true, "b": null}]]],
+            ["$add", ["$ref", "x"], 2, 3],
+            ["$range", 0, null, -1],
+            ["$call", ["$ref", "f"], 1, {"to": "$ref"}],
+            ["$quasiquote", {
+                "id": ["$unquote", ["$ref", "id"]],
+                "tags": ["$unquote", ["$quote", ["a"]]]
+            }]])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_host_forms_by_name() -> Result<(), ParseError> {
+        let mut forms = FormRegistry::builtin();
+        forms.register(Form::custom("$twice", 1, Some(1), |interpreter, args| {
+            interpreter.eval(&args[0])
+        }));
+        let forms = Rc::new(forms);
+        let source = r#"["$twice",["$add",1,["$ref","x"]]]"#;
+
+        let node = JirParser::new(forms.clone())
+            .read(source)
+            .map_err(|e| e.error)?;
+        assert_eq!(to_jir(&node).to_string(), source);
+        Ok(())
+    }
+
+    fn name() -> impl Strategy<Value = String> {
+        "[a-z][a-z0-9]{0,3}"
+    }
+
+    fn ident() -> impl Strategy<Value = Ident> {
+        name().prop_map(Ident)
+    }
+
+    fn ty() -> impl Strategy<Value = Type> {
+        prop_oneof![
+            Just("number"),
+            Just("string"),
+            Just("any"),
+            Just("array<number>"),
+            Just("(number, string) -> boolean"),
+            Just("Point"),
+        ]
+        .prop_map(|ty| Type::parse(ty).expect("a valid type"))
+    }
+
+    fn data(depth: u32) -> BoxedStrategy<Value> {
+        let scalar = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Boolean),
+            prop_oneof![
+                (-1000i32..1000).prop_map(f64::from),
+                any::<f64>().prop_filter("finite", |n| n.is_finite()),
+            ]
+            .prop_map(Value::Number),
+            any::<String>().prop_map(Value::String),
+        ];
+        if depth == 0 {
+            return scalar.boxed();
+        }
+        prop_oneof![
+            scalar,
+            prop::collection::vec(data(depth - 1), 0..3).prop_map(Value::Array),
+            prop::collection::btree_map(name(), data(depth - 1), 0..3).prop_map(Value::Object),
+        ]
+        .boxed()
+    }
+
+    fn node(kind: AstKind) -> AstNode {
+        kind.into()
+    }
+
+    fn boxed(node: AstNode) -> Box<AstNode> {
+        Box::new(node)
+    }
+
+    /// A tree that the parser could have produced, covering every node kind apart
+    /// from host forms and placeholders.
+    fn tree() -> impl Strategy<Value = AstNode> {
+        let leaf = prop_oneof![
+            data(2).prop_map(|value| node(AstKind::Literal(value))),
+            ident().prop_map(|ident| node(AstKind::Ident(ident))),
+            ident().prop_map(|ident| node(AstKind::Defined(ident))),
+            Just(node(AstKind::Now)),
+        ];
+        leaf.prop_recursive(3, 48, 4, |inner| {
+            let unary = |make: fn(Box<AstNode>) -> AstKind| {
+                inner
+                    .clone()
+                    .prop_map(move |a| node(make(boxed(a))))
+                    .boxed()
+            };
+            let binary = |make: fn(Box<AstNode>, Box<AstNode>) -> AstKind| {
+                (inner.clone(), inner.clone())
+                    .prop_map(move |(a, b)| node(make(boxed(a), boxed(b))))
+                    .boxed()
+            };
+            let ternary = |make: fn(Box<AstNode>, Box<AstNode>, Box<AstNode>) -> AstKind| {
+                (inner.clone(), inner.clone(), inner.clone())
+                    .prop_map(move |(a, b, c)| node(make(boxed(a), boxed(b), boxed(c))))
+                    .boxed()
+            };
+            let nodes = |min: usize| prop::collection::vec(inner.clone(), min..min + 3);
+            let optional = || prop::option::of(inner.clone().prop_map(boxed));
+            let values = || {
+                prop::collection::btree_map(name(), inner.clone(), 0..3)
+                    .prop_map(|values| values.into_iter().collect::<Vec<_>>())
+            };
+            let params = {
+                let param = (
+                    ident(),
+                    prop::option::of((ty(), prop::option::of(inner.clone()))),
+                );
+                (prop::collection::vec(param, 0..3), ident(), any::<bool>())
+                    .prop_map(|(params, rest, has_rest)| {
+                        let mut params = params
+                            .into_iter()
+                            .map(|(ident, spec)| {
+                                let (ty, default) = match spec {
+                                    Some((ty, default)) => (Some(ty), default),
+                                    None => (None, None),
+                                };
+                                Param {
+                                    ident,
+                                    ty,
+                                    default,
+                                    rest: false,
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        if has_rest {
+                            params.push(Param {
+                                ident: rest,
+                                ty: None,
+                                default: None,
+                                rest: true,
+                            });
+                        }
+                        params
+                    })
+                    .boxed()
+            };
+            let date_unit = prop_oneof![
+                Just(DateUnit::Years),
+                Just(DateUnit::Weeks),
+                Just(DateUnit::Milliseconds),
+            ];
+            let date_component = prop_oneof![
+                Just(DateComponent::Month),
+                Just(DateComponent::Weekday),
+                Just(DateComponent::DayOfYear),
+            ];
+
+            let kinds: Vec<BoxedStrategy<AstNode>> = vec![
+                binary(AstKind::Add),
+                binary(AstKind::Sub),
+                binary(AstKind::And),
+                binary(AstKind::Or),
+                unary(AstKind::Not),
+                binary(AstKind::Eq),
+                nodes(3).prop_map(|ns| node(AstKind::EqAll(ns))).boxed(),
+                binary(AstKind::NotEq),
+                binary(AstKind::Lt),
+                binary(AstKind::Lte),
+                binary(AstKind::Gt),
+                binary(AstKind::Gte),
+                (inner.clone(), inner.clone(), optional())
+                    .prop_map(|(c, t, f)| node(AstKind::If(boxed(c), boxed(t), f)))
+                    .boxed(),
+                binary(AstKind::While),
+                (inner.clone(), optional())
+                    .prop_map(|(cond, message)| {
+                        let source = to_jir(&cond).to_string();
+                        node(AstKind::Assert(boxed(cond), message, source))
+                    })
+                    .boxed(),
+                (ident(), inner.clone())
+                    .prop_map(|(i, v)| node(AstKind::Bind(i, boxed(v))))
+                    .boxed(),
+                (ident(), prop::option::of(ty()), inner.clone())
+                    .prop_map(|(i, t, v)| node(AstKind::Let(i, t, boxed(v))))
+                    .boxed(),
+                nodes(0).prop_map(|ns| node(AstKind::Do(ns))).boxed(),
+                nodes(0).prop_map(|ns| node(AstKind::Array(ns))).boxed(),
+                prop::collection::btree_map(name(), inner.clone(), 1..3)
+                    .prop_map(|entries| node(AstKind::Object(entries.into_iter().collect())))
+                    .boxed(),
+                (params.clone(), inner.clone())
+                    .prop_map(|(ps, body)| node(AstKind::Fn(ps, Rc::new(body))))
+                    .boxed(),
+                (inner.clone(), nodes(0), values())
+                    .prop_map(|(f, args, named)| node(AstKind::Call(boxed(f), args, named)))
+                    .boxed(),
+                binary(AstKind::Map),
+                binary(AstKind::Filter),
+                ternary(AstKind::Reduce),
+                binary(AstKind::Some),
+                binary(AstKind::Every),
+                (inner.clone(), optional())
+                    .prop_map(|(items, key)| node(AstKind::Sort(boxed(items), key)))
+                    .boxed(),
+                binary(AstKind::GroupBy),
+                unary(AstKind::Unique),
+                binary(AstKind::Union),
+                binary(AstKind::Intersect),
+                binary(AstKind::Difference),
+                // An end that is literally `null` reads back as no end.
+                (
+                    inner.clone(),
+                    prop::option::of(ident().prop_map(|i| boxed(node(AstKind::Ident(i))))),
+                    optional(),
+                )
+                    .prop_map(|(start, end, step)| node(AstKind::Range(boxed(start), end, step)))
+                    .boxed(),
+                binary(AstKind::Take),
+                binary(AstKind::TakeWhile),
+                unary(AstKind::Collect),
+                // `$yield` only parses in a generator body.
+                (params, inner.clone(), any::<bool>())
+                    .prop_map(|(ps, body, yields)| {
+                        let body = if yields {
+                            node(AstKind::Yield(boxed(body)))
+                        } else {
+                            body
+                        };
+                        node(AstKind::Gen(ps, Rc::new(body)))
+                    })
+                    .boxed(),
+                (
+                    name(),
+                    prop::collection::vec(
+                        (
+                            name(),
+                            prop::option::of((ty(), prop::option::of(inner.clone()))),
+                        ),
+                        0..3,
+                    ),
+                )
+                    .prop_map(|(name, mut fields)| {
+                        // `$struct` rejects a field declared twice.
+                        let mut seen = std::collections::HashSet::new();
+                        fields.retain(|(name, _)| seen.insert(name.clone()));
+                        let fields = fields
+                            .into_iter()
+                            .map(|(name, spec)| match spec {
+                                Some((ty, default)) => Field {
+                                    name,
+                                    ty: Some(ty),
+                                    default,
+                                },
+                                None => Field {
+                                    name,
+                                    ty: None,
+                                    default: None,
+                                },
+                            })
+                            .collect();
+                        node(AstKind::StructDecl(name, fields))
+                    })
+                    .boxed(),
+                (name(), values())
+                    .prop_map(|(name, values)| node(AstKind::New(name, values)))
+                    .boxed(),
+                (inner.clone(), name())
+                    .prop_map(|(target, name)| node(AstKind::GetField(boxed(target), name)))
+                    .boxed(),
+                (inner.clone(), values())
+                    .prop_map(|(target, values)| node(AstKind::With(boxed(target), values)))
+                    .boxed(),
+                nodes(1).prop_map(|ns| node(AstKind::Coalesce(ns))).boxed(),
+                (inner.clone(), nodes(1))
+                    .prop_map(|(target, keys)| node(AstKind::SafeGet(boxed(target), keys)))
+                    .boxed(),
+                (inner.clone(), inner.clone(), optional())
+                    .prop_map(|(t, p, s)| node(AstKind::GetIn(boxed(t), boxed(p), s)))
+                    .boxed(),
+                binary(AstKind::Query),
+                ternary(AstKind::SetIn),
+                ternary(AstKind::UpdateIn),
+                (
+                    name(),
+                    prop::collection::vec(
+                        (
+                            name(),
+                            prop::collection::vec((ident(), prop::option::of(ty())), 0..3),
+                        ),
+                        0..3,
+                    ),
+                )
+                    .prop_map(|(name, variants)| {
+                        let variants = variants
+                            .into_iter()
+                            .map(|(name, fields)| Variant {
+                                name,
+                                fields: fields
+                                    .into_iter()
+                                    .map(|(ident, ty)| Param {
+                                        ident,
+                                        ty,
+                                        default: None,
+                                        rest: false,
+                                    })
+                                    .collect(),
+                            })
+                            .collect();
+                        node(AstKind::EnumDecl(name, variants))
+                    })
+                    .boxed(),
+                (name(), name(), nodes(0))
+                    .prop_map(|(name, tag, values)| node(AstKind::NewVariant(name, tag, values)))
+                    .boxed(),
+                unary(AstKind::Tag),
+                (
+                    inner.clone(),
+                    prop::collection::vec(
+                        (
+                            prop::option::of((
+                                name(),
+                                prop::collection::vec(prop::option::of(ident()), 0..3),
+                            )),
+                            inner.clone(),
+                        ),
+                        1..3,
+                    ),
+                )
+                    .prop_map(|(scrutinee, arms)| {
+                        let arms = arms
+                            .into_iter()
+                            .map(|(pattern, body)| MatchArm {
+                                pattern: match pattern {
+                                    Some((name, bindings)) => Pattern::Variant(name, bindings),
+                                    None => Pattern::Wildcard,
+                                },
+                                body,
+                            })
+                            .collect();
+                        node(AstKind::Match(boxed(scrutinee), arms))
+                    })
+                    .boxed(),
+                unary(AstKind::DateTime),
+                (inner.clone(), optional())
+                    .prop_map(|(v, pattern)| node(AstKind::FormatDateTime(boxed(v), pattern)))
+                    .boxed(),
+                (inner.clone(), inner.clone(), date_unit.clone())
+                    .prop_map(|(a, b, unit)| node(AstKind::DateAdd(boxed(a), boxed(b), unit)))
+                    .boxed(),
+                (inner.clone(), inner.clone(), date_unit)
+                    .prop_map(|(a, b, unit)| node(AstKind::DateDiff(boxed(a), boxed(b), unit)))
+                    .boxed(),
+                (inner, date_component)
+                    .prop_map(|(v, component)| node(AstKind::DatePart(boxed(v), component)))
+                    .boxed(),
+            ];
+            Union::new(kinds)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn it_round_trips_every_node_kind(tree in tree()) {
+            let jir = to_jir(&tree);
+            let parsed = JirParser::parse_json(&jir.to_string())
+                .map_err(|e| TestCaseError::fail(format!("{:?} in {}", e, jir)))?;
+            prop_assert_eq!(format!("{:?}", parsed), format!("{:?}", tree));
+            prop_assert_eq!(to_jir(&parsed), jir);
+        }
+    }
+}
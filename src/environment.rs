@@ -1,16 +1,341 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
 
 use crate::ast::Ident;
-use crate::value::Value;
+use crate::sequence::Sequence;
+use crate::value::{Function, Value};
 
 pub struct Environment {
     pub bindings: HashMap<Ident, Value>,
+    parent: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             bindings: HashMap::new(),
+            parent: None,
         }
     }
+
+    pub fn with_parent(parent: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            bindings: HashMap::new(),
+            parent: Some(parent),
+        }
+    }
+
+    pub fn get(&self, ident: &Ident) -> Option<Value> {
+        match self.bindings.get(ident) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref()?.borrow().get(ident),
+        }
+    }
+
+    pub fn define(&mut self, ident: Ident, value: Value) {
+        self.bindings.insert(ident, value);
+    }
+
+    /// Updates the nearest enclosing binding of `ident`, or defines it in this scope
+    /// when no enclosing scope has it.
+    pub fn assign(&mut self, ident: Ident, value: Value) {
+        if !self.bindings.contains_key(&ident) {
+            if let Some(parent) = &self.parent {
+                if parent.borrow().is_bound(&ident) {
+                    parent.borrow_mut().assign(ident, value);
+                    return;
+                }
+            }
+        }
+        self.bindings.insert(ident, value);
+    }
+
+    pub fn is_bound(&self, ident: &Ident) -> bool {
+        self.bindings.contains_key(ident)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.borrow().is_bound(ident))
+    }
+}
+
+/// The environments an interpreter has created, so that the ones kept alive only by
+/// reference cycles can be freed.
+///
+/// A closure holds the environment it was made in, so binding it there, as every
+/// named function is, makes a cycle of `Rc`s that is never dropped. `collect`
+/// finds the environments that nothing outside them refers to, directly or through
+/// closures and sequences, and clears their bindings, which breaks the cycles.
+pub(crate) struct Heap {
+    envs: Vec<Weak<RefCell<Environment>>>,
+    /// The number of tracked environments at which the next collection is due.
+    threshold: usize,
+}
+
+/// A reference-counted value that can be part of a cycle.
+pub(crate) enum Object {
+    Env(Rc<RefCell<Environment>>),
+    Function(Rc<Function>),
+    Sequence(Sequence),
+}
+
+struct Node {
+    object: Object,
+    /// The references to the object from other objects in the heap.
+    internal: usize,
+    /// `None` when the object was in use and couldn't be looked into.
+    children: Option<Vec<*const ()>>,
+}
+
+impl Heap {
+    const MIN_THRESHOLD: usize = 1024;
+
+    pub fn new() -> Self {
+        Self {
+            envs: Vec::new(),
+            threshold: Self::MIN_THRESHOLD,
+        }
+    }
+
+    pub fn track(&mut self, env: &Rc<RefCell<Environment>>) {
+        self.envs.push(Rc::downgrade(env));
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.envs.len() >= self.threshold
+    }
+
+    /// Frees the environments that are only reachable from each other. An object
+    /// with more references than the heap accounts for is in use from outside,
+    /// like the interpreter's current scope or a value the host holds, and keeps
+    /// everything it refers to. `released` are references that are about to be
+    /// dropped and don't count as uses.
+    pub fn collect(&mut self, released: &[&Rc<RefCell<Environment>>]) {
+        let mut nodes = HashMap::new();
+        let mut pending = self
+            .envs
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Object::Env)
+            .collect::<Vec<_>>();
+        while let Some(object) = pending.pop() {
+            let id = object.id();
+            if nodes.contains_key(&id) {
+                continue;
+            }
+            let children = object.children().map(|children| {
+                children
+                    .into_iter()
+                    .map(|child| {
+                        let id = child.id();
+                        pending.push(child);
+                        id
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let node = Node {
+                object,
+                internal: 0,
+                children,
+            };
+            nodes.insert(id, node);
+        }
+        // Each object is now referenced once more, by its node.
+        let edges = nodes
+            .values()
+            .flat_map(|node| node.children.iter().flatten().copied())
+            .collect::<Vec<_>>();
+        for id in edges {
+            if let Some(node) = nodes.get_mut(&id) {
+                node.internal += 1;
+            }
+        }
+        for env in released {
+            if let Some(node) = nodes.get_mut(&(Rc::as_ptr(env) as *const ())) {
+                node.internal += 1;
+            }
+        }
+
+        let mut reachable = nodes
+            .iter()
+            .filter(|(_, node)| node.object.strong_count() > node.internal + 1)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let mut marked = HashSet::new();
+        while let Some(id) = reachable.pop() {
+            if marked.insert(id) {
+                reachable.extend(nodes[&id].children.iter().flatten().copied());
+            }
+        }
+
+        let mut garbage = Vec::new();
+        for (id, node) in &nodes {
+            if let (false, Object::Env(env)) = (marked.contains(id), &node.object) {
+                if let Ok(mut env) = env.try_borrow_mut() {
+                    garbage.push(std::mem::take(&mut env.bindings));
+                }
+            }
+        }
+        drop(nodes);
+        drop(garbage);
+
+        self.envs.retain(|env| env.strong_count() > 0);
+        self.threshold = (self.envs.len() * 2).max(Self::MIN_THRESHOLD);
+    }
+}
+
+impl Object {
+    fn id(&self) -> *const () {
+        match self {
+            Object::Env(env) => Rc::as_ptr(env) as *const (),
+            Object::Function(function) => Rc::as_ptr(function) as *const (),
+            Object::Sequence(sequence) => sequence.id(),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Env(env) => Rc::strong_count(env),
+            Object::Function(function) => Rc::strong_count(function),
+            Object::Sequence(sequence) => sequence.strong_count(),
+        }
+    }
+
+    /// The objects this one refers to, or `None` if it's borrowed.
+    fn children(&self) -> Option<Vec<Object>> {
+        let mut children = Vec::new();
+        match self {
+            Object::Env(env) => {
+                let env = env.try_borrow().ok()?;
+                for value in env.bindings.values() {
+                    value_children(value, &mut children);
+                }
+                if let Some(parent) = &env.parent {
+                    children.push(Object::Env(parent.clone()));
+                }
+            }
+            Object::Function(function) => children.push(Object::Env(function.env.clone())),
+            Object::Sequence(sequence) => sequence.trace(&mut children)?,
+        }
+        Some(children)
+    }
+}
+
+/// Adds the objects a value refers to.
+pub(crate) fn value_children(value: &Value, children: &mut Vec<Object>) {
+    match value {
+        Value::Array(items) => items.iter().for_each(|v| value_children(v, children)),
+        Value::Object(entries) => entries.values().for_each(|v| value_children(v, children)),
+        Value::Struct(s) => s
+            .fields
+            .iter()
+            .for_each(|(_, v)| value_children(v, children)),
+        Value::Variant(v) => v.values.iter().for_each(|v| value_children(v, children)),
+        Value::Function(function) => children.push(Object::Function(function.clone())),
+        Value::Sequence(sequence) => children.push(Object::Sequence(sequence.clone())),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{AstKind, AstNode};
+    use crate::value::StructValue;
+
+    /// A tracked environment holding a closure over itself, so that dropping every
+    /// other reference leaves it alive in a cycle.
+    fn cycle(
+        heap: &mut Heap,
+        parent: Option<&Rc<RefCell<Environment>>>,
+    ) -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new(match parent {
+            Some(parent) => Environment::with_parent(parent.clone()),
+            None => Environment::new(),
+        }));
+        heap.track(&env);
+        let function = closure(&env);
+        env.borrow_mut().define(Ident("self".into()), function);
+        env
+    }
+
+    fn closure(env: &Rc<RefCell<Environment>>) -> Value {
+        Value::Function(Rc::new(Function {
+            name: None,
+            params: Vec::new(),
+            body: Rc::new(AstNode::from(AstKind::Literal(Value::Null))),
+            generator: false,
+            env: env.clone(),
+        }))
+    }
+
+    #[test]
+    fn it_frees_only_environments_nothing_outside_refers_to() {
+        let mut heap = Heap::new();
+        let global = Rc::new(RefCell::new(Environment::new()));
+        heap.track(&global);
+
+        let in_sequence = cycle(&mut heap, None);
+        let sequence = Sequence::from_items(vec![closure(&in_sequence)]);
+        global
+            .borrow_mut()
+            .define(Ident("items".into()), Value::Sequence(sequence));
+
+        let in_struct = cycle(&mut heap, None);
+        global.borrow_mut().define(
+            Ident("point".into()),
+            Value::Struct(StructValue {
+                name: "Point".into(),
+                fields: vec![("on_move".into(), closure(&in_struct))],
+            }),
+        );
+
+        let parent = cycle(&mut heap, None);
+        let child = Rc::new(RefCell::new(Environment::with_parent(parent.clone())));
+        heap.track(&child);
+
+        let garbage = cycle(&mut heap, Some(&global));
+
+        let kept = [&in_sequence, &in_struct, &parent].map(Rc::downgrade);
+        let freed = Rc::downgrade(&garbage);
+        drop((in_sequence, in_struct, parent, garbage));
+
+        heap.collect(&[]);
+        for env in &kept {
+            let env = env.upgrade().expect("reachable from outside the heap");
+            assert!(env.borrow().is_bound(&Ident("self".into())));
+        }
+        assert!(freed.upgrade().is_none());
+
+        // Without the outside references, everything is garbage.
+        drop((global, child));
+        heap.collect(&[]);
+        assert!(kept.iter().all(|env| env.upgrade().is_none()));
+        assert!(heap.envs.is_empty());
+    }
+
+    #[test]
+    fn it_keeps_environments_in_use() {
+        let mut heap = Heap::new();
+        let scope = Rc::new(RefCell::new(Environment::new()));
+        heap.track(&scope);
+        let inner = cycle(&mut heap, None);
+        let function = closure(&inner);
+        scope.borrow_mut().define(Ident("f".into()), function);
+        let inner_weak = Rc::downgrade(&inner);
+        drop(inner);
+
+        // A borrowed environment can't be looked into, so whatever it refers to is
+        // assumed to be in use, and it isn't cleared even when it was released.
+        let guard = scope.borrow_mut();
+        heap.collect(&[&scope]);
+        assert!(inner_weak.upgrade().is_some());
+        assert!(guard.is_bound(&Ident("f".into())));
+
+        drop(guard);
+        heap.collect(&[&scope]);
+        assert!(!scope.borrow().is_bound(&Ident("f".into())));
+        assert!(inner_weak.upgrade().is_none());
+    }
 }
@@ -0,0 +1,270 @@
+//! The `$`-forms the JIR parser knows, so that hosts can add their own, or disable
+//! or replace built-in ones.
+//!
+//! ```
+//! use json_monkey_rs::forms::{Form, FormRegistry};
+//! use json_monkey_rs::interpreter::Interpreter;
+//! use json_monkey_rs::jir::JirParser;
+//! use json_monkey_rs::value::Value;
+//!
+//! let mut forms = FormRegistry::builtin();
+//! forms.register(Form::custom("$discount", 2, Some(2), |interpreter, args| {
+//!     let price = interpreter.eval(&args[0])?.to_number()?;
+//!     let percent = interpreter.eval(&args[1])?.to_number()?;
+//!     Ok(Value::Number(price * (100.0 - percent) / 100.0))
+//! }));
+//!
+//! let node = JirParser::new(forms.into()).read(r#"["$discount", 80, 25]"#).unwrap();
+//! assert_eq!(Interpreter::new().eval(&node).unwrap(), Value::Number(60.0));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{AstKind, AstNode};
+use crate::interpreter::{EvalError, Interpreter};
+use crate::jir::{self, JirParser, ParseError};
+use crate::json::Json;
+use crate::span::Located;
+use crate::value::Value;
+
+/// Builds the node for a form from its JSON, name included.
+type Parser = Rc<dyn Fn(&mut JirParser, &[Json]) -> Result<AstKind, Located<ParseError>>>;
+
+/// Evaluates a host form from its unevaluated operands.
+pub type Evaluator = Rc<dyn Fn(&mut Interpreter, &[AstNode]) -> Result<Value, EvalError>>;
+
+/// A form name, the number of operands it takes and how to parse it.
+pub struct Form {
+    pub name: String,
+    pub min_args: usize,
+    pub max_args: Option<usize>,
+    parse: Parser,
+}
+
+impl Form {
+    /// A form that parses to any node kind, the way the built-in forms are defined.
+    /// The parser is only called with an operand count within the arity.
+    pub fn new<F>(name: &str, min_args: usize, max_args: Option<usize>, parse: F) -> Self
+    where
+        F: Fn(&mut JirParser, &[Json]) -> Result<AstKind, Located<ParseError>> + 'static,
+    {
+        Self {
+            name: name.into(),
+            min_args,
+            max_args,
+            parse: Rc::new(parse),
+        }
+    }
+
+    /// A host form evaluated by `eval`, with its operands parsed as expressions.
+    pub fn custom<F>(name: &str, min_args: usize, max_args: Option<usize>, eval: F) -> Self
+    where
+        F: Fn(&mut Interpreter, &[AstNode]) -> Result<Value, EvalError> + 'static,
+    {
+        Self::custom_with_parser(name, min_args, max_args, parse_operands, eval)
+    }
+
+    /// Like `Form::custom`, with the operands parsed by `parse`, for forms that take
+    /// names or other data rather than expressions. `parse` is given the operands
+    /// without the form name.
+    pub fn custom_with_parser<P, F>(
+        name: &str,
+        min_args: usize,
+        max_args: Option<usize>,
+        parse: P,
+        eval: F,
+    ) -> Self
+    where
+        P: Fn(&mut JirParser, &[Json]) -> Result<Vec<AstNode>, Located<ParseError>> + 'static,
+        F: Fn(&mut Interpreter, &[AstNode]) -> Result<Value, EvalError> + 'static,
+    {
+        let custom = CustomForm {
+            name: name.into(),
+            eval: Rc::new(eval),
+        };
+        Self {
+            name: name.into(),
+            min_args,
+            max_args,
+            parse: custom_parser(custom, parse),
+        }
+    }
+
+    pub(crate) fn parse(
+        &self,
+        parser: &mut JirParser,
+        vs: &[Json],
+    ) -> Result<AstKind, Located<ParseError>> {
+        (self.parse)(parser, vs)
+    }
+}
+
+fn custom_parser<F>(custom: CustomForm, parse: F) -> Parser
+where
+    F: Fn(&mut JirParser, &[Json]) -> Result<Vec<AstNode>, Located<ParseError>> + 'static,
+{
+    Rc::new(move |parser, vs| Ok(AstKind::Custom(custom.clone(), parse(parser, &vs[1..])?)))
+}
+
+fn parse_operands(
+    parser: &mut JirParser,
+    operands: &[Json],
+) -> Result<Vec<AstNode>, Located<ParseError>> {
+    operands
+        .iter()
+        .map(|operand| parser.parse_expression(operand))
+        .collect()
+}
+
+/// Forms by name.
+#[derive(Default)]
+pub struct FormRegistry {
+    forms: HashMap<String, Rc<Form>>,
+}
+
+impl FormRegistry {
+    /// A registry without any forms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with every built-in form.
+    pub fn builtin() -> Self {
+        let mut forms = Self::new();
+        jir::register_builtins(&mut forms);
+        forms
+    }
+
+    /// Adds a form, replacing any form of the same name.
+    pub fn register(&mut self, form: Form) {
+        self.forms.insert(form.name.clone(), Rc::new(form));
+    }
+
+    /// Removes a form, so that using it is an `UnknownForm` error.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.forms.remove(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rc<Form>> {
+        self.forms.get(name)
+    }
+}
+
+/// A host form in the syntax tree, carrying its evaluator.
+#[derive(Clone)]
+pub struct CustomForm {
+    pub name: String,
+    pub eval: Evaluator,
+}
+
+impl fmt::Debug for CustomForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(forms: FormRegistry, source: &str) -> Result<Value, String> {
+        let node = JirParser::new(Rc::new(forms))
+            .read(source)
+            .map_err(|e| format!("{:?}", e.error))?;
+        Interpreter::new()
+            .eval(&node)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    #[test]
+    fn it_parses_and_evaluates_host_forms() {
+        let mut forms = FormRegistry::builtin();
+        forms.register(Form::custom(
+            "$discount",
+            2,
+            Some(2),
+            |interpreter, args| {
+                let price = interpreter.eval(&args[0])?.to_number()?;
+                let percent = interpreter.eval(&args[1])?.to_number()?;
+                Ok(Value::Number(price * (100.0 - percent) / 100.0))
+            },
+        ));
+        forms.register(Form::custom_with_parser(
+            "$env",
+            1,
+            Some(1),
+            |_, operands| {
+                let name = JirParser::parse_name(&operands[0])?;
+                Ok(vec![AstKind::Literal(Value::String(name)).into()])
+            },
+            |_, args| match &args[0].kind {
+                AstKind::Literal(Value::String(name)) => Ok(Value::String(format!("${}", name))),
+                _ => Err(EvalError::UnsupportedConversion),
+            },
+        ));
+        let forms = Rc::new(forms);
+        let read = |source| {
+            JirParser::new(forms.clone())
+                .read(source)
+                .map_err(|e| e.error)
+        };
+
+        let node = read(r#"["$discount", ["$add", 60, 20], 25]"#).expect("valid JIR");
+        assert_eq!(
+            format!("{:?}", node),
+            r#"Custom("$discount", [Add(Literal(Number(60.0)), Literal(Number(20.0))), Literal(Number(25.0))])"#
+        );
+        assert_eq!(
+            Interpreter::new().eval(&node).ok(),
+            Some(Value::Number(60.0))
+        );
+
+        let node = read(r#"["$env", "HOME"]"#).expect("valid JIR");
+        assert_eq!(
+            Interpreter::new().eval(&node).ok(),
+            Some(Value::String("$HOME".into()))
+        );
+        assert!(matches!(
+            read(r#"["$env", ["$ref", "x"]]"#),
+            Err(ParseError::NameExpected)
+        ));
+        assert!(matches!(
+            read(r#"["$discount", 1]"#),
+            Err(ParseError::NotEnoughArgs {
+                actual: 1,
+                expected_min: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn it_disables_and_overrides_builtin_forms() {
+        let mut forms = FormRegistry::builtin();
+        assert!(forms.remove("$while"));
+        assert_eq!(
+            eval(forms, r#"["$while", false, 1]"#),
+            Err(r#"UnknownForm("$while")"#.into())
+        );
+
+        let mut forms = FormRegistry::builtin();
+        forms.register(Form::custom("$add", 1, None, |interpreter, args| {
+            let mut joined = String::new();
+            for arg in args {
+                joined.push_str(&interpreter.eval(arg)?.to_string()?);
+            }
+            Ok(Value::String(joined))
+        }));
+        assert_eq!(
+            eval(forms, r#"["$add", 1, 2, "x"]"#),
+            Ok(Value::String("12x".into()))
+        );
+
+        let mut forms = FormRegistry::new();
+        forms.register(Form::new("$one", 0, Some(0), |_, _| {
+            Ok(AstKind::Literal(Value::Number(1.0)))
+        }));
+        assert_eq!(eval(forms, r#"["$one"]"#), Ok(Value::Number(1.0)));
+    }
+}
@@ -1,22 +1,95 @@
-use crate::ast::{AstNode, Ident};
-use crate::environment::Environment;
-use crate::value::Value;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::ast::{AstKind, AstNode, Field, Ident, MatchArm, Param, Pattern, Variant};
+use crate::datetime::{self, Clock, SystemClock};
+use crate::environment::{Environment, Heap};
+use crate::query::Query;
+use crate::sequence::Sequence;
+use crate::span::{Located, Span};
+use crate::value::{Function, Key, StructValue, Value, VariantValue};
 
 pub struct Interpreter {
-    global_env: Environment,
+    env: Rc<RefCell<Environment>>,
+    clock: Box<dyn Clock>,
+    structs: HashMap<String, StructDecl>,
+    enums: HashMap<String, Rc<Vec<Variant>>>,
+    error_span: Option<Span>,
+    heap: Heap,
+    /// Parsed `$query` strings that are written as literals, so that they are only
+    /// parsed once however often the query runs.
+    queries: HashMap<String, Query>,
+}
+
+/// A `$struct` and the scope it was declared in, where its field defaults are
+/// evaluated.
+#[derive(Clone)]
+struct StructDecl {
+    fields: Rc<Vec<Field>>,
+    scope: Rc<RefCell<Environment>>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_clock(Box::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Box<dyn Clock>) -> Self {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let mut heap = Heap::new();
+        heap.track(&env);
         Self {
-            global_env: Environment::new(),
+            env,
+            clock,
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            error_span: None,
+            heap,
+            queries: HashMap::new(),
+        }
+    }
+
+    /// Frees environments that are only kept alive by closures bound in them. This
+    /// happens on its own as scopes are created, and when the interpreter is
+    /// dropped, so hosts only need it to release memory at a particular time.
+    pub fn collect_garbage(&mut self) {
+        self.heap.collect(&[]);
+    }
+
+    /// A new scope inside `parent`.
+    fn scope(&mut self, parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        if self.heap.is_full() {
+            self.collect_garbage();
         }
+        let scope = Rc::new(RefCell::new(Environment::with_parent(parent)));
+        self.heap.track(&scope);
+        scope
     }
 
     pub fn eval(&mut self, ast: &AstNode) -> Result<Value, EvalError> {
-        match ast {
-            AstNode::Literal(value) => Ok(value.clone()),
-            AstNode::Add(lhs, rhs) => {
+        let result = self.eval_kind(ast);
+        if result.is_err() && self.error_span.is_none() {
+            self.error_span = ast.span;
+        }
+        result
+    }
+
+    /// Like `eval`, but locates an error at the innermost node with a span that was
+    /// being evaluated when it was raised. Boxed, as it's large for a `Result`.
+    pub fn eval_located(&mut self, ast: &AstNode) -> Result<Value, Box<Located<EvalError>>> {
+        self.error_span = None;
+        self.eval(ast)
+            .map_err(|error| Box::new(Located::new(error, self.error_span.take())))
+    }
+
+    fn eval_kind(&mut self, ast: &AstNode) -> Result<Value, EvalError> {
+        match &ast.kind {
+            AstKind::Literal(value) => Ok(value.clone()),
+            AstKind::Add(lhs, rhs) => {
                 let lv = self.eval(lhs)?;
                 let rv = self.eval(rhs)?;
                 match (lv, rv) {
@@ -25,25 +98,24 @@ impl Interpreter {
                     _ => Err(EvalError::UnexpectedTypeForOperation),
                 }
             }
-            AstNode::Sub(lhs, rhs) => {
+            AstKind::Sub(lhs, rhs) => {
                 let lv = self.eval(lhs)?;
                 let rv = self.eval(rhs)?;
                 Ok(Value::Number(lv.to_number()? - rv.to_number()?))
             }
-            AstNode::Ident(ident) => {
+            AstKind::Ident(ident) => {
                 // TODO: without clone
-                self.global_env
-                    .bindings
+                self.env
+                    .borrow()
                     .get(ident)
-                    .cloned()
-                    .ok_or(EvalError::UndefinedIdent(ident.clone()))
+                    .ok_or_else(|| EvalError::UndefinedIdent(ident.clone()))
             }
-            AstNode::Bind(ident, ast) => {
-                let value = self.eval(ast)?;
-                self.global_env.bindings.insert(ident.clone(), value);
+            AstKind::Bind(ident, ast) => {
+                let value = self.eval_named(ast, ident)?;
+                self.env.borrow_mut().assign(ident.clone(), value);
                 Ok(Value::Null)
             }
-            AstNode::If(cond, true_branch, false_branch) => {
+            AstKind::If(cond, true_branch, false_branch) => {
                 if self.eval(cond)?.to_boolean()? {
                     Ok(self.eval(true_branch)?)
                 } else {
@@ -54,7 +126,7 @@ impl Interpreter {
                     }
                 }
             }
-            AstNode::While(cond, body) => {
+            AstKind::While(cond, body) => {
                 loop {
                     if self.eval(cond)?.to_boolean()? {
                         self.eval(body)?;
@@ -64,35 +136,707 @@ impl Interpreter {
                 }
                 Ok(Value::Null)
             }
-            AstNode::And(lhs, rhs) => {
+            AstKind::And(lhs, rhs) => {
                 let lv = self.eval(lhs)?.to_boolean()?;
                 let rv = self.eval(rhs)?.to_boolean()?;
                 Ok(Value::Boolean(lv && rv))
             }
-            AstNode::Or(lhs, rhs) => {
+            AstKind::Or(lhs, rhs) => {
                 let lv = self.eval(lhs)?.to_boolean()?;
                 let rv = self.eval(rhs)?.to_boolean()?;
                 Ok(Value::Boolean(lv || rv))
             }
-            AstNode::Not(arg) => Ok(Value::Boolean(!self.eval(arg)?.to_boolean()?)),
-            AstNode::Eq(lhs, rhs) => {
+            AstKind::Not(arg) => Ok(Value::Boolean(!self.eval(arg)?.to_boolean()?)),
+            AstKind::Eq(lhs, rhs) => {
                 let lv = self.eval(lhs)?;
                 let rv = self.eval(rhs)?;
                 Ok(Value::Boolean(lv == rv))
             }
-            AstNode::NotEq(lhs, rhs) => {
+            AstKind::EqAll(operands) => {
+                let values = operands
+                    .iter()
+                    .map(|operand| self.eval(operand))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                Ok(Value::Boolean(
+                    values.windows(2).all(|pair| pair[0] == pair[1]),
+                ))
+            }
+            AstKind::NotEq(lhs, rhs) => {
                 let lv = self.eval(lhs)?;
                 let rv = self.eval(rhs)?;
                 Ok(Value::Boolean(lv != rv))
             }
+            AstKind::Lt(lhs, rhs) => self.eval_comparison(lhs, rhs, Ordering::is_lt),
+            AstKind::Lte(lhs, rhs) => self.eval_comparison(lhs, rhs, Ordering::is_le),
+            AstKind::Gt(lhs, rhs) => self.eval_comparison(lhs, rhs, Ordering::is_gt),
+            AstKind::Gte(lhs, rhs) => self.eval_comparison(lhs, rhs, Ordering::is_ge),
+            AstKind::Let(ident, _, ast) => {
+                let value = self.eval_named(ast, ident)?;
+                self.env.borrow_mut().define(ident.clone(), value);
+                Ok(Value::Null)
+            }
+            AstKind::Do(stmts) => {
+                let mut last = Value::Null;
+                for stmt in stmts {
+                    last = self.eval(stmt)?;
+                }
+                Ok(last)
+            }
+            AstKind::Array(items) => items
+                .iter()
+                .map(|item| self.eval(item))
+                .collect::<Result<Vec<Value>, EvalError>>()
+                .map(Value::Array),
+            AstKind::Object(entries) => entries
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), self.eval(value)?)))
+                .collect::<Result<BTreeMap<String, Value>, EvalError>>()
+                .map(Value::Object),
+            AstKind::Fn(params, body) => Ok(self.make_function(params, body, false, None)),
+            AstKind::Call(callee, args, named) => {
+                let function = self.eval(callee)?.to_function()?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                let named = named
+                    .iter()
+                    .map(|(name, arg)| Ok((name.clone(), self.eval(arg)?)))
+                    .collect::<Result<Vec<(String, Value)>, EvalError>>()?;
+                self.call_with_named(&function, args, named)
+            }
+            AstKind::Map(items, callback) => {
+                let items = self.eval(items)?;
+                let callback = self.eval(callback)?.to_function()?;
+                match items {
+                    Value::Sequence(seq) => Ok(Value::Sequence(seq.map(callback))),
+                    items => {
+                        let mapped = Sequence::from_items(items.into_array()?).map(callback);
+                        Ok(Value::Array(mapped.collect(self)?))
+                    }
+                }
+            }
+            AstKind::Filter(items, callback) => {
+                let items = self.eval(items)?;
+                let callback = self.eval(callback)?.to_function()?;
+                match items {
+                    Value::Sequence(seq) => Ok(Value::Sequence(seq.filter(callback))),
+                    items => {
+                        let kept = Sequence::from_items(items.into_array()?).filter(callback);
+                        Ok(Value::Array(kept.collect(self)?))
+                    }
+                }
+            }
+            AstKind::Reduce(items, callback, init) => {
+                let items = self.eval(items)?.to_sequence()?;
+                let callback = self.eval(callback)?.to_function()?;
+                let mut acc = self.eval(init)?;
+                let mut index = 0;
+                while let Some(item) = items.next(self)? {
+                    acc = self.call_callback(&callback, index, vec![acc, item])?;
+                    index += 1;
+                }
+                Ok(acc)
+            }
+            AstKind::Some(items, callback) => {
+                let items = self.eval(items)?.to_sequence()?;
+                let callback = self.eval(callback)?.to_function()?;
+                let mut index = 0;
+                while let Some(item) = items.next(self)? {
+                    if self
+                        .call_callback(&callback, index, vec![item])?
+                        .to_boolean()?
+                    {
+                        return Ok(Value::Boolean(true));
+                    }
+                    index += 1;
+                }
+                Ok(Value::Boolean(false))
+            }
+            AstKind::Every(items, callback) => {
+                let items = self.eval(items)?.to_sequence()?;
+                let callback = self.eval(callback)?.to_function()?;
+                let mut index = 0;
+                while let Some(item) = items.next(self)? {
+                    if !self
+                        .call_callback(&callback, index, vec![item])?
+                        .to_boolean()?
+                    {
+                        return Ok(Value::Boolean(false));
+                    }
+                    index += 1;
+                }
+                Ok(Value::Boolean(true))
+            }
+            AstKind::Sort(items, key) => {
+                let items = self.eval(items)?.to_sequence()?.collect(self)?;
+                let keys = match key {
+                    Some(key) => self.eval_keys(&items, key)?,
+                    None => items.clone(),
+                };
+                let mut order = (0..items.len()).collect::<Vec<_>>();
+                order.sort_by(|&a, &b| keys[a].total_cmp(&keys[b]));
+                Ok(Value::Array(
+                    order.into_iter().map(|i| items[i].clone()).collect(),
+                ))
+            }
+            AstKind::GroupBy(items, key) => {
+                let items = self.eval(items)?.to_sequence()?.collect(self)?;
+                let keys = self.eval_keys(&items, key)?;
+                let mut groups = BTreeMap::<Key, Vec<Value>>::new();
+                for (key, item) in keys.iter().zip(items) {
+                    groups.entry(Key(key)).or_default().push(item);
+                }
+                Ok(Value::Array(
+                    groups
+                        .into_iter()
+                        .map(|(key, items)| Value::Array(vec![key.0.clone(), Value::Array(items)]))
+                        .collect(),
+                ))
+            }
+            AstKind::Unique(items) => {
+                let items = self.eval(items)?.to_sequence()?.collect(self)?;
+                Ok(Value::Array(unique(items.iter(), |_| true)))
+            }
+            AstKind::Union(lhs, rhs) => {
+                let lhs = self.eval(lhs)?.to_sequence()?.collect(self)?;
+                let rhs = self.eval(rhs)?.to_sequence()?.collect(self)?;
+                Ok(Value::Array(unique(lhs.iter().chain(&rhs), |_| true)))
+            }
+            AstKind::Intersect(lhs, rhs) | AstKind::Difference(lhs, rhs) => {
+                let lhs = self.eval(lhs)?.to_sequence()?.collect(self)?;
+                let rhs = self.eval(rhs)?.to_sequence()?.collect(self)?;
+                let rhs = rhs.iter().map(Key).collect::<HashSet<_>>();
+                let keep = matches!(ast.kind, AstKind::Intersect(..));
+                Ok(Value::Array(unique(lhs.iter(), |key| {
+                    rhs.contains(key) == keep
+                })))
+            }
+            AstKind::Range(start, end, step) => {
+                let start = self.eval(start)?.to_number()?;
+                let end = match end {
+                    Some(end) => Some(self.eval(end)?.to_number()?),
+                    None => None,
+                };
+                let step = match step {
+                    Some(step) => self.eval(step)?.to_number()?,
+                    None => 1.0,
+                };
+                if step == 0.0 || step.is_nan() {
+                    return Err(EvalError::InvalidRangeStep);
+                }
+                Ok(Value::Sequence(Sequence::range(start, end, step)))
+            }
+            AstKind::Take(items, count) => {
+                let items = self.eval(items)?.to_sequence()?;
+                let count = self.eval(count)?.to_number()?.max(0.0) as usize;
+                Ok(Value::Sequence(items.take(count)))
+            }
+            AstKind::TakeWhile(items, predicate) => {
+                let items = self.eval(items)?.to_sequence()?;
+                let predicate = self.eval(predicate)?.to_function()?;
+                Ok(Value::Sequence(items.take_while(predicate)))
+            }
+            AstKind::Collect(items) => {
+                let items = self.eval(items)?.to_sequence()?;
+                Ok(Value::Array(items.collect(self)?))
+            }
+            AstKind::Gen(params, body) => Ok(self.make_function(params, body, true, None)),
+            AstKind::Yield(_) => Err(EvalError::YieldOutsideGenerator),
+            AstKind::StructDecl(name, fields) => {
+                let declaration = StructDecl {
+                    fields: Rc::new(fields.clone()),
+                    scope: self.env.clone(),
+                };
+                self.structs.insert(name.clone(), declaration);
+                Ok(Value::Null)
+            }
+            AstKind::New(name, values) => {
+                let StructDecl { fields, scope } = self
+                    .structs
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| EvalError::UndefinedStruct(name.clone()))?;
+                let mut provided = HashMap::new();
+                for (field, value) in values {
+                    if !fields.iter().any(|f| &f.name == field) {
+                        return Err(EvalError::UnknownField {
+                            struct_name: name.clone(),
+                            field: field.clone(),
+                        });
+                    }
+                    provided.insert(field, self.eval(value)?);
+                }
+                let mut instance = StructValue {
+                    name: name.clone(),
+                    fields: Vec::with_capacity(fields.len()),
+                };
+                for field in fields.iter() {
+                    let value = match (provided.remove(&field.name), &field.default) {
+                        (Some(value), _) => value,
+                        (None, Some(default)) => self.eval_in(&scope, default)?,
+                        (None, None) => {
+                            return Err(EvalError::MissingField {
+                                struct_name: name.clone(),
+                                field: field.name.clone(),
+                            })
+                        }
+                    };
+                    instance.fields.push((field.name.clone(), value));
+                }
+                Ok(Value::Struct(instance))
+            }
+            AstKind::GetField(target, field) => {
+                let instance = self.eval(target)?.into_struct()?;
+                instance
+                    .get(field)
+                    .cloned()
+                    .ok_or_else(|| EvalError::UnknownField {
+                        struct_name: instance.name.clone(),
+                        field: field.clone(),
+                    })
+            }
+            AstKind::With(target, values) => {
+                let mut instance = self.eval(target)?.into_struct()?;
+                for (field, value) in values {
+                    let value = self.eval(value)?;
+                    match instance.fields.iter_mut().find(|(name, _)| name == field) {
+                        Some((_, slot)) => *slot = value,
+                        None => {
+                            return Err(EvalError::UnknownField {
+                                struct_name: instance.name,
+                                field: field.clone(),
+                            })
+                        }
+                    }
+                }
+                Ok(Value::Struct(instance))
+            }
+            AstKind::Assert(cond, message, expression) => {
+                let (passed, operands) = self.eval_assertion(cond)?;
+                if passed {
+                    return Ok(Value::Null);
+                }
+                let message = match message {
+                    Some(message) => self.eval(message)?.to_string()?,
+                    None => "assertion failed".into(),
+                };
+                Err(EvalError::AssertionFailed {
+                    message,
+                    expression: expression.clone(),
+                    operands,
+                })
+            }
+            AstKind::Coalesce(args) => {
+                for arg in args {
+                    let value = match &arg.kind {
+                        AstKind::Ident(ident) => {
+                            self.env.borrow().get(ident).unwrap_or(Value::Null)
+                        }
+                        _ => self.eval(arg)?,
+                    };
+                    if value != Value::Null {
+                        return Ok(value);
+                    }
+                }
+                Ok(Value::Null)
+            }
+            AstKind::SafeGet(target, keys) => {
+                let target = self.eval(target)?;
+                let path = keys
+                    .iter()
+                    .map(|key| self.eval(key))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                let path = parse_path(&Value::Array(path))?;
+                Ok(get_in(&target, &path).cloned().unwrap_or(Value::Null))
+            }
+            AstKind::Defined(ident) => Ok(Value::Boolean(self.env.borrow().is_bound(ident))),
+            AstKind::GetIn(target, path, strict) => {
+                let target = self.eval(target)?;
+                let path = parse_path(&self.eval(path)?)?;
+                let strict = match strict {
+                    Some(strict) => self.eval(strict)?.to_boolean()?,
+                    None => false,
+                };
+                match get_in(&target, &path) {
+                    Some(value) => Ok(value.clone()),
+                    None if strict => Err(EvalError::PathNotFound(render_path(&path))),
+                    None => Ok(Value::Null),
+                }
+            }
+            AstKind::Query(target, query) => {
+                let target = self.eval(target)?;
+                if let AstKind::Literal(Value::String(source)) = &query.kind {
+                    if !self.queries.contains_key(source) {
+                        self.queries.insert(source.clone(), Query::parse(source)?);
+                    }
+                    return Ok(Value::Array(self.queries[source].evaluate(&target)));
+                }
+                let query = Query::parse(&self.eval(query)?.to_string()?)?;
+                Ok(Value::Array(query.evaluate(&target)))
+            }
+            AstKind::SetIn(target, path, value) => {
+                let target = self.eval(target)?;
+                let path = parse_path(&self.eval(path)?)?;
+                let value = self.eval(value)?;
+                set_in(target, &path, value)
+                    .ok_or_else(|| EvalError::PathNotFound(render_path(&path)))
+            }
+            AstKind::UpdateIn(target, path, callback) => {
+                let target = self.eval(target)?;
+                let path = parse_path(&self.eval(path)?)?;
+                let callback = self.eval(callback)?.to_function()?;
+                let current = get_in(&target, &path).cloned().unwrap_or(Value::Null);
+                let value = self.call(&callback, vec![current])?;
+                set_in(target, &path, value)
+                    .ok_or_else(|| EvalError::PathNotFound(render_path(&path)))
+            }
+            AstKind::EnumDecl(name, variants) => {
+                self.enums.insert(name.clone(), Rc::new(variants.clone()));
+                Ok(Value::Null)
+            }
+            AstKind::NewVariant(enum_name, tag, args) => {
+                let variants = self
+                    .enums
+                    .get(enum_name)
+                    .cloned()
+                    .ok_or_else(|| EvalError::UndefinedEnum(enum_name.clone()))?;
+                let variant = variants.iter().find(|v| &v.name == tag).ok_or_else(|| {
+                    EvalError::UnknownVariant {
+                        enum_name: enum_name.clone(),
+                        variant: tag.clone(),
+                    }
+                })?;
+                if args.len() != variant.fields.len() {
+                    return Err(EvalError::ArgumentCountMismatch {
+                        actual: args.len(),
+                        expected: variant.fields.len(),
+                    });
+                }
+                Ok(Value::Variant(VariantValue {
+                    enum_name: enum_name.clone(),
+                    tag: tag.clone(),
+                    values: args
+                        .iter()
+                        .map(|arg| self.eval(arg))
+                        .collect::<Result<Vec<Value>, EvalError>>()?,
+                }))
+            }
+            AstKind::Tag(value) => match self.eval(value)? {
+                Value::Variant(variant) => Ok(Value::String(variant.tag)),
+                _ => Err(EvalError::UnexpectedTypeForOperation),
+            },
+            AstKind::Match(scrutinee, arms) => {
+                let value = self.eval(scrutinee)?;
+                self.eval_match(value, arms)
+            }
+            AstKind::Now => Ok(Value::DateTime(self.clock.now())),
+            AstKind::DateTime(arg) => {
+                let s = self.eval(arg)?.to_string()?;
+                Ok(Value::DateTime(datetime::parse(&s)?))
+            }
+            AstKind::FormatDateTime(arg, pattern) => {
+                let dt = self.eval(arg)?.to_datetime()?;
+                match pattern {
+                    Some(pattern) => {
+                        let pattern = self.eval(pattern)?.to_string()?;
+                        Ok(Value::String(datetime::format_with(&dt, &pattern)?))
+                    }
+                    None => Ok(Value::String(datetime::format(&dt))),
+                }
+            }
+            AstKind::DateAdd(arg, amount, unit) => {
+                let dt = self.eval(arg)?.to_datetime()?;
+                let amount = self.eval(amount)?.to_number()?;
+                Ok(Value::DateTime(datetime::add(&dt, amount, *unit)?))
+            }
+            AstKind::DateDiff(lhs, rhs, unit) => {
+                let lv = self.eval(lhs)?.to_datetime()?;
+                let rv = self.eval(rhs)?.to_datetime()?;
+                Ok(Value::Number(datetime::diff(&lv, &rv, *unit)?))
+            }
+            AstKind::DatePart(arg, component) => {
+                let dt = self.eval(arg)?.to_datetime()?;
+                Ok(Value::Number(datetime::component(&dt, *component)))
+            }
+            AstKind::Custom(form, args) => (form.eval)(self, args),
+            AstKind::Error => Err(EvalError::UnparsedNode),
+        }
+    }
+
+    /// Evaluates the value of a `$bind`/`$let`, naming it after the binding if it's
+    /// a function literal.
+    fn eval_named(&mut self, ast: &AstNode, ident: &Ident) -> Result<Value, EvalError> {
+        match &ast.kind {
+            AstKind::Fn(params, body) => Ok(self.make_function(params, body, false, Some(ident))),
+            AstKind::Gen(params, body) => Ok(self.make_function(params, body, true, Some(ident))),
+            _ => self.eval(ast),
+        }
+    }
+
+    fn make_function(
+        &self,
+        params: &[Param],
+        body: &Rc<AstNode>,
+        generator: bool,
+        name: Option<&Ident>,
+    ) -> Value {
+        Value::Function(Rc::new(Function {
+            name: name.map(|ident| ident.0.clone()),
+            params: params.to_vec(),
+            body: body.clone(),
+            generator,
+            env: self.env.clone(),
+        }))
+    }
+
+    pub fn call(&mut self, function: &Function, args: Vec<Value>) -> Result<Value, EvalError> {
+        self.call_with_named(function, args, Vec::new())
+    }
+
+    /// Binds positional arguments to parameters in order, then named arguments by
+    /// parameter name. An omitted parameter takes its default, and a rest parameter
+    /// collects the positional arguments left over.
+    pub fn call_with_named(
+        &mut self,
+        function: &Function,
+        args: Vec<Value>,
+        mut named: Vec<(String, Value)>,
+    ) -> Result<Value, EvalError> {
+        let function_name = || function.name.clone().unwrap_or_else(|| "<fn>".into());
+        let actual = args.len() + named.len();
+        let positional = function.params.iter().filter(|p| !p.rest).count();
+        if args.len() > positional && !function.params.iter().any(|p| p.rest) {
+            return Err(EvalError::TooManyArgs {
+                function: function_name(),
+                actual: args.len(),
+                expected_max: positional,
+            });
+        }
+        let scope = self.scope(function.env.clone());
+        let mut args = args.into_iter();
+        for param in &function.params {
+            let by_name = named
+                .iter()
+                .position(|(name, _)| name == &param.ident.0)
+                .map(|index| named.remove(index).1);
+            let by_position = if param.rest {
+                let rest = args.by_ref().collect::<Vec<_>>();
+                // An empty rest may be given by name instead.
+                (by_name.is_none() || !rest.is_empty()).then_some(Value::Array(rest))
+            } else {
+                args.next()
+            };
+            let value = match (by_position, by_name, &param.default) {
+                (Some(_), Some(_), _) => {
+                    return Err(EvalError::DuplicateArg {
+                        function: function_name(),
+                        param: param.ident.0.clone(),
+                    })
+                }
+                (Some(value), None, _) | (None, Some(value), _) => value,
+                (None, None, Some(default)) => self.eval_in(&scope, default)?,
+                (None, None, None) => {
+                    return Err(EvalError::NotEnoughArgs {
+                        function: function_name(),
+                        param: param.ident.0.clone(),
+                        actual,
+                        expected_min: function
+                            .params
+                            .iter()
+                            .filter(|p| !p.rest && p.default.is_none())
+                            .count(),
+                    })
+                }
+            };
+            scope.borrow_mut().define(param.ident.clone(), value);
+        }
+        if let Some((name, _)) = named.into_iter().next() {
+            // Each parameter takes the first argument with its name, so one that is
+            // left over names a parameter given twice, or none.
+            if function.params.iter().any(|p| p.ident.0 == name) {
+                return Err(EvalError::DuplicateArg {
+                    function: function_name(),
+                    param: name,
+                });
+            }
+            return Err(EvalError::UnknownNamedArg {
+                function: function_name(),
+                param: name,
+            });
+        }
+        if function.generator {
+            return Ok(Value::Sequence(Sequence::generator(scope, &function.body)));
+        }
+        self.eval_in(&scope, &function.body)
+    }
+
+    /// Evaluates an `$assert` condition. The operands of a comparison or `$not` are
+    /// evaluated once and reported; any other condition reports its own value.
+    fn eval_assertion(&mut self, cond: &AstNode) -> Result<(bool, Vec<Value>), EvalError> {
+        let (lhs, rhs) = match &cond.kind {
+            AstKind::Eq(lhs, rhs)
+            | AstKind::NotEq(lhs, rhs)
+            | AstKind::Lt(lhs, rhs)
+            | AstKind::Lte(lhs, rhs)
+            | AstKind::Gt(lhs, rhs)
+            | AstKind::Gte(lhs, rhs) => (lhs, rhs),
+            AstKind::Not(operand) => {
+                let value = self.eval(operand)?;
+                return Ok((!value.to_boolean()?, vec![value]));
+            }
+            AstKind::EqAll(operands) => {
+                let values = operands
+                    .iter()
+                    .map(|operand| self.eval(operand))
+                    .collect::<Result<Vec<Value>, EvalError>>()?;
+                let holds = values.windows(2).all(|pair| pair[0] == pair[1]);
+                return Ok((holds, values));
+            }
+            _ => {
+                let value = self.eval(cond)?;
+                return Ok((value.to_boolean()?, vec![value]));
+            }
+        };
+        let (lv, rv) = (self.eval(lhs)?, self.eval(rhs)?);
+        let literal = |value: &Value| -> Box<AstNode> { AstKind::Literal(value.clone()).into() };
+        let (l, r) = (literal(&lv), literal(&rv));
+        let evaluated = match cond.kind {
+            AstKind::Eq(..) => AstNode::from(AstKind::Eq(l, r)),
+            AstKind::NotEq(..) => AstNode::from(AstKind::NotEq(l, r)),
+            AstKind::Lt(..) => AstNode::from(AstKind::Lt(l, r)),
+            AstKind::Lte(..) => AstNode::from(AstKind::Lte(l, r)),
+            AstKind::Gt(..) => AstNode::from(AstKind::Gt(l, r)),
+            _ => AstNode::from(AstKind::Gte(l, r)),
+        };
+        Ok((self.eval(&evaluated)?.to_boolean()?, vec![lv, rv]))
+    }
+
+    /// Calls a key function for each item, as `$sort` and `$groupBy` do.
+    fn eval_keys(&mut self, items: &[Value], key: &AstNode) -> Result<Vec<Value>, EvalError> {
+        let key = self.eval(key)?.to_function()?;
+        items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| self.call_callback(&key, index, vec![item.clone()]))
+            .collect()
+    }
+
+    /// Runs the first arm whose pattern matches `value`, with the variant's values
+    /// bound in a new scope.
+    fn eval_match(&mut self, value: Value, arms: &[MatchArm]) -> Result<Value, EvalError> {
+        // Patterns name variants by tag only, so arms written for another enum with
+        // the same tags would match. Every arm must name a variant of the value's enum.
+        if let Value::Variant(variant) = &value {
+            let variants = self.enums.get(&variant.enum_name);
+            for arm in arms {
+                if let Pattern::Variant(tag, _) = &arm.pattern {
+                    if !variants.is_some_and(|variants| variants.iter().any(|v| &v.name == tag)) {
+                        return Err(EvalError::UnknownVariant {
+                            enum_name: variant.enum_name.clone(),
+                            variant: tag.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        for arm in arms {
+            let bindings = match (&arm.pattern, &value) {
+                (Pattern::Wildcard, _) => &[][..],
+                (Pattern::Variant(tag, bindings), Value::Variant(variant))
+                    if tag == &variant.tag =>
+                {
+                    if !bindings.is_empty() && bindings.len() != variant.values.len() {
+                        return Err(EvalError::PatternArityMismatch {
+                            variant: tag.clone(),
+                            actual: bindings.len(),
+                            expected: variant.values.len(),
+                        });
+                    }
+                    &bindings[..]
+                }
+                _ => continue,
+            };
+            let scope = self.scope(self.env.clone());
+            if let Value::Variant(variant) = &value {
+                for (binding, value) in bindings.iter().zip(&variant.values) {
+                    if let Some(ident) = binding {
+                        scope.borrow_mut().define(ident.clone(), value.clone());
+                    }
+                }
+            }
+            return self.eval_in(&scope, &arm.body);
+        }
+        Err(EvalError::NoMatchingArm(value))
+    }
+
+    /// Evaluates `ast` with `env` as the current scope.
+    pub(crate) fn eval_in(
+        &mut self,
+        env: &Rc<RefCell<Environment>>,
+        ast: &AstNode,
+    ) -> Result<Value, EvalError> {
+        let caller_env = std::mem::replace(&mut self.env, env.clone());
+        let result = self.eval(ast);
+        self.env = caller_env;
+        result
+    }
+
+    /// Calls a collection callback with `args` followed by the element index, passing
+    /// only as many of them as the callback declares parameters for.
+    pub(crate) fn call_callback(
+        &mut self,
+        callback: &Function,
+        index: usize,
+        mut args: Vec<Value>,
+    ) -> Result<Value, EvalError> {
+        args.push(Value::Number(index as f64));
+        if !callback.params.iter().any(|p| p.rest) {
+            args.truncate(callback.params.len());
         }
+        self.call(callback, args)
+            .map_err(|e| EvalError::CallbackFailed {
+                index,
+                error: Box::new(e),
+            })
+    }
+
+    fn eval_comparison(
+        &mut self,
+        lhs: &AstNode,
+        rhs: &AstNode,
+        accept: fn(Ordering) -> bool,
+    ) -> Result<Value, EvalError> {
+        let lv = self.eval(lhs)?;
+        let rv = self.eval(rhs)?;
+        let ordering = match (&lv, &rv) {
+            (Value::Number(l), Value::Number(r)) => l.partial_cmp(r),
+            (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+            (Value::DateTime(l), Value::DateTime(r)) => Some(l.cmp(r)),
+            _ => return Err(EvalError::UnexpectedTypeForOperation),
+        };
+        Ok(Value::Boolean(ordering.is_some_and(accept)))
+    }
+}
+
+/// Frees the environments left in cycles, unless a value the host still holds
+/// refers to them.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.heap.collect(&[&self.env]);
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Value {
     pub fn to_number(&self) -> Result<f64, EvalError> {
         if let Value::Number(num) = self {
-            Ok(num.clone())
+            Ok(*num)
         } else {
             Err(EvalError::UnsupportedConversion)
         }
@@ -104,16 +848,237 @@ impl Value {
             Value::String(s) => Ok(s.clone()),
             Value::Null => Ok("null".into()),
             Value::Boolean(b) => Ok(b.to_string()),
+            Value::DateTime(dt) => Ok(datetime::format(dt)),
+            Value::Array(items) => Ok(format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Result<Vec<String>, EvalError>>()?
+                    .join(", ")
+            )),
+            Value::Object(entries) => Ok(format!(
+                "{{{}}}",
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok(format!("{}: {}", key, value.to_string()?)))
+                    .collect::<Result<Vec<String>, EvalError>>()?
+                    .join(", ")
+            )),
+            Value::Variant(variant) if variant.values.is_empty() => Ok(variant.tag.clone()),
+            Value::Variant(variant) => Ok(format!(
+                "{}({})",
+                variant.tag,
+                variant
+                    .values
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Result<Vec<String>, EvalError>>()?
+                    .join(", ")
+            )),
+            Value::Function(_) => Ok("<fn>".into()),
+            Value::Sequence(_) => Ok("<sequence>".into()),
+            Value::Struct(instance) => Ok(format!(
+                "{} {{ {} }}",
+                instance.name,
+                instance
+                    .fields
+                    .iter()
+                    .map(|(name, value)| Ok(format!("{}: {}", name, value.to_string()?)))
+                    .collect::<Result<Vec<String>, EvalError>>()?
+                    .join(", ")
+            )),
         }
     }
 
     pub fn to_boolean(&self) -> Result<bool, EvalError> {
         match self {
-            Value::Boolean(b) => Ok(b.clone()),
+            Value::Boolean(b) => Ok(*b),
             Value::Number(n) => Ok(*n != 0.0),
             Value::String(s) => Ok(!s.is_empty()),
             Value::Null => Ok(false),
+            Value::DateTime(_) => Ok(true),
+            Value::Array(items) => Ok(!items.is_empty()),
+            Value::Object(entries) => Ok(!entries.is_empty()),
+            Value::Function(_) => Ok(true),
+            Value::Sequence(_) => Ok(true),
+            Value::Struct(_) => Ok(true),
+            Value::Variant(_) => Ok(true),
+        }
+    }
+
+    pub fn into_array(self) -> Result<Vec<Value>, EvalError> {
+        if let Value::Array(items) = self {
+            Ok(items)
+        } else {
+            Err(EvalError::UnsupportedConversion)
+        }
+    }
+
+    /// Arrays are read as a sequence over their elements.
+    pub fn to_sequence(&self) -> Result<Sequence, EvalError> {
+        match self {
+            Value::Sequence(seq) => Ok(seq.clone()),
+            Value::Array(items) => Ok(Sequence::from_items(items.clone())),
+            _ => Err(EvalError::UnsupportedConversion),
+        }
+    }
+
+    pub fn into_struct(self) -> Result<StructValue, EvalError> {
+        if let Value::Struct(instance) = self {
+            Ok(instance)
+        } else {
+            Err(EvalError::UnexpectedTypeForOperation)
+        }
+    }
+
+    pub fn to_function(&self) -> Result<Rc<Function>, EvalError> {
+        if let Value::Function(function) = self {
+            Ok(function.clone())
+        } else {
+            Err(EvalError::NotCallable)
+        }
+    }
+
+    pub fn to_datetime(&self) -> Result<DateTime<FixedOffset>, EvalError> {
+        if let Value::DateTime(dt) = self {
+            Ok(*dt)
+        } else {
+            Err(EvalError::UnsupportedConversion)
+        }
+    }
+}
+
+/// The distinct items that pass `keep`, in order of first occurrence.
+fn unique<'a>(items: impl Iterator<Item = &'a Value>, keep: impl Fn(&Key) -> bool) -> Vec<Value> {
+    let mut seen = HashSet::new();
+    items
+        .filter(|item| keep(&Key(item)) && seen.insert(Key(item)))
+        .cloned()
+        .collect()
+}
+
+/// One step of a `$getIn`/`$setIn`/`$updateIn` path.
+#[derive(Debug)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+impl PathSegment {
+    /// The array index this segment addresses. JSON Pointer segments are keys, so a
+    /// key made of digits is an index too, and `-` is the position past the end.
+    fn index(&self, len: usize) -> Option<usize> {
+        match self {
+            PathSegment::Index(index) => Some(*index),
+            PathSegment::Key(key) if key == "-" => Some(len),
+            PathSegment::Key(key) if key == "0" || !key.starts_with('0') => key
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+                .then(|| key.parse().ok())?,
+            PathSegment::Key(_) => None,
+        }
+    }
+}
+
+/// A path is an array of keys and indices, or an RFC 6901 JSON Pointer string.
+fn parse_path(path: &Value) -> Result<Vec<PathSegment>, EvalError> {
+    match path {
+        Value::Array(segments) => segments
+            .iter()
+            .map(|segment| match segment {
+                Value::String(key) => Ok(PathSegment::Key(key.clone())),
+                Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => {
+                    Ok(PathSegment::Index(*n as usize))
+                }
+                other => Err(EvalError::InvalidPath(other.to_string()?)),
+            })
+            .collect(),
+        Value::String(pointer) if pointer.is_empty() => Ok(Vec::new()),
+        Value::String(pointer) => pointer
+            .strip_prefix('/')
+            .ok_or_else(|| EvalError::InvalidPath(pointer.clone()))?
+            .split('/')
+            .map(|token| match unescape_pointer_token(token) {
+                Some(key) => Ok(PathSegment::Key(key)),
+                None => Err(EvalError::InvalidPath(pointer.clone())),
+            })
+            .collect(),
+        _ => Err(EvalError::UnexpectedTypeForOperation),
+    }
+}
+
+/// Decodes `~1` to `/` and `~0` to `~`. Any other use of `~` is invalid.
+fn unescape_pointer_token(token: &str) -> Option<String> {
+    let mut key = String::with_capacity(token.len());
+    let mut chars = token.chars();
+    while let Some(c) = chars.next() {
+        key.push(match c {
+            '~' => match chars.next()? {
+                '0' => '~',
+                '1' => '/',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(key)
+}
+
+/// Renders a path as a JSON Pointer for error messages.
+fn render_path(path: &[PathSegment]) -> String {
+    path.iter()
+        .map(|segment| match segment {
+            PathSegment::Key(key) => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+            PathSegment::Index(index) => format!("/{}", index),
+        })
+        .collect()
+}
+
+fn get_in<'a>(value: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(value, |value, segment| match (value, segment) {
+            (Value::Object(entries), PathSegment::Key(key)) => entries.get(key),
+            (Value::Struct(instance), PathSegment::Key(key)) => instance.get(key),
+            (Value::Array(items), segment) => items.get(segment.index(items.len())?),
+            _ => None,
+        })
+}
+
+/// Returns `target` with the value at `path` replaced. The last segment may name a
+/// new object key or the position just past the end of an array, but not a field a
+/// struct doesn't declare; every other segment must exist.
+fn set_in(target: Value, path: &[PathSegment], value: Value) -> Option<Value> {
+    let Some((segment, rest)) = path.split_first() else {
+        return Some(value);
+    };
+    match (target, segment) {
+        (Value::Object(mut entries), PathSegment::Key(key)) => {
+            let child = match entries.remove(key) {
+                Some(child) => child,
+                None if rest.is_empty() => Value::Null,
+                None => return None,
+            };
+            entries.insert(key.clone(), set_in(child, rest, value)?);
+            Some(Value::Object(entries))
+        }
+        (Value::Struct(mut instance), PathSegment::Key(key)) => {
+            let (_, child) = instance.fields.iter_mut().find(|(name, _)| name == key)?;
+            let old = std::mem::replace(child, Value::Null);
+            *child = set_in(old, rest, value)?;
+            Some(Value::Struct(instance))
         }
+        (Value::Array(mut items), segment) => {
+            let index = segment.index(items.len())?;
+            if index == items.len() && rest.is_empty() {
+                items.push(value);
+            } else {
+                let child = std::mem::replace(items.get_mut(index)?, Value::Null);
+                items[index] = set_in(child, rest, value)?;
+            }
+            Some(Value::Array(items))
+        }
+        _ => None,
     }
 }
 
@@ -122,20 +1087,105 @@ pub enum EvalError {
     UnsupportedConversion,
     UndefinedIdent(Ident),
     UnexpectedTypeForOperation,
+    InvalidDateTime(String),
+    InvalidDateFormat(String),
+    InvalidDateArithmetic,
+    NotCallable,
+    ArgumentCountMismatch {
+        actual: usize,
+        expected: usize,
+    },
+    /// A call that leaves `param` without a value. `actual` counts positional and
+    /// named arguments.
+    NotEnoughArgs {
+        function: String,
+        param: String,
+        actual: usize,
+        expected_min: usize,
+    },
+    TooManyArgs {
+        function: String,
+        actual: usize,
+        expected_max: usize,
+    },
+    UnknownNamedArg {
+        function: String,
+        param: String,
+    },
+    /// A parameter given both positionally and by name.
+    DuplicateArg {
+        function: String,
+        param: String,
+    },
+    InvalidRangeStep,
+    YieldOutsideGenerator,
+    /// A sequence was advanced from inside its own callback or generator body.
+    SequenceAlreadyRunning,
+    /// An `$assert` whose condition didn't hold. `expression` is the condition as
+    /// written in JIR, and `operands` are the values it compared.
+    AssertionFailed {
+        message: String,
+        expression: String,
+        operands: Vec<Value>,
+    },
+    /// A malformed `$getIn`/`$setIn`/`$updateIn` path.
+    InvalidPath(String),
+    /// No value at the path, rendered as a JSON Pointer.
+    PathNotFound(String),
+    /// A `$query` string that doesn't parse; `position` is a byte offset into it.
+    InvalidQuery {
+        query: String,
+        position: usize,
+        expected: &'static str,
+    },
+    UndefinedStruct(String),
+    MissingField {
+        struct_name: String,
+        field: String,
+    },
+    UnknownField {
+        struct_name: String,
+        field: String,
+    },
+    UndefinedEnum(String),
+    UnknownVariant {
+        enum_name: String,
+        variant: String,
+    },
+    /// A `$match` pattern binds a different number of values than the variant has.
+    PatternArityMismatch {
+        variant: String,
+        actual: usize,
+        expected: usize,
+    },
+    NoMatchingArm(Value),
+    /// An error raised by a `$map`/`$filter`/... callback at element `index`.
+    CallbackFailed {
+        index: usize,
+        error: Box<EvalError>,
+    },
+    /// An `AstKind::Error` placeholder left by `JirParser::parse_all`.
+    UnparsedNode,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ast::Ident;
+    use crate::ast::{AstKind, DateComponent, DateUnit, Ident};
+    use crate::datetime::FixedClock;
+    use crate::jir::JirParser;
+
+    fn eval_json(i: &mut Interpreter, json: &str) -> Result<Value, EvalError> {
+        i.eval(&JirParser::parse_json(json).expect("valid JIR"))
+    }
 
     #[test]
     fn it_evaluate_addition() -> Result<(), EvalError> {
         let mut i = Interpreter::new();
-        let ast = AstNode::Add(
-            Box::new(AstNode::Literal(Value::Number(1.0))),
-            Box::new(AstNode::Literal(Value::Number(2.0))),
-        );
+        let ast = AstNode::from(AstKind::Add(
+            AstKind::Literal(Value::Number(1.0)).into(),
+            AstKind::Literal(Value::Number(2.0)).into(),
+        ));
         let result = i.eval(&ast);
         assert_eq!(result?.to_number()?, 3.0);
         Ok(())
@@ -144,10 +1194,10 @@ mod tests {
     #[test]
     fn it_evaluate_subtraction() -> Result<(), EvalError> {
         let mut i = Interpreter::new();
-        let ast = AstNode::Sub(
-            Box::new(AstNode::Literal(Value::Number(2.0))),
-            Box::new(AstNode::Literal(Value::Number(1.0))),
-        );
+        let ast = AstNode::from(AstKind::Sub(
+            AstKind::Literal(Value::Number(2.0)).into(),
+            AstKind::Literal(Value::Number(1.0)).into(),
+        ));
         let result = i.eval(&ast);
         assert_eq!(result?.to_number()?, 1.0);
         Ok(())
@@ -157,21 +1207,22 @@ mod tests {
     fn it_evaluate_binding_and_ident() -> Result<(), EvalError> {
         let mut i = Interpreter::new();
 
-        i.eval(&AstNode::Bind(
+        i.eval(&AstNode::from(AstKind::Bind(
             Ident("foo".into()),
-            Box::new(AstNode::Literal(Value::Number(1.0))),
-        ))?;
+            AstKind::Literal(Value::Number(1.0)).into(),
+        )))?;
 
         assert_eq!(
-            i.eval(&AstNode::Ident(Ident("foo".into())))?.to_number()?,
+            i.eval(&AstNode::from(AstKind::Ident(Ident("foo".into()))))?
+                .to_number()?,
             1.0
         );
 
         assert_eq!(
-            i.eval(&AstNode::Add(
-                Box::new(AstNode::Ident(Ident("foo".into()))),
-                Box::new(AstNode::Ident(Ident("foo".into())))
-            ))?
+            i.eval(&AstNode::from(AstKind::Add(
+                AstKind::Ident(Ident("foo".into())).into(),
+                AstKind::Ident(Ident("foo".into())).into()
+            )))?
             .to_number()?,
             2.0
         );
@@ -182,10 +1233,10 @@ mod tests {
     fn it_concatenate_strings() -> Result<(), EvalError> {
         let mut i = Interpreter::new();
         assert_eq!(
-            i.eval(&AstNode::Add(
-                Box::new(AstNode::Literal(Value::String("foo".into()))),
-                Box::new(AstNode::Literal(Value::String("bar".into()))),
-            ))?
+            i.eval(&AstNode::from(AstKind::Add(
+                AstKind::Literal(Value::String("foo".into())).into(),
+                AstKind::Literal(Value::String("bar".into())).into(),
+            )))?
             .to_string()?,
             "foobar".to_string()
         );
@@ -197,11 +1248,11 @@ mod tests {
     fn it_evaluates_if_expression() -> Result<(), EvalError> {
         let mut i = Interpreter::new();
         assert_eq!(
-            i.eval(&AstNode::If(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Some(Box::new(AstNode::Literal(Value::Number(2.0)))),
-            ))?,
+            i.eval(&AstNode::from(AstKind::If(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Number(1.0)).into(),
+                Some(AstKind::Literal(Value::Number(2.0)).into()),
+            )))?,
             Value::Number(1.0)
         );
 
@@ -212,11 +1263,11 @@ mod tests {
     fn it_evaluates_if_expression_as_null() -> Result<(), EvalError> {
         let mut i = Interpreter::new();
         assert_eq!(
-            i.eval(&AstNode::If(
-                Box::new(AstNode::Literal(Value::Boolean(false))),
-                Box::new(AstNode::Literal(Value::Number(1.0))),
+            i.eval(&AstNode::from(AstKind::If(
+                AstKind::Literal(Value::Boolean(false)).into(),
+                AstKind::Literal(Value::Number(1.0)).into(),
                 None,
-            ))?,
+            )))?,
             Value::Null,
         );
 
@@ -224,6 +1275,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn it_evaluates_non_boolean_types_as_boolean() -> Result<(), EvalError> {
         assert_eq!(Value::Number(0.0).to_boolean()?, false);
         assert_eq!(Value::Number(1.0).to_boolean()?, true);
@@ -242,25 +1294,25 @@ mod tests {
         let mut i = Interpreter::new();
 
         assert_eq!(
-            i.eval(&AstNode::And(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Boolean(false)))
-            ))?,
+            i.eval(&AstNode::from(AstKind::And(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Boolean(false)).into()
+            )))?,
             Value::Boolean(false)
         );
 
         assert_eq!(
-            i.eval(&AstNode::Or(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Boolean(false)))
-            ))?,
+            i.eval(&AstNode::from(AstKind::Or(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Boolean(false)).into()
+            )))?,
             Value::Boolean(true)
         );
 
         assert_eq!(
-            i.eval(&AstNode::Not(Box::new(AstNode::Literal(Value::Boolean(
-                true
-            ))),))?,
+            i.eval(&AstNode::from(AstKind::Not(
+                AstKind::Literal(Value::Boolean(true)).into(),
+            )))?,
             Value::Boolean(false)
         );
 
@@ -272,18 +1324,18 @@ mod tests {
         let mut i = Interpreter::new();
 
         assert_eq!(
-            i.eval(&AstNode::Eq(
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Box::new(AstNode::Literal(Value::Number(1.0)))
-            ))?,
+            i.eval(&AstNode::from(AstKind::Eq(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::Number(1.0)).into()
+            )))?,
             Value::Boolean(true)
         );
 
         assert_eq!(
-            i.eval(&AstNode::NotEq(
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Box::new(AstNode::Literal(Value::Number(1.0)))
-            ))?,
+            i.eval(&AstNode::from(AstKind::NotEq(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::Number(1.0)).into()
+            )))?,
             Value::Boolean(false)
         );
 
@@ -294,30 +1346,1016 @@ mod tests {
     fn it_evaluates_while_loop() -> Result<(), EvalError> {
         let mut i = Interpreter::new();
 
-        i.eval(&AstNode::Bind(
+        i.eval(&AstNode::from(AstKind::Bind(
             Ident("foo".into()),
-            Box::new(AstNode::Literal(Value::Number(5.0))),
-        ))?;
+            AstKind::Literal(Value::Number(5.0)).into(),
+        )))?;
 
-        i.eval(&AstNode::While(
-            Box::new(AstNode::NotEq(
-                Box::new(AstNode::Ident(Ident("foo".into()))),
-                Box::new(AstNode::Literal(Value::Number(0.0))),
-            )),
-            Box::new(AstNode::Bind(
+        i.eval(&AstNode::from(AstKind::While(
+            AstKind::NotEq(
+                AstKind::Ident(Ident("foo".into())).into(),
+                AstKind::Literal(Value::Number(0.0)).into(),
+            )
+            .into(),
+            AstKind::Bind(
                 Ident("foo".into()),
-                Box::new(AstNode::Sub(
-                    Box::new(AstNode::Ident(Ident("foo".into()))),
-                    Box::new(AstNode::Literal(Value::Number(1.0))),
-                )),
-            )),
-        ))?;
+                AstKind::Sub(
+                    AstKind::Ident(Ident("foo".into())).into(),
+                    AstKind::Literal(Value::Number(1.0)).into(),
+                )
+                .into(),
+            )
+            .into(),
+        )))?;
 
         assert_eq!(
-            i.eval(&AstNode::Ident(Ident("foo".into())))?,
+            i.eval(&AstNode::from(AstKind::Ident(Ident("foo".into()))))?,
             Value::Number(0.0)
         );
 
         Ok(())
     }
+
+    #[test]
+    fn it_evaluates_ordering_comparison() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        assert_eq!(
+            i.eval(&AstNode::from(AstKind::Lt(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::Number(2.0)).into()
+            )))?,
+            Value::Boolean(true)
+        );
+
+        assert_eq!(
+            i.eval(&AstNode::from(AstKind::Gte(
+                AstKind::Literal(Value::String("a".into())).into(),
+                AstKind::Literal(Value::String("b".into())).into()
+            )))?,
+            Value::Boolean(false)
+        );
+
+        assert!(matches!(
+            i.eval(&AstNode::from(AstKind::Gt(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::String("b".into())).into()
+            ))),
+            Err(EvalError::UnexpectedTypeForOperation)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_date_time_operations() -> Result<(), EvalError> {
+        let now = datetime::parse("2021-06-01T09:00:00+09:00")?;
+        let mut i = Interpreter::with_clock(Box::new(FixedClock(now)));
+
+        i.eval(&AstNode::from(AstKind::Bind(
+            Ident("deadline".into()),
+            AstKind::DateAdd(
+                AstKind::Now.into(),
+                AstKind::Literal(Value::Number(2.0)).into(),
+                DateUnit::Days,
+            )
+            .into(),
+        )))?;
+
+        assert_eq!(
+            i.eval(&AstNode::from(AstKind::FormatDateTime(
+                AstKind::Ident(Ident("deadline".into())).into(),
+                None,
+            )))?,
+            Value::String("2021-06-03T09:00:00+09:00".into())
+        );
+
+        assert_eq!(
+            i.eval(&AstNode::from(AstKind::DateDiff(
+                AstKind::Ident(Ident("deadline".into())).into(),
+                AstKind::Now.into(),
+                DateUnit::Hours,
+            )))?,
+            Value::Number(48.0)
+        );
+
+        assert_eq!(
+            i.eval(&AstNode::from(AstKind::DatePart(
+                AstKind::Ident(Ident("deadline".into())).into(),
+                DateComponent::Weekday,
+            )))?,
+            Value::Number(4.0)
+        );
+
+        // The same instant written in another offset is equal.
+        assert_eq!(
+            i.eval(&AstNode::from(AstKind::Eq(
+                AstKind::Now.into(),
+                AstKind::DateTime(
+                    AstKind::Literal(Value::String("2021-06-01T00:00:00Z".into())).into()
+                )
+                .into(),
+            )))?,
+            Value::Boolean(true)
+        );
+
+        assert_eq!(
+            i.eval(&AstNode::from(AstKind::Lt(
+                AstKind::Now.into(),
+                AstKind::Ident(Ident("deadline".into())).into(),
+            )))?,
+            Value::Boolean(true)
+        );
+
+        assert!(matches!(
+            eval_json(&mut i, r#"["$dateAdd", ["$now"], -1e300, "days"]"#),
+            Err(EvalError::InvalidDateArithmetic)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_function_call_with_closure() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        eval_json(
+            &mut i,
+            r#"["$bind", "adder", ["$fn", ["n"], ["$fn", ["x"], ["$add", ["$ref", "x"], ["$ref", "n"]]]]]"#,
+        )?;
+        eval_json(
+            &mut i,
+            r#"["$bind", "addTwo", ["$call", ["$ref", "adder"], 2]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(&mut i, r#"["$call", ["$ref", "addTwo"], 40]"#)?,
+            Value::Number(42.0)
+        );
+        assert!(matches!(
+            eval_json(&mut i, r#"["$ref", "n"]"#),
+            Err(EvalError::UndefinedIdent(_))
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$call", ["$ref", "addTwo"]]"#),
+            Err(EvalError::NotEnoughArgs {
+                function,
+                param,
+                actual: 0,
+                expected_min: 1
+            }) if function == "<fn>" && param == "x"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_higher_order_operations() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        eval_json(
+            &mut i,
+            r#"["$bind", "isBig", ["$fn", ["x"], ["$gt", ["$ref", "x"], 2]]]"#,
+        )?;
+        eval_json(&mut i, r#"["$bind", "xs", ["$array", 1, 2, 3, 4]]"#)?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$map", ["$ref", "xs"], ["$fn", ["x", "i"], ["$sub", ["$ref", "x"], ["$ref", "i"]]]]"#
+            )?,
+            Value::Array(vec![Value::Number(1.0); 4])
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$filter", ["$ref", "xs"], ["$ref", "isBig"]]"#)?,
+            Value::Array(vec![Value::Number(3.0), Value::Number(4.0)])
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$reduce", ["$ref", "xs"], ["$fn", ["acc", "x"], ["$add", ["$ref", "acc"], ["$ref", "x"]]], 0]"#
+            )?,
+            Value::Number(10.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$some", ["$ref", "xs"], ["$ref", "isBig"]]"#)?,
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$every", ["$ref", "xs"], ["$ref", "isBig"]]"#)?,
+            Value::Boolean(false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_index_of_a_failing_callback() {
+        let mut i = Interpreter::new();
+
+        let result = eval_json(
+            &mut i,
+            r#"["$map", ["$array", 1, 2, "three"], ["$fn", ["x"], ["$sub", ["$ref", "x"], 1]]]"#,
+        );
+
+        match result {
+            Err(EvalError::CallbackFailed { index, error }) => {
+                assert_eq!(index, 2);
+                assert!(matches!(*error, EvalError::UnsupportedConversion));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_evaluates_lazy_sequences() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        eval_json(&mut i, r#"["$bind", "calls", 0]"#)?;
+        eval_json(
+            &mut i,
+            r#"["$bind", "doubled", ["$map", ["$range", 1], ["$fn", ["x"], ["$do",
+                ["$bind", "calls", ["$add", ["$ref", "calls"], 1]],
+                ["$sub", ["$ref", "x"], ["$sub", 0, ["$ref", "x"]]]
+            ]]]]"#,
+        )?;
+        assert_eq!(
+            eval_json(&mut i, r#"["$ref", "calls"]"#)?,
+            Value::Number(0.0)
+        );
+
+        assert_eq!(
+            eval_json(&mut i, r#"["$collect", ["$take", ["$ref", "doubled"], 3]]"#)?,
+            Value::Array(vec![
+                Value::Number(2.0),
+                Value::Number(4.0),
+                Value::Number(6.0)
+            ])
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$ref", "calls"]"#)?,
+            Value::Number(3.0)
+        );
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$collect", ["$takeWhile", ["$range", 10, 0, -3], ["$fn", ["x"], ["$gt", ["$ref", "x"], 2]]]]"#
+            )?,
+            Value::Array(vec![
+                Value::Number(10.0),
+                Value::Number(7.0),
+                Value::Number(4.0)
+            ])
+        );
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$some", ["$range", 0], ["$fn", ["x"], ["$eq", ["$ref", "x"], 100]]]"#
+            )?,
+            Value::Boolean(true)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_generator() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        eval_json(
+            &mut i,
+            r#"["$bind", "countdown", ["$gen", ["n"], ["$do",
+                ["$while", ["$gt", ["$ref", "n"], 0], ["$do",
+                    ["$yield", ["$ref", "n"]],
+                    ["$bind", "n", ["$sub", ["$ref", "n"], 1]]
+                ]],
+                ["$yield", "liftoff"]
+            ]]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$collect", ["$call", ["$ref", "countdown"], 3]]"#
+            )?,
+            Value::Array(vec![
+                Value::Number(3.0),
+                Value::Number(2.0),
+                Value::Number(1.0),
+                Value::String("liftoff".into())
+            ])
+        );
+
+        eval_json(
+            &mut i,
+            r#"["$bind", "naturals", ["$gen", [], ["$do",
+                ["$bind", "k", 0],
+                ["$while", true, ["$do", ["$yield", ["$ref", "k"]], ["$bind", "k", ["$add", ["$ref", "k"], 1]]]]
+            ]]]"#,
+        )?;
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$collect", ["$take", ["$call", ["$ref", "naturals"]], 2]]"#
+            )?,
+            Value::Array(vec![Value::Number(0.0), Value::Number(1.0)])
+        );
+
+        // The parser rejects this, but a host may build the tree itself.
+        let stray = AstNode::from(AstKind::Yield(AstKind::Literal(Value::Null).into()));
+        assert!(matches!(
+            i.eval(&stray),
+            Err(EvalError::YieldOutsideGenerator)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_frees_closures_bound_in_their_own_scope() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$let", "counter", ["$fn", [], ["$do",
+                ["$let", "n", 0],
+                ["$let", "inc", ["$fn", [], ["$do",
+                    ["$bind", "n", ["$add", ["$ref", "n"], 1]],
+                    ["$ref", "n"]
+                ]]],
+                ["$ref", "inc"]
+            ]]]"#,
+        )?;
+        let inc = eval_json(&mut i, r#"["$call", ["$ref", "counter"]]"#)?.to_function()?;
+        let scope = Rc::downgrade(&inc.env);
+        let global = Rc::downgrade(&i.env);
+
+        // Held by the host, so kept however often garbage is collected.
+        i.collect_garbage();
+        assert_eq!(i.call(&inc, vec![])?, Value::Number(1.0));
+        assert_eq!(i.call(&inc, vec![])?, Value::Number(2.0));
+
+        drop(inc);
+        assert!(scope.upgrade().is_some(), "`inc` is bound in its own scope");
+        i.collect_garbage();
+        assert!(scope.upgrade().is_none());
+
+        for _ in 0..3000 {
+            eval_json(&mut i, r#"["$call", ["$call", ["$ref", "counter"]]]"#)?;
+        }
+        drop(i);
+        assert!(global.upgrade().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_structs() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        eval_json(
+            &mut i,
+            r#"["$struct", "Order", ["id", ["qty", "number", ["$add", 1, 1]]]]"#,
+        )?;
+        eval_json(
+            &mut i,
+            r#"["$bind", "o", ["$new", "Order", {"id": "A-1"}]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(&mut i, r#"["$field", ["$ref", "o"], "qty"]"#)?,
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$ref", "o"]"#)?.to_string()?,
+            "Order { id: A-1, qty: 2 }"
+        );
+
+        let updated = eval_json(&mut i, r#"["$with", ["$ref", "o"], {"qty": 5}]"#)?;
+        assert_eq!(
+            eval_json(&mut i, r#"["$field", ["$ref", "o"], "qty"]"#)?,
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            updated,
+            eval_json(&mut i, r#"["$new", "Order", {"qty": 5, "id": "A-1"}]"#)?
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$eq", ["$ref", "o"], ["$new", "Order", {"id": "A-1", "qty": 2}]]"#
+            )?,
+            Value::Boolean(true)
+        );
+
+        // Field values run in source order, also after a round trip through JIR.
+        let log = r#"["$new", "Order", {
+            "qty": ["$do", ["$bind", "log", ["$add", ["$ref", "log"], "q"]], 1],
+            "id": ["$do", ["$bind", "log", ["$add", ["$ref", "log"], "i"]], "A-2"]
+        }]"#;
+        let ast = JirParser::parse_json(log).expect("valid JIR");
+        let encoded = crate::binary::encode(&ast);
+        for ast in [
+            ast,
+            crate::binary::decode(&encoded).expect("valid encoding"),
+        ] {
+            eval_json(&mut i, r#"["$bind", "log", ""]"#)?;
+            i.eval(&ast)?;
+            assert_eq!(
+                eval_json(&mut i, r#"["$ref", "log"]"#)?,
+                Value::String("qi".into())
+            );
+        }
+
+        // Defaults see the bindings where the struct was declared, not where `$new` is.
+        eval_json(
+            &mut i,
+            r#"["$do",
+                ["$let", "rate", 2],
+                ["$struct", "Fee", [["amount", "number", ["$ref", "rate"]]]],
+                ["$let", "scoped", ["$fn", [], ["$do",
+                    ["$let", "local", "inner"],
+                    ["$struct", "Scoped", [["v", "string", ["$ref", "local"]]]]
+                ]]]
+            ]"#,
+        )?;
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$call", ["$fn", ["rate"], ["$field", ["$new", "Fee", {}], "amount"]], 10]"#
+            )?,
+            Value::Number(2.0)
+        );
+        eval_json(&mut i, r#"["$call", ["$ref", "scoped"]]"#)?;
+        assert_eq!(
+            eval_json(&mut i, r#"["$field", ["$new", "Scoped", {}], "v"]"#)?,
+            Value::String("inner".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_validates_struct_fields() {
+        let mut i = Interpreter::new();
+        eval_json(&mut i, r#"["$struct", "User", ["name", "email"]]"#).unwrap();
+
+        assert!(matches!(
+            eval_json(&mut i, r#"["$new", "User", {"name": "kim"}]"#),
+            Err(EvalError::MissingField { field, .. }) if field == "email"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$new", "User", {"name": "kim", "email": "k@x", "age": 3}]"#),
+            Err(EvalError::UnknownField { field, .. }) if field == "age"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$new", "Admin", {}]"#),
+            Err(EvalError::UndefinedStruct(_))
+        ));
+    }
+
+    #[test]
+    fn it_evaluates_enums() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$enum", "Decision", ["Approved", ["Rejected", "reason"]]]"#,
+        )?;
+        eval_json(
+            &mut i,
+            r#"["$bind", "describe", ["$fn", ["d"], ["$match", ["$ref", "d"],
+                ["Approved", "ok"],
+                [["Rejected", "why"], ["$add", "no: ", ["$ref", "why"]]]
+            ]]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$call", ["$ref", "describe"], ["$variant", "Decision", "Rejected", "late"]]"#
+            )?,
+            Value::String("no: late".into())
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$call", ["$ref", "describe"], ["$variant", "Decision", "Approved"]]"#
+            )?,
+            Value::String("ok".into())
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$tag", ["$variant", "Decision", "Rejected", 1]]"#
+            )?,
+            Value::String("Rejected".into())
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$eq", ["$variant", "Decision", "Rejected", 1], ["$variant", "Decision", "Rejected", 1]]"#
+            )?,
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$eq", ["$variant", "Decision", "Rejected", 1], ["$variant", "Decision", "Rejected", 2]]"#
+            )?,
+            Value::Boolean(false)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$variant", "Decision", "Rejected", "late"]"#)?.to_string()?,
+            "Rejected(late)"
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$match", ["$variant", "Decision", "Rejected", 1], ["Approved", 1], ["_", 2]]"#
+            )?,
+            Value::Number(2.0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_enum_errors() {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$enum", "Decision", ["Approved", ["Rejected", "reason"]]]"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            eval_json(&mut i, r#"["$variant", "Decision", "Pending"]"#),
+            Err(EvalError::UnknownVariant { variant, .. }) if variant == "Pending"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$variant", "Decision", "Rejected"]"#),
+            Err(EvalError::ArgumentCountMismatch {
+                actual: 0,
+                expected: 1
+            })
+        ));
+        assert!(matches!(
+            eval_json(
+                &mut i,
+                r#"["$match", ["$variant", "Decision", "Approved"], ["Rejected", 1]]"#
+            ),
+            Err(EvalError::NoMatchingArm(_))
+        ));
+        eval_json(&mut i, r#"["$enum", "Appeal", [["Rejected", "reason"]]]"#).unwrap();
+        assert!(matches!(
+            eval_json(
+                &mut i,
+                r#"["$match", ["$variant", "Appeal", "Rejected", 1], ["Approved", 1], ["Rejected", 2]]"#
+            ),
+            Err(EvalError::UnknownVariant { enum_name, variant })
+                if enum_name == "Appeal" && variant == "Approved"
+        ));
+        assert!(matches!(
+            eval_json(
+                &mut i,
+                r#"["$match", ["$variant", "Decision", "Rejected", 1], [["Rejected", "a", "b"], 1]]"#
+            ),
+            Err(EvalError::PatternArityMismatch {
+                actual: 2,
+                expected: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_evaluates_quoted_data() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(&mut i, r#"["$bind", "x", 2]"#)?;
+
+        assert_eq!(
+            eval_json(&mut i, r#"["$quote", ["$add", 1, ["$ref", "x"]]]"#)?,
+            Value::Array(vec![
+                Value::String("$add".into()),
+                Value::Number(1.0),
+                Value::Array(vec![
+                    Value::String("$ref".into()),
+                    Value::String("x".into())
+                ]),
+            ])
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$quasiquote", {"op": "$add", "args": [1, ["$unquote", ["$ref", "x"]]]}]"#
+            )?
+            .to_string()?,
+            "{args: [1, 2], op: $add}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_and_updates_nested_paths() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$bind", "doc", ["$quote", {"orders": [{"id": 1, "tags": ["a/b"]}], "m~n": 2}]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$getIn", ["$ref", "doc"], ["$quote", ["orders", 0, "id"]]]"#
+            )?,
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$getIn", ["$ref", "doc"], "/orders/0/tags/0"]"#)?,
+            Value::String("a/b".into())
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$getIn", ["$ref", "doc"], "/m~0n"]"#)?,
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$getIn", ["$ref", "doc"], "/orders/1/id"]"#)?,
+            Value::Null
+        );
+        assert!(matches!(
+            eval_json(&mut i, r#"["$getIn", ["$ref", "doc"], "/orders/1/id", true]"#),
+            Err(EvalError::PathNotFound(path)) if path == "/orders/1/id"
+        ));
+
+        let updated = eval_json(
+            &mut i,
+            r#"["$updateIn",
+                ["$setIn", ["$ref", "doc"], "/orders/0/tags/-", "c"],
+                ["$quote", ["orders", 0, "id"]],
+                ["$fn", ["id"], ["$add", ["$ref", "id"], 10]]]"#,
+        )?;
+        assert_eq!(
+            updated.to_string()?,
+            "{m~n: 2, orders: [{id: 11, tags: [a/b, c]}]}"
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$ref", "doc"]"#)?.to_string()?,
+            "{m~n: 2, orders: [{id: 1, tags: [a/b]}]}"
+        );
+
+        assert!(matches!(
+            eval_json(&mut i, r#"["$setIn", ["$ref", "doc"], "/missing/id", 1]"#),
+            Err(EvalError::PathNotFound(_))
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$getIn", ["$ref", "doc"], "orders"]"#),
+            Err(EvalError::InvalidPath(_))
+        ));
+
+        eval_json(&mut i, r#"["$struct", "Order", ["id", "tags"]]"#)?;
+        eval_json(
+            &mut i,
+            r#"["$bind", "order", ["$new", "Order", {"id": 1, "tags": ["$quote", ["a"]]}]]"#,
+        )?;
+        assert_eq!(
+            eval_json(&mut i, r#"["$getIn", ["$ref", "order"], "/tags/0"]"#)?,
+            Value::String("a".into())
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$updateIn", ["$setIn", ["$ref", "order"], "/tags/-", "b"], "/id",
+                    ["$fn", ["id"], ["$add", ["$ref", "id"], 1]]]"#
+            )?
+            .to_string()?,
+            "Order { id: 2, tags: [a, b] }"
+        );
+        assert!(matches!(
+            eval_json(&mut i, r#"["$setIn", ["$ref", "order"], "/total", 3]"#),
+            Err(EvalError::PathNotFound(path)) if path == "/total"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_queries() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$bind", "doc", ["$quote", {"orders": [
+                {"id": 1, "lines": [{"sku": "a", "qty": 2}]},
+                {"id": 2, "lines": [{"sku": "b", "qty": 0}, {"sku": "c", "qty": 4}]}
+            ]}]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$query", ["$ref", "doc"], ".orders[*].lines[?(@.qty > 0)].sku"]"#
+            )?
+            .to_string()?,
+            "[a, c]"
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$query", ["$ref", "doc"], "..sku"]"#)?.to_string()?,
+            "[a, b, c]"
+        );
+        assert!(matches!(
+            eval_json(&mut i, r#"["$query", ["$ref", "doc"], ".orders["]"#),
+            Err(EvalError::InvalidQuery { .. })
+        ));
+
+        // Literal queries are parsed once, computed ones every time.
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$map", ["$array", 1, 2], ["$fn", ["n"], ["$query", ["$ref", "doc"], "..id"]]]"#
+            )?
+            .to_string()?,
+            "[[1, 2], [1, 2]]"
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$query", ["$ref", "doc"], ["$add", "..", "qty"]]"#
+            )?
+            .to_string()?,
+            "[2, 0, 4]"
+        );
+        assert_eq!(i.queries.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_sorts_groups_and_combines_collections() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$bind", "orders", ["$quote", [
+                {"id": 1, "region": "eu", "total": 30},
+                {"id": 2, "region": "us", "total": 10},
+                {"id": 3, "region": "eu", "total": 10}
+            ]]]"#,
+        )?;
+        eval_json(
+            &mut i,
+            r#"["$bind", "ids", ["$fn", ["xs"], ["$map", ["$ref", "xs"], ["$fn", ["o"], ["$getIn", ["$ref", "o"], "/id"]]]]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$call", ["$ref", "ids"], ["$sort", ["$ref", "orders"], ["$fn", ["o"], ["$getIn", ["$ref", "o"], "/total"]]]]"#
+            )?
+            .to_string()?,
+            "[2, 3, 1]"
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$sort", ["$quote", ["b", 2, null, "a", 1, true]]]"#
+            )?
+            .to_string()?,
+            "[null, true, 1, 2, a, b]"
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$groupBy", ["$ref", "orders"], ["$fn", ["o"], ["$getIn", ["$ref", "o"], "/region"]]]"#
+            )?
+            .to_string()?,
+            "[[eu, [{id: 1, region: eu, total: 30}, {id: 3, region: eu, total: 10}]], \
+             [us, [{id: 2, region: us, total: 10}]]]"
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$unique", ["$quote", [3, 1, 3, [1], 1, [1]]]]"#)?.to_string()?,
+            "[3, 1, [1]]"
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$union", ["$quote", [1, 2]], ["$quote", [2, 3]]]"#
+            )?
+            .to_string()?,
+            "[1, 2, 3]"
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$intersect", ["$quote", [1, 2, 2, 3]], ["$quote", [3, 2]]]"#
+            )?
+            .to_string()?,
+            "[2, 3]"
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$difference", ["$quote", [1, 2, 3]], ["$quote", [2]]]"#
+            )?
+            .to_string()?,
+            "[1, 3]"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_null_safe_forms() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$bind", "config", ["$quote", {"db": {"port": 5432}}]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$coalesce", ["$ref", "unset"], null, 3, ["$ref", "boom"]]"#
+            )?,
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$safeGet", ["$ref", "config"], "db", "port"]"#)?,
+            Value::Number(5432.0)
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$coalesce", ["$safeGet", ["$ref", "config"], "cache", "ttl"], 60]"#
+            )?,
+            Value::Number(60.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$safeGet", null, "db"]"#)?,
+            Value::Null
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$defined", "config"]"#)?,
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$defined", "unset"]"#)?,
+            Value::Boolean(false)
+        );
+        assert!(matches!(
+            eval_json(
+                &mut i,
+                r#"["$coalesce", null, ["$add", ["$ref", "unset"], 1]]"#
+            ),
+            Err(EvalError::UndefinedIdent(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_failed_assertions() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(&mut i, r#"["$bind", "replicas", 2]"#)?;
+
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$assert", ["$gte", ["$ref", "replicas"], 1], "ok"]"#
+            )?,
+            Value::Null
+        );
+        match eval_json(
+            &mut i,
+            r#"["$assert", ["$gte", ["$ref", "replicas"], 3], "need at least 3 replicas"]"#,
+        ) {
+            Err(EvalError::AssertionFailed {
+                message,
+                expression,
+                operands,
+            }) => {
+                assert_eq!(message, "need at least 3 replicas");
+                assert_eq!(expression, r#"["$gte",["$ref","replicas"],3]"#);
+                assert_eq!(operands, vec![Value::Number(2.0), Value::Number(3.0)]);
+            }
+            other => panic!("expected an assertion failure, got {:?}", other),
+        }
+        assert!(matches!(
+            eval_json(&mut i, r#"["$assert", ["$not", ["$ref", "replicas"]]]"#),
+            Err(EvalError::AssertionFailed { message, operands, .. })
+                if message == "assertion failed" && operands == vec![Value::Number(2.0)]
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$assert", ["$eq", 2, ["$ref", "replicas"], 3, 2]]"#),
+            Err(EvalError::AssertionFailed { operands, .. })
+                if operands == [2.0, 2.0, 3.0, 2.0].map(Value::Number)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_variadic_operators() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        assert_eq!(
+            eval_json(&mut i, r#"["$add", 1, 2, 3, 4, 5]"#)?,
+            Value::Number(15.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$add", "a", "b", "c"]"#)?,
+            Value::String("abc".into())
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$sub", 10, 1, 2]"#)?,
+            Value::Number(7.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$and", true, 1, "x"]"#)?,
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$or", false, 0, ""]"#)?,
+            Value::Boolean(false)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$eq", 2, ["$add", 1, 1], 2]"#)?,
+            Value::Boolean(true)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$eq", 2, 2, 3]"#)?,
+            Value::Boolean(false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_binds_default_rest_and_named_arguments() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        eval_json(
+            &mut i,
+            r#"["$bind", "greet", ["$fn", ["name", ["greeting", "string", "hello"], ["punct", "string", "!"]],
+                ["$add", ["$ref", "greeting"], " ", ["$ref", "name"], ["$ref", "punct"]]]]"#,
+        )?;
+        eval_json(
+            &mut i,
+            r#"["$bind", "sum", ["$fn", ["first", "...rest"],
+                ["$reduce", ["$ref", "rest"], ["$fn", ["acc", "x"], ["$add", ["$ref", "acc"], ["$ref", "x"]]], ["$ref", "first"]]]]"#,
+        )?;
+
+        assert_eq!(
+            eval_json(&mut i, r#"["$call", ["$ref", "greet"], "kim"]"#)?,
+            Value::String("hello kim!".into())
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$call", ["$ref", "greet"], "kim", {"punct": "?"}]"#
+            )?,
+            Value::String("hello kim?".into())
+        );
+        assert_eq!(
+            eval_json(
+                &mut i,
+                r#"["$call", ["$ref", "greet"], {"greeting": "hi", "name": "lee"}]"#
+            )?,
+            Value::String("hi lee!".into())
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$call", ["$ref", "sum"], 1, 2, 3, 4]"#)?,
+            Value::Number(10.0)
+        );
+        assert_eq!(
+            eval_json(&mut i, r#"["$call", ["$ref", "sum"], 1]"#)?,
+            Value::Number(1.0)
+        );
+
+        assert!(matches!(
+            eval_json(&mut i, r#"["$call", ["$ref", "greet"], {"greeting": "hi"}]"#),
+            Err(EvalError::NotEnoughArgs { function, param, actual: 1, expected_min: 1 })
+                if function == "greet" && param == "name"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$call", ["$ref", "greet"], "a", "b", "c", "d"]"#),
+            Err(EvalError::TooManyArgs { function, actual: 4, expected_max: 3 })
+                if function == "greet"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$call", ["$ref", "greet"], "a", {"nmae": "b"}]"#),
+            Err(EvalError::UnknownNamedArg { param, .. }) if param == "nmae"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$call", ["$ref", "greet"], "a", {"name": "b"}]"#),
+            Err(EvalError::DuplicateArg { param, .. }) if param == "name"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$call", ["$ref", "greet"], {"name": "a", "name": "b"}]"#),
+            Err(EvalError::DuplicateArg { param, .. }) if param == "name"
+        ));
+        assert!(matches!(
+            eval_json(&mut i, r#"["$call", ["$fn", ["x"], 1]]"#),
+            Err(EvalError::NotEnoughArgs { function, .. }) if function == "<fn>"
+        ));
+
+        Ok(())
+    }
 }
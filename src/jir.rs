@@ -1,154 +1,1061 @@
-use serde_json::{Error, Value as JsonValue};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
 
-use crate::ast::{AstNode, Ident};
+use crate::ast::{
+    AstKind, AstNode, DateComponent, DateUnit, Field, Ident, MatchArm, Param, Pattern, Variant,
+};
+use crate::forms::{Form, FormRegistry};
+use crate::json::{self, Json, JsonKind};
+use crate::span::{Located, Span};
+use crate::typecheck::Type;
 use crate::value::Value;
+use crate::{binary, sexpr, yaml};
 
-pub struct JirParser {}
+type ParseResult<T> = Result<T, Located<ParseError>>;
+
+pub struct JirParser {
+    forms: Rc<FormRegistry>,
+    /// Whether a failed expression becomes an `AstKind::Error` placeholder, with its
+    /// error collected in `diagnostics`, instead of failing the whole parse.
+    recover: bool,
+    diagnostics: Vec<Diagnostic>,
+    /// Whether the expression being parsed is inside a `$gen` body, and whether it
+    /// is in statement position there, where a `$yield` can suspend the generator.
+    in_generator: bool,
+    statement: bool,
+    /// The position `parse_expression` gives the next expression it parses.
+    next_statement: bool,
+    /// Set on every span of what is read, see `set_source`.
+    source: usize,
+}
+
+thread_local! {
+    static BUILTIN_FORMS: Rc<FormRegistry> = Rc::new(FormRegistry::builtin());
+}
 
 impl JirParser {
+    /// A parser that knows the given forms, rather than the built-in ones.
+    pub fn new(forms: Rc<FormRegistry>) -> Self {
+        Self {
+            forms,
+            recover: false,
+            diagnostics: Vec::new(),
+            in_generator: false,
+            statement: false,
+            next_statement: false,
+            source: 0,
+        }
+    }
+
+    /// Numbers the source read next, for callers that keep several: the spans of its
+    /// nodes and errors get `source` as their `Span::source`.
+    pub fn set_source(&mut self, source: usize) {
+        self.source = source;
+    }
+
     pub fn parse_json(json_str: &str) -> Result<AstNode, ParseError> {
-        let value: serde_json::Value = serde_json::from_str(json_str)?;
+        Self::parse(json_str).map_err(|e| e.error)
+    }
 
-        Self::parse_expression(&value)
+    /// Like `parse_json`, but errors carry the span of the JSON they were raised at.
+    pub fn parse(json_str: &str) -> ParseResult<AstNode> {
+        Self::default().read(json_str)
     }
 
-    fn parse_expression(json: &JsonValue) -> Result<AstNode, ParseError> {
-        match json {
-            JsonValue::Array(values) => Self::parse_compound(values),
-            JsonValue::Number(num) => {
-                if let Some(n) = num.as_f64() {
-                    Ok(AstNode::Literal(Value::Number(n)))
-                } else {
-                    Err(ParseError::UnsupportedNumberLiteral(num.to_string()))
-                }
+    /// Like `parse`, for programs written as S-expressions.
+    pub fn parse_sexpr(source: &str) -> ParseResult<AstNode> {
+        Self::parse_as(source, Format::SExpr)
+    }
+
+    /// Like `parse`, for programs written in another format. Spans, and so error
+    /// positions, refer to the original source.
+    pub fn parse_as(source: &str, format: Format) -> ParseResult<AstNode> {
+        Self::default().read_as(source, format)
+    }
+
+    /// `JirParser::parse_as` with this parser's forms.
+    pub fn read_as(&mut self, source: &str, format: Format) -> ParseResult<AstNode> {
+        self.recover = false;
+        let json = format.read(source).map_err(|e| self.in_source(e))?;
+        self.parse_expression(&json).map_err(|e| self.in_source(e))
+    }
+
+    /// Like `read`, for programs in the `crate::binary` encoding.
+    pub fn read_binary(&mut self, bytes: &[u8]) -> ParseResult<AstNode> {
+        self.recover = false;
+        let json = binary::read(bytes).map_err(|e| self.in_source(e))?;
+        self.parse_expression(&json).map_err(|e| self.in_source(e))
+    }
+
+    /// Parses as much as possible, reporting every problem rather than the first.
+    /// Expressions that fail to parse are replaced by `AstKind::Error` placeholders
+    /// and parsing carries on with their siblings. Malformed JSON has no tree to
+    /// recover, so the result is a single placeholder.
+    pub fn parse_all(json_str: &str) -> (AstNode, Vec<Diagnostic>) {
+        Self::default().read_all(json_str)
+    }
+
+    /// `JirParser::parse` with this parser's forms.
+    pub fn read(&mut self, json_str: &str) -> ParseResult<AstNode> {
+        self.recover = false;
+        let json = json::parse(json_str).map_err(|e| self.in_source(e))?;
+        self.parse_expression(&json).map_err(|e| self.in_source(e))
+    }
+
+    /// `JirParser::parse_all` with this parser's forms.
+    pub fn read_all(&mut self, json_str: &str) -> (AstNode, Vec<Diagnostic>) {
+        let json = match json::parse(json_str) {
+            Ok(json) => json,
+            Err(error) => {
+                let error = self.in_source(error);
+                let span = error.span;
+                return (
+                    AstNode::new(AstKind::Error, span),
+                    vec![Diagnostic::new(Severity::Error, error)],
+                );
             }
-            JsonValue::Null => Ok(AstNode::Literal(Value::Null)),
-            JsonValue::String(s) => Ok(AstNode::Literal(Value::String(s.clone()))),
-            JsonValue::Bool(b) => Ok(AstNode::Literal(Value::Boolean(b.clone()))),
-            _ => unimplemented!(),
+        };
+        self.recover = true;
+        let node = self
+            .parse_expression(&json)
+            .expect("errors are recovered from");
+        (node, std::mem::take(&mut self.diagnostics))
+    }
+
+    fn span_of(&self, json: &Json) -> Span {
+        Span {
+            source: self.source,
+            ..json.span
         }
     }
 
-    fn parse_compound(vs: &[JsonValue]) -> Result<AstNode, ParseError> {
-        match &vs[0] {
-            JsonValue::String(s) if s == "$add" => {
-                Self::assert_form_range(vs, Some(3), Some(3))?;
-                let lhs = Self::parse_expression(&vs[1])?;
-                let rhs = Self::parse_expression(&vs[2])?;
-                Ok(AstNode::Add(Box::new(lhs), Box::new(rhs)))
+    fn in_source<E>(&self, mut error: Located<E>) -> Located<E> {
+        if let Some(span) = &mut error.span {
+            span.source = self.source;
+        }
+        error
+    }
+
+    /// Parses JSON as an expression, for the parsers of forms with operands.
+    pub fn parse_expression(&mut self, json: &Json) -> ParseResult<AstNode> {
+        let statement = std::mem::take(&mut self.next_statement);
+        let kind = match &json.kind {
+            JsonKind::Array(values) => {
+                let outer = std::mem::replace(&mut self.statement, statement);
+                let kind = self.parse_compound(values);
+                self.statement = outer;
+                kind
             }
-            JsonValue::String(s) if s == "$sub" => vs[1..=2]
-                .iter()
-                .map(Self::parse_expression)
-                .collect::<Result<Vec<AstNode>, ParseError>>()
-                .map(|ns| AstNode::Sub(Box::new(ns[0].clone()), Box::new(ns[1].clone()))),
-            JsonValue::String(s) if s == "$bind" => Ok(AstNode::Bind(
-                Self::parse_ident(&vs[1])?,
-                Box::new(Self::parse_expression(&vs[2])?),
-            )),
-            JsonValue::String(s) if s == "$ref" => Ok(AstNode::Ident(Self::parse_ident(&vs[1])?)),
-            JsonValue::String(s) if s == "$if" => {
-                Self::assert_form_range(vs, Some(3), Some(4))?;
-                let cond = Self::parse_expression(&vs[1])?;
-                let true_branch = Self::parse_expression(&vs[2])?;
-                let false_branch = if vs.len() == 4 {
-                    Some(Box::new(Self::parse_expression(&vs[3])?))
-                } else {
-                    None
+            JsonKind::Number(_) => Self::parse_number(json).map(AstKind::Literal),
+            JsonKind::Null => Ok(AstKind::Literal(Value::Null)),
+            JsonKind::String(s) => Ok(AstKind::Literal(Value::String(s.clone()))),
+            JsonKind::Bool(b) => Ok(AstKind::Literal(Value::Boolean(*b))),
+            JsonKind::Object(_) => Err(ParseError::UnexpectedObject.into()),
+        };
+        match kind {
+            Ok(kind) => Ok(AstNode::new(kind, Some(self.span_of(json)))),
+            Err(error) if self.recover => {
+                let severity = match error.error {
+                    ParseError::UnknownForm(_) => Severity::Warning,
+                    _ => Severity::Error,
                 };
-                Ok(AstNode::If(
-                    Box::new(cond),
-                    Box::new(true_branch),
-                    false_branch,
-                ))
-            }
-            JsonValue::String(s) if s == "$while" => {
-                Self::assert_form_range(vs, Some(3), Some(3))?;
-                let cond = Self::parse_expression(&vs[1])?;
-                let body = Self::parse_expression(&vs[2])?;
-                Ok(AstNode::While(Box::new(cond), Box::new(body)))
-            }
-            JsonValue::String(s) if s == "$and" => {
-                Self::assert_form_range(vs, Some(3), Some(3))?;
-                Ok(AstNode::And(
-                    Box::new(Self::parse_expression(&vs[1])?),
-                    Box::new(Self::parse_expression(&vs[2])?),
-                ))
-            }
-            JsonValue::String(s) if s == "$or" => {
-                Self::assert_form_range(vs, Some(3), Some(3))?;
-                Ok(AstNode::Or(
-                    Box::new(Self::parse_expression(&vs[1])?),
-                    Box::new(Self::parse_expression(&vs[2])?),
-                ))
+                let error = self.in_source(error.or_at(Some(json.span)));
+                self.diagnostics.push(Diagnostic::new(severity, error));
+                Ok(AstNode::new(AstKind::Error, Some(self.span_of(json))))
             }
-            JsonValue::String(s) if s == "$not" => {
-                Self::assert_form_range(vs, Some(2), Some(2))?;
-                Ok(AstNode::Not(Box::new(Self::parse_expression(&vs[1])?)))
+            Err(error) => Err(error.or_at(Some(json.span))),
+        }
+    }
+
+    /// Parses an operand that is in statement position when its form is, like the
+    /// statements of `$do` and the branches of `$if`.
+    fn parse_statement(&mut self, json: &Json) -> ParseResult<AstNode> {
+        self.next_statement = self.statement;
+        self.parse_expression(json)
+    }
+
+    /// Parses a function body, which is a generator's when `generator` is true.
+    fn parse_body(&mut self, json: &Json, generator: bool) -> ParseResult<AstNode> {
+        let outer = std::mem::replace(&mut self.in_generator, generator);
+        self.next_statement = true;
+        let body = self.parse_expression(json);
+        self.in_generator = outer;
+        body
+    }
+
+    fn parse_number(v: &Json) -> ParseResult<Value> {
+        match &v.kind {
+            JsonKind::Number(num) => match num.parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+                _ => Self::error_at(ParseError::UnsupportedNumberLiteral(num.clone()), v),
+            },
+            _ => unreachable!("not a number"),
+        }
+    }
+
+    fn parse_compound(&mut self, vs: &[Json]) -> ParseResult<AstKind> {
+        let head = match vs.first() {
+            Some(head) => head,
+            None => return Err(ParseError::EmptyForm.into()),
+        };
+        let name = match &head.kind {
+            JsonKind::String(name) => name,
+            _ => return Err(ParseError::UnsupportedForm.into()),
+        };
+        let form = match self.forms.get(name).cloned() {
+            Some(form) => form,
+            None if name.starts_with('$') => {
+                return Err(ParseError::UnknownForm(name.clone()).into())
             }
-            JsonValue::String(s) if s == "$eq" => {
-                Self::assert_form_range(vs, Some(3), Some(3))?;
-                Ok(AstNode::Eq(
-                    Box::new(Self::parse_expression(&vs[1])?),
-                    Box::new(Self::parse_expression(&vs[2])?),
-                ))
+            None => return Err(ParseError::UnsupportedForm.into()),
+        };
+        Self::assert_arity(&vs[1..], form.min_args, form.max_args)?;
+        form.parse(self, vs)
+    }
+
+    fn parse_date_unit(v: &Json) -> ParseResult<DateUnit> {
+        match v.as_str() {
+            Some("years") => Ok(DateUnit::Years),
+            Some("months") => Ok(DateUnit::Months),
+            Some("weeks") => Ok(DateUnit::Weeks),
+            Some("days") => Ok(DateUnit::Days),
+            Some("hours") => Ok(DateUnit::Hours),
+            Some("minutes") => Ok(DateUnit::Minutes),
+            Some("seconds") => Ok(DateUnit::Seconds),
+            Some("milliseconds") => Ok(DateUnit::Milliseconds),
+            _ => Self::error_at(ParseError::UnknownDateUnit(v.to_string()), v),
+        }
+    }
+
+    fn parse_date_component(v: &Json) -> ParseResult<DateComponent> {
+        match v.as_str() {
+            Some("year") => Ok(DateComponent::Year),
+            Some("month") => Ok(DateComponent::Month),
+            Some("day") => Ok(DateComponent::Day),
+            Some("hour") => Ok(DateComponent::Hour),
+            Some("minute") => Ok(DateComponent::Minute),
+            Some("second") => Ok(DateComponent::Second),
+            Some("millisecond") => Ok(DateComponent::Millisecond),
+            Some("weekday") => Ok(DateComponent::Weekday),
+            Some("dayOfYear") => Ok(DateComponent::DayOfYear),
+            _ => Self::error_at(ParseError::UnknownDateComponent(v.to_string()), v),
+        }
+    }
+
+    /// Desugars `[op, a, b, c]` to `op(op(a, b), c)`.
+    fn parse_left_fold(
+        &mut self,
+        vs: &[Json],
+        op: fn(Box<AstNode>, Box<AstNode>) -> AstKind,
+    ) -> ParseResult<AstKind> {
+        let first = op(
+            Box::new(self.parse_expression(&vs[1])?),
+            Box::new(self.parse_expression(&vs[2])?),
+        );
+        vs[3..].iter().try_fold(first, |lhs, rhs| {
+            Ok(op(lhs.into(), Box::new(self.parse_expression(rhs)?)))
+        })
+    }
+
+    fn parse_params(&mut self, v: &Json) -> ParseResult<Vec<Param>> {
+        let params = match &v.kind {
+            JsonKind::Array(params) => params
+                .iter()
+                .map(|v| self.parse_param(v))
+                .collect::<ParseResult<Vec<Param>>>()?,
+            _ => return Self::error_at(ParseError::ParamsExpected, v),
+        };
+        match params.iter().position(|p| p.rest) {
+            Some(index) if index + 1 != params.len() => {
+                Self::error_at(ParseError::InvalidRestParam, v)
             }
-            JsonValue::String(s) if s == "$notEq" => {
-                Self::assert_form_range(vs, Some(3), Some(3))?;
-                Ok(AstNode::NotEq(
-                    Box::new(Self::parse_expression(&vs[1])?),
-                    Box::new(Self::parse_expression(&vs[2])?),
-                ))
+            _ => Ok(params),
+        }
+    }
+
+    /// A parameter is a name, a `[name, type]` pair or a `[name, type, default]`
+    /// triple. A name starting with `...` makes a rest parameter, which can't have
+    /// a default.
+    fn parse_param(&mut self, v: &Json) -> ParseResult<Param> {
+        let (name, ty, default) = match &v.kind {
+            JsonKind::Array(spec) if (2..=3).contains(&spec.len()) => (
+                &spec[0],
+                Some(Self::parse_type(&spec[1])?),
+                match spec.get(2) {
+                    Some(default) => Some(self.parse_expression(default)?),
+                    None => None,
+                },
+            ),
+            _ => (v, None, None),
+        };
+        let Ident(name) = Self::parse_ident(name)?;
+        let (ident, rest) = match name.strip_prefix("...") {
+            Some(name) => (Ident(name.into()), true),
+            None => (Ident(name), false),
+        };
+        if rest && default.is_some() {
+            return Self::error_at(ParseError::InvalidRestParam, v);
+        }
+        Ok(Param {
+            ident,
+            ty,
+            default,
+            rest,
+        })
+    }
+
+    /// A field is a name, a `[name, type]` pair or a `[name, type, default]` triple.
+    fn parse_field(&mut self, v: &Json) -> ParseResult<Field> {
+        match &v.kind {
+            JsonKind::Array(spec) if (2..=3).contains(&spec.len()) => Ok(Field {
+                name: Self::parse_name(&spec[0])?,
+                ty: Some(Self::parse_type(&spec[1])?),
+                default: match spec.get(2) {
+                    Some(default) => Some(self.parse_expression(default)?),
+                    None => None,
+                },
+            }),
+            _ => Ok(Field {
+                name: Self::parse_name(v)?,
+                ty: None,
+                default: None,
+            }),
+        }
+    }
+
+    /// Field values are written as a JSON object whose values are expressions.
+    fn parse_field_values(&mut self, v: &Json) -> ParseResult<Vec<(String, AstNode)>> {
+        match &v.kind {
+            JsonKind::Object(map) => map
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), self.parse_expression(value)?)))
+                .collect(),
+            _ => Self::error_at(ParseError::FieldsExpected, v),
+        }
+    }
+
+    /// A variant is a name, or a `[name, field...]` array whose fields are written
+    /// like function parameters.
+    fn parse_variant(&mut self, v: &Json) -> ParseResult<Variant> {
+        match &v.kind {
+            JsonKind::Array(spec) if !spec.is_empty() => Ok(Variant {
+                name: Self::parse_name(&spec[0])?,
+                fields: spec[1..]
+                    .iter()
+                    .map(|field| match self.parse_param(field)? {
+                        field @ Param {
+                            default: None,
+                            rest: false,
+                            ..
+                        } => Ok(field),
+                        _ => Self::error_at(ParseError::InvalidVariantField, field),
+                    })
+                    .collect::<ParseResult<Vec<Param>>>()?,
+            }),
+            _ => Ok(Variant {
+                name: Self::parse_name(v)?,
+                fields: Vec::new(),
+            }),
+        }
+    }
+
+    /// An arm is a `[pattern, body]` pair. Patterns are `"_"`, a variant name, or a
+    /// `[name, binding...]` array.
+    fn parse_match_arm(&mut self, v: &Json) -> ParseResult<MatchArm> {
+        let (pattern, body) = match &v.kind {
+            JsonKind::Array(arm) if arm.len() == 2 => (&arm[0], &arm[1]),
+            _ => return Self::error_at(ParseError::MatchArmExpected, v),
+        };
+        let pattern = match &pattern.kind {
+            JsonKind::String(s) if s == "_" => Pattern::Wildcard,
+            JsonKind::Array(spec) if !spec.is_empty() => Pattern::Variant(
+                Self::parse_name(&spec[0])?,
+                spec[1..]
+                    .iter()
+                    .map(|binding| match &binding.kind {
+                        JsonKind::String(s) if s == "_" => Ok(None),
+                        _ => Self::parse_ident(binding).map(Some),
+                    })
+                    .collect::<ParseResult<Vec<Option<Ident>>>>()?,
+            ),
+            _ => Pattern::Variant(Self::parse_name(pattern)?, Vec::new()),
+        };
+        Ok(MatchArm {
+            pattern,
+            body: self.parse_expression(body)?,
+        })
+    }
+
+    /// Reads JSON as data: arrays are never forms and `$`-prefixed strings are
+    /// plain strings.
+    fn parse_quoted(v: &Json) -> ParseResult<Value> {
+        match &v.kind {
+            JsonKind::Null => Ok(Value::Null),
+            JsonKind::Bool(b) => Ok(Value::Boolean(*b)),
+            JsonKind::Number(_) => Self::parse_number(v),
+            JsonKind::String(s) => Ok(Value::String(s.clone())),
+            JsonKind::Array(items) => items
+                .iter()
+                .map(Self::parse_quoted)
+                .collect::<ParseResult<Vec<Value>>>()
+                .map(Value::Array),
+            JsonKind::Object(entries) => entries
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), Self::parse_quoted(value)?)))
+                .collect::<ParseResult<BTreeMap<String, Value>>>()
+                .map(Value::Object),
+        }
+    }
+
+    /// Like `parse_quoted`, except that `["$unquote", expr]` anywhere inside is an
+    /// expression to evaluate. Parts without an `$unquote` become literals.
+    fn parse_quasiquoted(&mut self, v: &Json) -> ParseResult<AstNode> {
+        if !Self::contains_unquote(v) {
+            return Ok(AstNode::new(
+                AstKind::Literal(Self::parse_quoted(v)?),
+                Some(self.span_of(v)),
+            ));
+        }
+        let kind = match &v.kind {
+            JsonKind::Array(vs) if Self::is_unquote(vs) => {
+                Self::assert_arity(&vs[1..], 1, Some(1)).map_err(|e| e.or_at(Some(v.span)))?;
+                return self.parse_expression(&vs[1]);
             }
-            _ => Err(ParseError::UnsupportedForm),
+            JsonKind::Array(items) => items
+                .iter()
+                .map(|v| self.parse_quasiquoted(v))
+                .collect::<ParseResult<Vec<AstNode>>>()
+                .map(AstKind::Array),
+            JsonKind::Object(entries) => entries
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), self.parse_quasiquoted(value)?)))
+                .collect::<ParseResult<Vec<(String, AstNode)>>>()
+                .map(AstKind::Object),
+            _ => unreachable!("scalars contain no $unquote"),
+        };
+        Ok(AstNode::new(kind?, Some(self.span_of(v))))
+    }
+
+    fn contains_unquote(v: &Json) -> bool {
+        match &v.kind {
+            JsonKind::Array(vs) => Self::is_unquote(vs) || vs.iter().any(Self::contains_unquote),
+            JsonKind::Object(entries) => entries.iter().any(|(_, v)| Self::contains_unquote(v)),
+            _ => false,
+        }
+    }
+
+    fn is_unquote(vs: &[Json]) -> bool {
+        matches!(vs.first().and_then(Json::as_str), Some("$unquote"))
+    }
+
+    pub fn parse_name(v: &Json) -> ParseResult<String> {
+        match &v.kind {
+            JsonKind::String(s) => Ok(s.clone()),
+            _ => Self::error_at(ParseError::NameExpected, v),
         }
     }
 
-    fn parse_ident(v: &JsonValue) -> Result<Ident, ParseError> {
-        match v {
-            JsonValue::String(s) => Ok(Ident(s.clone())),
-            _ => Err(ParseError::IdentExpected),
+    pub fn parse_type(v: &Json) -> ParseResult<Type> {
+        v.as_str().and_then(Type::parse).ok_or_else(|| {
+            Located::new(
+                ParseError::InvalidTypeAnnotation(v.to_string()),
+                Some(v.span),
+            )
+        })
+    }
+
+    pub fn parse_ident(v: &Json) -> ParseResult<Ident> {
+        match &v.kind {
+            JsonKind::String(s) => Ok(Ident(s.clone())),
+            _ => Self::error_at(ParseError::IdentExpected, v),
         }
     }
 
-    fn assert_form_range(
-        vs: &[JsonValue],
-        min: Option<usize>,
-        max: Option<usize>,
-    ) -> Result<(), ParseError> {
-        let expected_min = min.unwrap_or(usize::MIN);
+    fn assert_arity(operands: &[Json], expected_min: usize, max: Option<usize>) -> ParseResult<()> {
         let expected_max = max.unwrap_or(usize::MAX);
-        let actual = vs.len();
+        let actual = operands.len();
         if actual < expected_min {
             Err(ParseError::NotEnoughArgs {
                 actual,
                 expected_min,
-            })
+            }
+            .into())
         } else if actual > expected_max {
             Err(ParseError::TooManyArgs {
                 actual,
                 expected_max,
-            })
+            }
+            .into())
         } else {
             Ok(())
         }
     }
+
+    fn error_at<T>(error: ParseError, v: &Json) -> ParseResult<T> {
+        Err(Located::new(error, Some(v.span)))
+    }
+}
+
+/// Registers the built-in forms. Arities count operands, not the form name.
+pub(crate) fn register_builtins(forms: &mut FormRegistry) {
+    forms.register(Form::new("$add", 2, None, |p, vs| {
+        p.parse_left_fold(vs, AstKind::Add)
+    }));
+    forms.register(Form::new("$sub", 2, None, |p, vs| {
+        p.parse_left_fold(vs, AstKind::Sub)
+    }));
+    forms.register(Form::new("$bind", 2, Some(2), |p, vs| {
+        Ok(AstKind::Bind(
+            JirParser::parse_ident(&vs[1])?,
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$let", 2, Some(3), |p, vs| {
+        let ident = JirParser::parse_ident(&vs[1])?;
+        let (ty, value) = if vs.len() == 4 {
+            (Some(JirParser::parse_type(&vs[2])?), &vs[3])
+        } else {
+            (None, &vs[2])
+        };
+        Ok(AstKind::Let(
+            ident,
+            ty,
+            Box::new(p.parse_expression(value)?),
+        ))
+    }));
+    forms.register(Form::new("$ref", 1, Some(1), |_, vs| {
+        Ok(AstKind::Ident(JirParser::parse_ident(&vs[1])?))
+    }));
+    forms.register(Form::new("$if", 2, Some(3), |p, vs| {
+        let cond = p.parse_expression(&vs[1])?;
+        let true_branch = p.parse_statement(&vs[2])?;
+        let false_branch = if vs.len() == 4 {
+            Some(Box::new(p.parse_statement(&vs[3])?))
+        } else {
+            None
+        };
+        Ok(AstKind::If(
+            Box::new(cond),
+            Box::new(true_branch),
+            false_branch,
+        ))
+    }));
+    forms.register(Form::new("$while", 2, Some(2), |p, vs| {
+        let cond = p.parse_expression(&vs[1])?;
+        let body = p.parse_statement(&vs[2])?;
+        Ok(AstKind::While(Box::new(cond), Box::new(body)))
+    }));
+    forms.register(Form::new("$and", 2, None, |p, vs| {
+        p.parse_left_fold(vs, AstKind::And)
+    }));
+    forms.register(Form::new("$or", 2, None, |p, vs| {
+        p.parse_left_fold(vs, AstKind::Or)
+    }));
+    forms.register(Form::new("$not", 1, Some(1), |p, vs| {
+        Ok(AstKind::Not(Box::new(p.parse_expression(&vs[1])?)))
+    }));
+    forms.register(Form::new("$eq", 2, None, |p, vs| {
+        let mut operands = vs[1..]
+            .iter()
+            .map(|v| p.parse_expression(v))
+            .collect::<ParseResult<Vec<AstNode>>>()?;
+        if operands.len() > 2 {
+            return Ok(AstKind::EqAll(operands));
+        }
+        let rhs = operands.pop().expect("two operands");
+        let lhs = operands.pop().expect("two operands");
+        Ok(AstKind::Eq(Box::new(lhs), Box::new(rhs)))
+    }));
+    forms.register(Form::new("$notEq", 2, Some(2), |p, vs| {
+        Ok(AstKind::NotEq(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$lt", 2, Some(2), |p, vs| {
+        Ok(AstKind::Lt(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$lte", 2, Some(2), |p, vs| {
+        Ok(AstKind::Lte(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$gt", 2, Some(2), |p, vs| {
+        Ok(AstKind::Gt(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$gte", 2, Some(2), |p, vs| {
+        Ok(AstKind::Gte(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$array", 0, None, |p, vs| {
+        vs[1..]
+            .iter()
+            .map(|v| p.parse_expression(v))
+            .collect::<ParseResult<Vec<AstNode>>>()
+            .map(AstKind::Array)
+    }));
+    forms.register(Form::new("$fn", 2, Some(2), |p, vs| {
+        let params = p.parse_params(&vs[1])?;
+        let body = p.parse_body(&vs[2], false)?;
+        Ok(AstKind::Fn(params, Rc::new(body)))
+    }));
+    forms.register(Form::new("$call", 1, None, |p, vs| {
+        let callee = p.parse_expression(&vs[1])?;
+        let (args, named) = match vs[2..].split_last() {
+            Some((
+                named @ Json {
+                    kind: JsonKind::Object(_),
+                    ..
+                },
+                args,
+            )) => (args, p.parse_field_values(named)?),
+            _ => (&vs[2..], Vec::new()),
+        };
+        let args = args
+            .iter()
+            .map(|v| p.parse_expression(v))
+            .collect::<ParseResult<Vec<AstNode>>>()?;
+        Ok(AstKind::Call(Box::new(callee), args, named))
+    }));
+    forms.register(Form::new("$map", 2, Some(2), |p, vs| {
+        Ok(AstKind::Map(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$filter", 2, Some(2), |p, vs| {
+        Ok(AstKind::Filter(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$reduce", 3, Some(3), |p, vs| {
+        Ok(AstKind::Reduce(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+            Box::new(p.parse_expression(&vs[3])?),
+        ))
+    }));
+    forms.register(Form::new("$some", 2, Some(2), |p, vs| {
+        Ok(AstKind::Some(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$every", 2, Some(2), |p, vs| {
+        Ok(AstKind::Every(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$do", 0, None, |p, vs| {
+        vs[1..]
+            .iter()
+            .map(|v| p.parse_statement(v))
+            .collect::<ParseResult<Vec<AstNode>>>()
+            .map(AstKind::Do)
+    }));
+    forms.register(Form::new("$sort", 1, Some(2), |p, vs| {
+        Ok(AstKind::Sort(
+            Box::new(p.parse_expression(&vs[1])?),
+            match vs.get(2) {
+                Some(key) => Some(Box::new(p.parse_expression(key)?)),
+                None => None,
+            },
+        ))
+    }));
+    forms.register(Form::new("$groupBy", 2, Some(2), |p, vs| {
+        Ok(AstKind::GroupBy(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$unique", 1, Some(1), |p, vs| {
+        Ok(AstKind::Unique(Box::new(p.parse_expression(&vs[1])?)))
+    }));
+    forms.register(Form::new("$union", 2, Some(2), |p, vs| {
+        Ok(AstKind::Union(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$intersect", 2, Some(2), |p, vs| {
+        Ok(AstKind::Intersect(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$difference", 2, Some(2), |p, vs| {
+        Ok(AstKind::Difference(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$range", 1, Some(3), |p, vs| {
+        let start = p.parse_expression(&vs[1])?;
+        let end = match vs.get(2) {
+            None
+            | Some(Json {
+                kind: JsonKind::Null,
+                ..
+            }) => None,
+            Some(end) => Some(Box::new(p.parse_expression(end)?)),
+        };
+        let step = match vs.get(3) {
+            Some(step) => Some(Box::new(p.parse_expression(step)?)),
+            None => None,
+        };
+        Ok(AstKind::Range(Box::new(start), end, step))
+    }));
+    forms.register(Form::new("$take", 2, Some(2), |p, vs| {
+        Ok(AstKind::Take(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$takeWhile", 2, Some(2), |p, vs| {
+        Ok(AstKind::TakeWhile(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$collect", 1, Some(1), |p, vs| {
+        Ok(AstKind::Collect(Box::new(p.parse_expression(&vs[1])?)))
+    }));
+    forms.register(Form::new("$gen", 2, Some(2), |p, vs| {
+        let params = p.parse_params(&vs[1])?;
+        let body = p.parse_body(&vs[2], true)?;
+        Ok(AstKind::Gen(params, Rc::new(body)))
+    }));
+    forms.register(Form::new("$yield", 1, Some(1), |p, vs| {
+        if !p.in_generator {
+            return Err(ParseError::YieldOutsideGenerator.into());
+        }
+        if !p.statement {
+            return Err(ParseError::MisplacedYield.into());
+        }
+        Ok(AstKind::Yield(Box::new(p.parse_expression(&vs[1])?)))
+    }));
+    forms.register(Form::new("$struct", 2, Some(2), |p, vs| {
+        let name = JirParser::parse_name(&vs[1])?;
+        let specs = match &vs[2].kind {
+            JsonKind::Array(specs) => specs,
+            _ => return JirParser::error_at(ParseError::FieldsExpected, &vs[2]),
+        };
+        let mut fields = Vec::<Field>::with_capacity(specs.len());
+        for spec in specs {
+            let field = p.parse_field(spec)?;
+            if fields.iter().any(|f| f.name == field.name) {
+                return JirParser::error_at(ParseError::DuplicateField(field.name), spec);
+            }
+            fields.push(field);
+        }
+        Ok(AstKind::StructDecl(name, fields))
+    }));
+    forms.register(Form::new("$new", 1, Some(2), |p, vs| {
+        let name = JirParser::parse_name(&vs[1])?;
+        let values = match vs.get(2) {
+            Some(values) => p.parse_field_values(values)?,
+            None => Vec::new(),
+        };
+        Ok(AstKind::New(name, values))
+    }));
+    forms.register(Form::new("$field", 2, Some(2), |p, vs| {
+        Ok(AstKind::GetField(
+            Box::new(p.parse_expression(&vs[1])?),
+            JirParser::parse_name(&vs[2])?,
+        ))
+    }));
+    forms.register(Form::new("$with", 2, Some(2), |p, vs| {
+        Ok(AstKind::With(
+            Box::new(p.parse_expression(&vs[1])?),
+            p.parse_field_values(&vs[2])?,
+        ))
+    }));
+    forms.register(Form::new("$assert", 1, Some(2), |p, vs| {
+        Ok(AstKind::Assert(
+            Box::new(p.parse_expression(&vs[1])?),
+            match vs.get(2) {
+                Some(message) => Some(Box::new(p.parse_expression(message)?)),
+                None => None,
+            },
+            vs[1].to_string(),
+        ))
+    }));
+    forms.register(Form::new("$coalesce", 1, None, |p, vs| {
+        vs[1..]
+            .iter()
+            .map(|v| p.parse_expression(v))
+            .collect::<ParseResult<Vec<AstNode>>>()
+            .map(AstKind::Coalesce)
+    }));
+    forms.register(Form::new("$safeGet", 2, None, |p, vs| {
+        Ok(AstKind::SafeGet(
+            Box::new(p.parse_expression(&vs[1])?),
+            vs[2..]
+                .iter()
+                .map(|v| p.parse_expression(v))
+                .collect::<ParseResult<Vec<AstNode>>>()?,
+        ))
+    }));
+    forms.register(Form::new("$defined", 1, Some(1), |_, vs| {
+        Ok(AstKind::Defined(JirParser::parse_ident(&vs[1])?))
+    }));
+    forms.register(Form::new("$getIn", 2, Some(3), |p, vs| {
+        Ok(AstKind::GetIn(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+            match vs.get(3) {
+                Some(strict) => Some(Box::new(p.parse_expression(strict)?)),
+                None => None,
+            },
+        ))
+    }));
+    forms.register(Form::new("$query", 2, Some(2), |p, vs| {
+        Ok(AstKind::Query(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+        ))
+    }));
+    forms.register(Form::new("$setIn", 3, Some(3), |p, vs| {
+        Ok(AstKind::SetIn(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+            Box::new(p.parse_expression(&vs[3])?),
+        ))
+    }));
+    forms.register(Form::new("$updateIn", 3, Some(3), |p, vs| {
+        Ok(AstKind::UpdateIn(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+            Box::new(p.parse_expression(&vs[3])?),
+        ))
+    }));
+    forms.register(Form::new("$enum", 2, Some(2), |p, vs| {
+        let name = JirParser::parse_name(&vs[1])?;
+        let variants = match &vs[2].kind {
+            JsonKind::Array(variants) => variants
+                .iter()
+                .map(|v| p.parse_variant(v))
+                .collect::<ParseResult<Vec<Variant>>>()?,
+            _ => return JirParser::error_at(ParseError::VariantsExpected, &vs[2]),
+        };
+        Ok(AstKind::EnumDecl(name, variants))
+    }));
+    forms.register(Form::new("$variant", 2, None, |p, vs| {
+        Ok(AstKind::NewVariant(
+            JirParser::parse_name(&vs[1])?,
+            JirParser::parse_name(&vs[2])?,
+            vs[3..]
+                .iter()
+                .map(|v| p.parse_expression(v))
+                .collect::<ParseResult<Vec<AstNode>>>()?,
+        ))
+    }));
+    forms.register(Form::new("$tag", 1, Some(1), |p, vs| {
+        Ok(AstKind::Tag(Box::new(p.parse_expression(&vs[1])?)))
+    }));
+    forms.register(Form::new("$match", 2, None, |p, vs| {
+        Ok(AstKind::Match(
+            Box::new(p.parse_expression(&vs[1])?),
+            vs[2..]
+                .iter()
+                .map(|v| p.parse_match_arm(v))
+                .collect::<ParseResult<Vec<MatchArm>>>()?,
+        ))
+    }));
+    forms.register(Form::new("$quote", 1, Some(1), |_, vs| {
+        Ok(AstKind::Literal(JirParser::parse_quoted(&vs[1])?))
+    }));
+    forms.register(Form::new("$quasiquote", 1, Some(1), |p, vs| {
+        p.parse_quasiquoted(&vs[1]).map(|node| node.kind)
+    }));
+    forms.register(Form::new("$unquote", 0, None, |_, _| {
+        Err(ParseError::UnquoteOutsideQuasiquote.into())
+    }));
+    forms.register(Form::new("$now", 0, Some(0), |_, _| Ok(AstKind::Now)));
+    forms.register(Form::new("$dateTime", 1, Some(1), |p, vs| {
+        Ok(AstKind::DateTime(Box::new(p.parse_expression(&vs[1])?)))
+    }));
+    forms.register(Form::new("$formatDateTime", 1, Some(2), |p, vs| {
+        let pattern = if vs.len() == 3 {
+            Some(Box::new(p.parse_expression(&vs[2])?))
+        } else {
+            None
+        };
+        Ok(AstKind::FormatDateTime(
+            Box::new(p.parse_expression(&vs[1])?),
+            pattern,
+        ))
+    }));
+    forms.register(Form::new("$dateAdd", 3, Some(3), |p, vs| {
+        Ok(AstKind::DateAdd(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+            JirParser::parse_date_unit(&vs[3])?,
+        ))
+    }));
+    forms.register(Form::new("$dateDiff", 3, Some(3), |p, vs| {
+        Ok(AstKind::DateDiff(
+            Box::new(p.parse_expression(&vs[1])?),
+            Box::new(p.parse_expression(&vs[2])?),
+            JirParser::parse_date_unit(&vs[3])?,
+        ))
+    }));
+    forms.register(Form::new("$datePart", 2, Some(2), |p, vs| {
+        Ok(AstKind::DatePart(
+            Box::new(p.parse_expression(&vs[1])?),
+            JirParser::parse_date_component(&vs[2])?,
+        ))
+    }));
 }
 
 #[derive(Debug)]
 pub enum ParseError {
-    InvalidJson(serde_json::Error),
+    /// Malformed JSON, and what was expected where it went wrong.
+    InvalidJson(String),
+    /// Malformed `crate::sexpr` syntax, and what was expected where it went wrong.
+    InvalidSExpr(String),
+    InvalidJson5(String),
+    /// Malformed YAML, or YAML with no JSON equivalent such as a non-scalar key.
+    InvalidYaml(String),
+    /// A malformed `crate::binary` encoding, and what was expected where it went
+    /// wrong.
+    InvalidBinary(String),
+    /// A `crate::binary` encoding in a version of the format this one can't read.
+    UnsupportedBinaryVersion(u8),
     IdentExpected,
-    TooManyArgs { actual: usize, expected_max: usize },
-    NotEnoughArgs { actual: usize, expected_min: usize },
-    InvalidFormLength { actual: usize, expected: String },
+    ParamsExpected,
+    InvalidTypeAnnotation(String),
+    NameExpected,
+    FieldsExpected,
+    /// A `$struct` that declares the same field more than once.
+    DuplicateField(String),
+    VariantsExpected,
+    /// A variant field with a default or a `...` rest marker.
+    InvalidVariantField,
+    /// A rest parameter that isn't last or has a default.
+    InvalidRestParam,
+    MatchArmExpected,
+    UnquoteOutsideQuasiquote,
+    /// A `$yield` in a `$gen` body that isn't in statement position: the body
+    /// itself, a statement of `$do`, a branch of `$if` or the body of `$while`.
+    MisplacedYield,
+    /// A `$yield` that isn't in a `$gen` body, including one in a function nested
+    /// in a generator.
+    YieldOutsideGenerator,
+    /// A form with more operands than it takes. Both counts exclude the form name,
+    /// like `Form::max_args`: `["$ref", "x", "y"]` has `actual: 2, expected_max: 1`.
+    /// (They used to be lengths of the whole form array.)
+    TooManyArgs {
+        actual: usize,
+        expected_max: usize,
+    },
+    /// A form with fewer operands than it takes. Both counts exclude the form name:
+    /// `["$sub", 1]` has `actual: 1, expected_min: 2`.
+    NotEnoughArgs {
+        actual: usize,
+        expected_min: usize,
+    },
+    InvalidFormLength {
+        actual: usize,
+        expected: String,
+    },
     UnsupportedNumberLiteral(String),
     UnsupportedForm,
+    /// A `$`-prefixed form name that isn't known, possibly one from a newer
+    /// version. Only a warning for `JirParser::parse_all`.
+    UnknownForm(String),
+    /// `[]`, which has no form name.
+    EmptyForm,
+    /// A JSON object where an expression was expected. Objects are only data, inside
+    /// `$quote` and `$quasiquote`, or named values in forms like `$new`.
+    UnexpectedObject,
+    UnknownDateUnit(String),
+    UnknownDateComponent(String),
+}
+
+/// A concrete syntax that JIR can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Json5,
+    Yaml,
+    SExpr,
+}
+
+impl Format {
+    /// The format for a file extension: `json`, `json5`, `yaml` or `yml`, and
+    /// `sexp` or `sexpr`.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "json5" => Some(Format::Json5),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "sexp" | "sexpr" => Some(Format::SExpr),
+            _ => None,
+        }
+    }
+
+    /// Reads a document into the JSON it stands for.
+    pub fn read(self, source: &str) -> Result<Json, Located<ParseError>> {
+        match self {
+            Format::Json => json::parse(source),
+            Format::Json5 => json::parse_json5(source),
+            Format::Yaml => yaml::parse(source),
+            Format::SExpr => sexpr::parse(source),
+        }
+    }
+}
+
+impl Default for JirParser {
+    /// A parser for the built-in forms.
+    fn default() -> Self {
+        Self::new(BUILTIN_FORMS.with(Rc::clone))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found by `JirParser::parse_all`.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: Located<ParseError>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, error: Located<ParseError>) -> Self {
+        Self { severity, error }
+    }
+
+    /// Like `Located::render`, labelled with the severity.
+    pub fn render(&self, source: &str) -> String {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        self.error.render_as(label, source)
+    }
 }
 
-impl From<serde_json::Error> for ParseError {
-    fn from(e: Error) -> Self {
-        ParseError::InvalidJson(e)
+impl From<ParseError> for Located<ParseError> {
+    fn from(e: ParseError) -> Self {
+        Located::new(e, None)
     }
 }
 
@@ -161,10 +1068,10 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$add", 1, 2]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::Add(
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Box::new(AstNode::Literal(Value::Number(2.0)))
-            )
+            AstNode::from(AstKind::Add(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::Number(2.0)).into()
+            ))
         );
         assert_eq!(actual, expected);
         Ok(())
@@ -175,10 +1082,10 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$sub", 1, 2]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::Sub(
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Box::new(AstNode::Literal(Value::Number(2.0)))
-            )
+            AstNode::from(AstKind::Sub(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::Number(2.0)).into()
+            ))
         );
         assert_eq!(actual, expected);
         Ok(())
@@ -189,19 +1096,95 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$bind", "foo", 1]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::Bind(
+            AstNode::from(AstKind::Bind(
                 Ident("foo".into()),
-                Box::new(AstNode::Literal(Value::Number(1.0)))
-            )
+                AstKind::Literal(Value::Number(1.0)).into()
+            ))
+        );
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_annotated_let() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$let", "x", "number", 1]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Let(
+                Ident("x".into()),
+                Some(Type::Number),
+                AstKind::Literal(Value::Number(1.0)).into()
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        assert!(matches!(
+            JirParser::parse_json(r#"["$let", "x", "numbr", 1]"#),
+            Err(ParseError::InvalidTypeAnnotation(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_struct_declaration_and_construction() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$struct", "Order", ["id", ["qty", "number", 1]]]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::StructDecl(
+                "Order".into(),
+                vec![
+                    Field {
+                        name: "id".into(),
+                        ty: None,
+                        default: None
+                    },
+                    Field {
+                        name: "qty".into(),
+                        ty: Some(Type::Number),
+                        default: Some(AstNode::from(AstKind::Literal(Value::Number(1.0))))
+                    }
+                ]
+            ))
         );
         assert_eq!(actual, expected);
+
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$new", "Order", {"id": ["$ref", "x"]}]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::New(
+                "Order".into(),
+                vec![(
+                    "id".into(),
+                    AstNode::from(AstKind::Ident(Ident("x".into())))
+                )]
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        assert!(matches!(
+            JirParser::parse_json(r#"["$new", "Order", ["id", 1]]"#),
+            Err(ParseError::FieldsExpected)
+        ));
+        assert!(matches!(
+            JirParser::parse_json(r#"["$struct", "P", ["a", ["a", "number"]]]"#),
+            Err(ParseError::DuplicateField(field)) if field == "a"
+        ));
         Ok(())
     }
 
     #[test]
     fn it_parses_ref() -> Result<(), ParseError> {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$ref", "foo"]"#)?);
-        let expected = format!("{:?}", AstNode::Ident(Ident("foo".into()),));
+        let expected = format!("{:?}", AstNode::from(AstKind::Ident(Ident("foo".into()),)));
         assert_eq!(actual, expected);
         Ok(())
     }
@@ -211,11 +1194,11 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$if", true, 1, 2]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::If(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Some(Box::new(AstNode::Literal(Value::Number(2.0))))
-            )
+            AstNode::from(AstKind::If(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Number(1.0)).into(),
+                Some(AstKind::Literal(Value::Number(2.0)).into())
+            ))
         );
         assert_eq!(actual, expected);
         Ok(())
@@ -226,10 +1209,10 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$while", true, 1]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::While(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-            )
+            AstNode::from(AstKind::While(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Number(1.0)).into(),
+            ))
         );
         assert_eq!(actual, expected);
         Ok(())
@@ -240,11 +1223,11 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$if", true, 1]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::If(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Number(1.0))),
+            AstNode::from(AstKind::If(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Number(1.0)).into(),
                 None,
-            )
+            ))
         );
         assert_eq!(actual, expected);
         Ok(())
@@ -255,27 +1238,27 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$and", true, false]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::And(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Boolean(false))),
-            )
+            AstNode::from(AstKind::And(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Boolean(false)).into(),
+            ))
         );
         assert_eq!(actual, expected);
 
         let actual = format!("{:?}", JirParser::parse_json(r#"["$or", true, false]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::Or(
-                Box::new(AstNode::Literal(Value::Boolean(true))),
-                Box::new(AstNode::Literal(Value::Boolean(false))),
-            )
+            AstNode::from(AstKind::Or(
+                AstKind::Literal(Value::Boolean(true)).into(),
+                AstKind::Literal(Value::Boolean(false)).into(),
+            ))
         );
         assert_eq!(actual, expected);
 
         let actual = format!("{:?}", JirParser::parse_json(r#"["$not", true]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::Not(Box::new(AstNode::Literal(Value::Boolean(true))))
+            AstNode::from(AstKind::Not(AstKind::Literal(Value::Boolean(true)).into()))
         );
         assert_eq!(actual, expected);
 
@@ -287,23 +1270,622 @@ mod tests {
         let actual = format!("{:?}", JirParser::parse_json(r#"["$eq", 1.0, 2.0]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::Eq(
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Box::new(AstNode::Literal(Value::Number(2.0))),
-            )
+            AstNode::from(AstKind::Eq(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::Number(2.0)).into(),
+            ))
         );
         assert_eq!(actual, expected);
 
         let actual = format!("{:?}", JirParser::parse_json(r#"["$notEq", 1.0, 2.0]"#)?);
         let expected = format!(
             "{:?}",
-            AstNode::NotEq(
-                Box::new(AstNode::Literal(Value::Number(1.0))),
-                Box::new(AstNode::Literal(Value::Number(2.0))),
-            )
+            AstNode::from(AstKind::NotEq(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                AstKind::Literal(Value::Number(2.0)).into(),
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_function_and_call() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$call", ["$fn", ["x"], ["$ref", "x"]], 1]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Call(
+                AstKind::Fn(
+                    vec![Param {
+                        ident: Ident("x".into()),
+                        ty: None,
+                        default: None,
+                        rest: false,
+                    }],
+                    AstKind::Ident(Ident("x".into())).into()
+                )
+                .into(),
+                vec![AstNode::from(AstKind::Literal(Value::Number(1.0)))],
+                vec![]
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        assert!(matches!(
+            JirParser::parse_json(r#"["$fn", "x", 1]"#),
+            Err(ParseError::ParamsExpected)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_higher_order_operation() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$map", ["$array", 1], ["$ref", "f"]]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Map(
+                AstKind::Array(vec![AstNode::from(AstKind::Literal(Value::Number(1.0)))]).into(),
+                AstKind::Ident(Ident("f".into())).into()
+            ))
+        );
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_unbounded_range() -> Result<(), ParseError> {
+        let actual = format!("{:?}", JirParser::parse_json(r#"["$range", 1, null, 2]"#)?);
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Range(
+                AstKind::Literal(Value::Number(1.0)).into(),
+                None,
+                Some(AstKind::Literal(Value::Number(2.0)).into())
+            ))
+        );
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_date_operations() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$dateAdd", ["$now"], 3, "days"]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::DateAdd(
+                AstKind::Now.into(),
+                AstKind::Literal(Value::Number(3.0)).into(),
+                DateUnit::Days,
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$datePart", ["$now"], "weekday"]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::DatePart(
+                AstKind::Now.into(),
+                DateComponent::Weekday
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        assert!(matches!(
+            JirParser::parse_json(r#"["$dateAdd", ["$now"], 3, "fortnights"]"#),
+            Err(ParseError::UnknownDateUnit(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_enums_and_match() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(
+                r#"["$enum", "Decision", ["Approved", ["Rejected", "reason"]]]"#
+            )?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::EnumDecl(
+                "Decision".into(),
+                vec![
+                    Variant {
+                        name: "Approved".into(),
+                        fields: vec![]
+                    },
+                    Variant {
+                        name: "Rejected".into(),
+                        fields: vec![Param {
+                            ident: Ident("reason".into()),
+                            ty: None,
+                            default: None,
+                            rest: false,
+                        }]
+                    }
+                ]
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(
+                r#"["$match", ["$ref", "d"], [["Rejected", "_"], 1], ["_", 2]]"#
+            )?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Match(
+                AstKind::Ident(Ident("d".into())).into(),
+                vec![
+                    MatchArm {
+                        pattern: Pattern::Variant("Rejected".into(), vec![None]),
+                        body: AstNode::from(AstKind::Literal(Value::Number(1.0)))
+                    },
+                    MatchArm {
+                        pattern: Pattern::Wildcard,
+                        body: AstNode::from(AstKind::Literal(Value::Number(2.0)))
+                    }
+                ]
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        assert!(matches!(
+            JirParser::parse_json(r#"["$match", ["$ref", "d"], ["_"]]"#),
+            Err(ParseError::MatchArmExpected)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_quoted_data() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$quote", ["a", "$b", {"c": null}]]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Literal(Value::Array(vec![
+                Value::String("a".into()),
+                Value::String("$b".into()),
+                Value::Object(BTreeMap::from([("c".to_string(), Value::Null)])),
+            ])))
+        );
+        assert_eq!(actual, expected);
+
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(r#"["$quasiquote", [["x"], ["$unquote", ["$ref", "y"]]]]"#)?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Array(vec![
+                AstNode::from(AstKind::Literal(Value::Array(vec![Value::String(
+                    "x".into()
+                )]))),
+                AstNode::from(AstKind::Ident(Ident("y".into()))),
+            ]))
+        );
+        assert_eq!(actual, expected);
+
+        assert!(matches!(
+            JirParser::parse_json(r#"["$unquote", 1]"#),
+            Err(ParseError::UnquoteOutsideQuasiquote)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_accepts_yield_only_in_statement_position_of_generators() {
+        let generator = |body: &str| JirParser::parse_json(&format!(r#"["$gen", [], {}]"#, body));
+        assert!(generator(r#"["$yield", 1]"#).is_ok());
+        assert!(generator(
+            r#"["$do", ["$if", true, ["$yield", 1], ["$while", true, ["$do", ["$yield", 2]]]]]"#
+        )
+        .is_ok());
+        for misplaced in [
+            r#"["$add", ["$yield", 1], 1]"#,
+            r#"["$let", "x", ["$yield", 1]]"#,
+            r#"["$if", ["$yield", 1], 2]"#,
+            r#"["$do", ["$call", ["$ref", "f"], ["$yield", 1]]]"#,
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(generator(misplaced), Err(ParseError::MisplacedYield)),
+                "{}",
+                misplaced
+            );
+        }
+        // A function inside a generator has its own body, which can't yield for it.
+        assert!(matches!(
+            generator(r#"["$fn", [], ["$yield", 1]]"#),
+            Err(ParseError::YieldOutsideGenerator)
+        ));
+        assert!(matches!(
+            JirParser::parse_json(r#"["$do", ["$yield", 1]]"#),
+            Err(ParseError::YieldOutsideGenerator)
+        ));
+    }
+
+    #[test]
+    fn it_parses_variadic_operators() -> Result<(), ParseError> {
+        let actual = format!("{:?}", JirParser::parse_json(r#"["$sub", 1, 2, 3]"#)?);
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Sub(
+                AstKind::Sub(
+                    AstKind::Literal(Value::Number(1.0)).into(),
+                    AstKind::Literal(Value::Number(2.0)).into(),
+                )
+                .into(),
+                AstKind::Literal(Value::Number(3.0)).into(),
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        let actual = format!("{:?}", JirParser::parse_json(r#"["$eq", 1, 1, 1]"#)?);
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::EqAll(vec![
+                AstNode::from(AstKind::Literal(
+                    Value::Number(1.0)
+                ));
+                3
+            ]))
         );
         assert_eq!(actual, expected);
 
+        assert!(matches!(
+            JirParser::parse_json(r#"["$sub", 1]"#),
+            Err(ParseError::NotEnoughArgs { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_default_and_rest_params_and_named_args() -> Result<(), ParseError> {
+        let actual = format!(
+            "{:?}",
+            JirParser::parse_json(
+                r#"["$call", ["$fn", [["n", "number", 1], "...xs"], null], 2, {"n": 3}]"#
+            )?
+        );
+        let expected = format!(
+            "{:?}",
+            AstNode::from(AstKind::Call(
+                AstKind::Fn(
+                    vec![
+                        Param {
+                            ident: Ident("n".into()),
+                            ty: Some(Type::Number),
+                            default: Some(AstNode::from(AstKind::Literal(Value::Number(1.0)))),
+                            rest: false,
+                        },
+                        Param {
+                            ident: Ident("xs".into()),
+                            ty: None,
+                            default: None,
+                            rest: true,
+                        }
+                    ],
+                    AstKind::Literal(Value::Null).into()
+                )
+                .into(),
+                vec![AstNode::from(AstKind::Literal(Value::Number(2.0)))],
+                vec![(
+                    "n".into(),
+                    AstNode::from(AstKind::Literal(Value::Number(3.0)))
+                )]
+            ))
+        );
+        assert_eq!(actual, expected);
+
+        assert!(matches!(
+            JirParser::parse_json(r#"["$fn", ["...xs", "y"], null]"#),
+            Err(ParseError::InvalidRestParam)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_records_the_span_of_each_node() -> Result<(), Located<ParseError>> {
+        let source = "[\"$add\", 1,\n  [\"$ref\", \"x\"]]";
+        let node = JirParser::parse(source)?;
+        let rhs = match &node.kind {
+            AstKind::Add(_, rhs) => rhs,
+            other => panic!("unexpected node: {:?}", other),
+        };
+
+        assert_eq!(node.span.map(|s| (s.start, s.end)), Some((0, source.len())));
+        assert_eq!(
+            rhs.span
+                .map(|s| (&source[s.start..s.end], s.line, s.column)),
+            Some((r#"["$ref", "x"]"#, 2, 3))
+        );
+
+        let error = JirParser::parse(r#"["$let", "x", "numbr", 1]"#).expect_err("bad type");
+        assert!(matches!(error.error, ParseError::InvalidTypeAnnotation(_)));
+        assert_eq!(error.span.map(|s| s.column), Some(15));
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_malformed_forms_without_panicking() {
+        let cases = [
+            ("[]", "EmptyForm"),
+            (r#"{"a": 1}"#, "UnexpectedObject"),
+            (r#"["$add", {}]"#, "NotEnoughArgs"),
+            (r#"["$sub", 1]"#, "NotEnoughArgs"),
+            (r#"["$bind"]"#, "NotEnoughArgs"),
+            (r#"["$bind", "x"]"#, "NotEnoughArgs"),
+            (r#"["$ref"]"#, "NotEnoughArgs"),
+            (r#"["$ref", "x", "y"]"#, "TooManyArgs"),
+            (r#"["$do", {"a": 1}]"#, "UnexpectedObject"),
+            (r#"[1, 2]"#, "UnsupportedForm"),
+            (r#"["$frobnicate"]"#, "UnknownForm"),
+            (r#"["$add", 1, 1e999]"#, "UnsupportedNumberLiteral"),
+            (&"[".repeat(200), "InvalidJson"),
+        ];
+        for (source, expected) in cases.iter() {
+            match JirParser::parse_json(source) {
+                Err(e) => assert!(
+                    format!("{:?}", e).starts_with(expected),
+                    "{}: {:?}",
+                    source,
+                    e
+                ),
+                Ok(node) => panic!("{} parsed as {:?}", source, node),
+            }
+        }
+    }
+
+    #[test]
+    fn it_collects_every_problem_and_keeps_parsing_siblings() {
+        let source = [
+            r#"["$do","#,
+            r#"  ["$sub", 1],"#,
+            r#"  ["$frobnicate", 2],"#,
+            r#"  ["$add", 1, 2],"#,
+            r#"  ["$let", 3, 4]]"#,
+        ]
+        .join("\n");
+        let (node, diagnostics) = JirParser::parse_all(&source);
+
+        assert_eq!(
+            format!("{:?}", node),
+            "Do([Error, Error, Add(Literal(Number(1.0)), Literal(Number(2.0))), Error])"
+        );
+        let summary = diagnostics
+            .iter()
+            .map(|d| {
+                let span = d.error.span.expect("located");
+                (
+                    d.severity,
+                    format!("{:?}", d.error.error),
+                    span.line,
+                    span.column,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    Severity::Error,
+                    "NotEnoughArgs { actual: 1, expected_min: 2 }".into(),
+                    2,
+                    3
+                ),
+                (
+                    Severity::Warning,
+                    r#"UnknownForm("$frobnicate")"#.into(),
+                    3,
+                    3
+                ),
+                (Severity::Error, "IdentExpected".into(), 5, 12),
+            ]
+        );
+        assert_eq!(
+            diagnostics[1].render(&source),
+            [
+                r#"warning: UnknownForm("$frobnicate")"#,
+                " --> 3:3",
+                "  |",
+                r#"3 |   ["$frobnicate", 2],"#,
+                "  |   ^^^^^^^^^^^^^^^^^^",
+            ]
+            .join("\n")
+        );
+
+        let (node, diagnostics) = JirParser::parse_all("[1,");
+        assert!(matches!(node.kind, AstKind::Error));
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [Diagnostic {
+                severity: Severity::Error,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn it_reads_every_format_into_the_same_tree() -> ParseResult<()> {
+        let sources = [
+            ("rule.json", r#"["$if", ["$gt", ["$ref", "x"], 1], "big"]"#),
+            (
+                "rule.json5",
+                "['$if', ['$gt', ['$ref', 'x'], 1,], 'big'] // x > 1",
+            ),
+            ("rule.yaml", "- $if\n- [$gt, [$ref, x], 1]\n- big\n"),
+            ("rule.sexp", "(if (gt (ref x) 1) big)"),
+        ];
+        let expected = format!("{:?}", JirParser::parse(sources[0].1)?);
+        for (path, source) in sources.iter() {
+            let format = Format::from_path(Path::new(path)).expect("a known extension");
+            let node = JirParser::parse_as(source, format)?;
+            assert_eq!(format!("{:?}", node), expected, "{}", path);
+        }
+        assert_eq!(Format::from_path(Path::new("rule.yml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("rule.txt")), None);
         Ok(())
     }
+
+    mod fuzz {
+        use proptest::prelude::*;
+        use serde_json::Value as JsonValue;
+
+        use super::*;
+
+        const FORMS: &[&str] = &[
+            "$add",
+            "$sub",
+            "$bind",
+            "$let",
+            "$ref",
+            "$if",
+            "$while",
+            "$and",
+            "$or",
+            "$not",
+            "$eq",
+            "$notEq",
+            "$lt",
+            "$lte",
+            "$gt",
+            "$gte",
+            "$array",
+            "$fn",
+            "$call",
+            "$map",
+            "$filter",
+            "$reduce",
+            "$some",
+            "$every",
+            "$do",
+            "$sort",
+            "$groupBy",
+            "$unique",
+            "$union",
+            "$intersect",
+            "$difference",
+            "$range",
+            "$take",
+            "$takeWhile",
+            "$collect",
+            "$gen",
+            "$yield",
+            "$struct",
+            "$new",
+            "$field",
+            "$with",
+            "$assert",
+            "$coalesce",
+            "$safeGet",
+            "$defined",
+            "$getIn",
+            "$query",
+            "$setIn",
+            "$updateIn",
+            "$enum",
+            "$variant",
+            "$tag",
+            "$match",
+            "$quote",
+            "$quasiquote",
+            "$unquote",
+            "$now",
+            "$dateTime",
+            "$formatDateTime",
+            "$dateAdd",
+            "$dateDiff",
+            "$datePart",
+        ];
+
+        /// Strings likely to mean something to the parser: form names, type names,
+        /// units, patterns and parameter markers.
+        fn word() -> impl Strategy<Value = String> {
+            prop_oneof![
+                prop::sample::select(FORMS).prop_map(String::from),
+                prop::sample::select(&["_", "x", "...xs", "number", "Foo", "days", "year"][..])
+                    .prop_map(String::from),
+                "\\PC{0,8}",
+            ]
+        }
+
+        fn json() -> impl Strategy<Value = JsonValue> {
+            let leaf = prop_oneof![
+                Just(JsonValue::Null),
+                any::<bool>().prop_map(JsonValue::Bool),
+                any::<i32>().prop_map(JsonValue::from),
+                any::<f64>().prop_map(JsonValue::from),
+                word().prop_map(JsonValue::String),
+            ];
+            leaf.prop_recursive(6, 64, 6, |inner| {
+                prop_oneof![
+                    (word(), prop::collection::vec(inner.clone(), 0..6)).prop_map(
+                        |(head, mut rest)| {
+                            rest.insert(0, JsonValue::String(head));
+                            JsonValue::Array(rest)
+                        }
+                    ),
+                    prop::collection::vec(inner.clone(), 0..4).prop_map(JsonValue::Array),
+                    prop::collection::btree_map(word(), inner, 0..3)
+                        .prop_map(|map| JsonValue::Object(map.into_iter().collect())),
+                ]
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn it_never_panics_on_arbitrary_text(source in "\\PC{0,64}") {
+                let _ = JirParser::parse_json(&source);
+            }
+
+            #[test]
+            fn it_never_panics_on_arbitrary_forms(json in json()) {
+                let _ = JirParser::parse_json(&json.to_string());
+            }
+
+            #[test]
+            fn it_recovers_from_arbitrary_forms(json in json()) {
+                let source = json.to_string();
+                let (_, diagnostics) = JirParser::parse_all(&source);
+                match JirParser::parse_json(&source) {
+                    Ok(_) => prop_assert!(diagnostics.is_empty()),
+                    Err(_) => prop_assert!(!diagnostics.is_empty()),
+                }
+            }
+
+            #[test]
+            fn it_never_panics_on_truncated_forms(json in json(), cut in any::<prop::sample::Index>()) {
+                let source = json.to_string();
+                let cut = cut.index(source.len() + 1);
+                if let Some(prefix) = source.get(..cut) {
+                    let _ = JirParser::parse_json(prefix);
+                }
+            }
+        }
+    }
 }
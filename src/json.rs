@@ -0,0 +1,540 @@
+//! A JSON reader that keeps the source span of every value, so that the JIR parser
+//! can attach positions to the nodes it builds and to its errors. It also reads
+//! JSON5, for hand-written programs.
+
+use std::fmt;
+
+use crate::jir::ParseError;
+use crate::span::{Located, Span};
+
+#[derive(Debug, Clone)]
+pub struct Json {
+    pub kind: JsonKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum JsonKind {
+    Null,
+    Bool(bool),
+    /// The number as written, which is also how it's printed back.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    /// Entries in source order. A repeated key is kept as written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.kind {
+            JsonKind::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Prints compact JSON, keeping object entries in source order.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            JsonKind::Null => write!(f, "null"),
+            JsonKind::Bool(b) => write!(f, "{}", b),
+            JsonKind::Number(n) => write!(f, "{}", n),
+            JsonKind::String(s) => write_string(f, s),
+            JsonKind::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            JsonKind::Object(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "{}", serde_json::Value::String(s.into()))
+}
+
+/// Reads a single JSON value, surrounded by optional whitespace.
+pub fn parse(source: &str) -> Result<Json, Located<ParseError>> {
+    let mut reader = Reader::new(source, ParseError::InvalidJson);
+    let value = reader.value()?;
+    reader.skip_whitespace()?;
+    if reader.pos < source.len() {
+        return Err(reader.error("end of input"));
+    }
+    Ok(value)
+}
+
+/// Reads a single JSON5 value: JSON with comments, trailing commas, single-quoted
+/// strings, unquoted keys, and hexadecimal, signed and dot-leading numbers. Numbers
+/// are normalized to JSON syntax, apart from `Infinity` and `NaN`, which JIR rejects.
+pub fn parse_json5(source: &str) -> Result<Json, Located<ParseError>> {
+    let mut reader = Reader {
+        json5: true,
+        ..Reader::new(source, ParseError::InvalidJson5)
+    };
+    let value = reader.value()?;
+    reader.skip_whitespace()?;
+    if reader.pos < source.len() {
+        return Err(reader.error("end of input"));
+    }
+    Ok(value)
+}
+
+/// A cursor over the source that tracks positions for spans. Also used by
+/// `crate::sexpr`, which shares the string and number syntax.
+pub(crate) struct Reader<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) pos: usize,
+    pub(crate) line: usize,
+    pub(crate) line_start: usize,
+    /// Arrays and objects currently open, limited so that deeply nested input
+    /// can't overflow the stack.
+    pub(crate) depth: usize,
+    /// Wraps what was expected where reading failed.
+    invalid: fn(String) -> ParseError,
+    json5: bool,
+}
+
+pub(crate) const MAX_DEPTH: usize = 128;
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(source: &'a str, invalid: fn(String) -> ParseError) -> Self {
+        Self {
+            source,
+            pos: 0,
+            line: 1,
+            line_start: 0,
+            depth: 0,
+            invalid,
+            json5: false,
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, Located<ParseError>> {
+        self.skip_whitespace()?;
+        let start = self.here();
+        let kind = match self.peek() {
+            Some('{') | Some('[') if self.depth == MAX_DEPTH => {
+                return Err(self.error(&format!("at most {} levels of nesting", MAX_DEPTH)));
+            }
+            Some('{') => self.nested(Self::object)?,
+            Some('[') => self.nested(Self::array)?,
+            Some('"') => JsonKind::String(self.string()?),
+            Some('-') | Some('0'..='9') => self.number()?,
+            Some('\'') if self.json5 => JsonKind::String(self.string()?),
+            Some('+') | Some('.') | Some('I') | Some('N') if self.json5 => self.number()?,
+            Some('t') => self.keyword("true", JsonKind::Bool(true))?,
+            Some('f') => self.keyword("false", JsonKind::Bool(false))?,
+            Some('n') => self.keyword("null", JsonKind::Null)?,
+            _ => return Err(self.error("a value")),
+        };
+        Ok(Json {
+            kind,
+            span: Span {
+                end: self.pos,
+                ..start
+            },
+        })
+    }
+
+    pub(crate) fn nested(
+        &mut self,
+        read: fn(&mut Self) -> Result<JsonKind, Located<ParseError>>,
+    ) -> Result<JsonKind, Located<ParseError>> {
+        self.depth += 1;
+        let kind = read(self);
+        self.depth -= 1;
+        kind
+    }
+
+    fn array(&mut self) -> Result<JsonKind, Located<ParseError>> {
+        self.bump();
+        let mut items = Vec::new();
+        self.skip_whitespace()?;
+        if self.eat(']') {
+            return Ok(JsonKind::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace()?;
+            if self.eat(']') {
+                return Ok(JsonKind::Array(items));
+            }
+            if !self.eat(',') {
+                return Err(self.error("`,` or `]`"));
+            }
+            self.skip_whitespace()?;
+            if self.json5 && self.eat(']') {
+                return Ok(JsonKind::Array(items));
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonKind, Located<ParseError>> {
+        self.bump();
+        let mut entries = Vec::new();
+        self.skip_whitespace()?;
+        if self.eat('}') {
+            return Ok(JsonKind::Object(entries));
+        }
+        loop {
+            self.skip_whitespace()?;
+            let key = match self.peek() {
+                Some('"') => self.string()?,
+                Some('\'') if self.json5 => self.string()?,
+                Some(c) if self.json5 && is_identifier_start(c) => self.identifier(),
+                _ => return Err(self.error("a string key")),
+            };
+            self.skip_whitespace()?;
+            if !self.eat(':') {
+                return Err(self.error("`:`"));
+            }
+            entries.push((key, self.value()?));
+            self.skip_whitespace()?;
+            if self.eat('}') {
+                return Ok(JsonKind::Object(entries));
+            }
+            if !self.eat(',') {
+                return Err(self.error("`,` or `}`"));
+            }
+            self.skip_whitespace()?;
+            if self.json5 && self.eat('}') {
+                return Ok(JsonKind::Object(entries));
+            }
+        }
+    }
+
+    /// A string, from its opening quote, which is `"` or, in JSON5, `'`.
+    pub(crate) fn string(&mut self) -> Result<String, Located<ParseError>> {
+        let quote = self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                c if c == quote => return Ok(s),
+                Some('\\') => s.extend(self.escape()?),
+                Some('\n') | Some('\r') if self.json5 => break,
+                Some(c) if c >= ' ' || self.json5 => s.push(c),
+                _ => break,
+            }
+        }
+        Err(self.error("a closing quote"))
+    }
+
+    /// The character an escape stands for. A JSON5 line continuation stands for
+    /// none.
+    fn escape(&mut self) -> Result<Option<char>, Located<ParseError>> {
+        Ok(Some(match self.bump() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let high = self.hex(4)?;
+                if !(0xd800..0xdc00).contains(&high) {
+                    return char::from_u32(high)
+                        .map(Some)
+                        .ok_or_else(|| self.error("a valid escape"));
+                }
+                if !(self.eat('\\') && self.eat('u')) {
+                    return Err(self.error("a low surrogate"));
+                }
+                let low = self.hex(4)?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return Err(self.error("a low surrogate"));
+                }
+                let c = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+                return char::from_u32(c)
+                    .map(Some)
+                    .ok_or_else(|| self.error("a valid escape"));
+            }
+            Some(c) if self.json5 => match c {
+                'v' => '\u{b}',
+                '0' if !matches!(self.peek(), Some('0'..='9')) => '\0',
+                'x' => {
+                    let code = self.hex(2)?;
+                    char::from_u32(code).ok_or_else(|| self.error("a valid escape"))?
+                }
+                '\n' | '\r' | '\u{2028}' | '\u{2029}' => {
+                    if c == '\r' {
+                        self.eat('\n');
+                    }
+                    if c != '\u{2028}' && c != '\u{2029}' {
+                        self.line += 1;
+                        self.line_start = self.pos;
+                    }
+                    return Ok(None);
+                }
+                '1'..='9' => return Err(self.error("a valid escape")),
+                c => c,
+            },
+            _ => return Err(self.error("a valid escape")),
+        }))
+    }
+
+    fn hex(&mut self, len: usize) -> Result<u32, Located<ParseError>> {
+        let digits = self.source.get(self.pos..self.pos + len).unwrap_or("");
+        match u32::from_str_radix(digits, 16) {
+            Ok(n) if digits.chars().all(|c| c.is_ascii_hexdigit()) => {
+                self.pos += len;
+                Ok(n)
+            }
+            _ => Err(self.error(&format!("{} hex digits", len))),
+        }
+    }
+
+    pub(crate) fn number(&mut self) -> Result<JsonKind, Located<ParseError>> {
+        if self.json5 {
+            return self.json5_number();
+        }
+        let start = self.pos;
+        self.eat('-');
+        if !self.eat('0') && self.digits() == 0 {
+            return Err(self.error("a digit"));
+        }
+        if self.eat('.') && self.digits() == 0 {
+            return Err(self.error("a digit"));
+        }
+        self.exponent()?;
+        Ok(JsonKind::Number(self.source[start..self.pos].into()))
+    }
+
+    /// A JSON5 number, written back in JSON syntax.
+    fn json5_number(&mut self) -> Result<JsonKind, Located<ParseError>> {
+        let negative = self.eat('-');
+        if !negative {
+            self.eat('+');
+        }
+        let sign = if negative { "-" } else { "" };
+        for word in ["Infinity", "NaN"].iter() {
+            if self.source[self.pos..].starts_with(word) {
+                self.pos += word.len();
+                return Ok(JsonKind::Number(format!("{}{}", sign, word)));
+            }
+        }
+        if self.source[self.pos..].starts_with("0x") || self.source[self.pos..].starts_with("0X") {
+            self.pos += 2;
+            let start = self.pos;
+            while matches!(self.peek(), Some(c) if c.is_ascii_hexdigit()) {
+                self.pos += 1;
+            }
+            let digits = &self.source[start..self.pos];
+            return match u128::from_str_radix(digits, 16) {
+                Ok(n) if n <= u64::MAX as u128 => Ok(JsonKind::Number(format!("{}{}", sign, n))),
+                Ok(n) => Ok(JsonKind::Number(format!("{}{:e}", sign, n as f64))),
+                Err(_) => Err(self.error("a hex digit")),
+            };
+        }
+        let int_start = self.pos;
+        let int = if self.eat('0') { 1 } else { self.digits() };
+        let int = &self.source[int_start..int_start + int];
+        let fraction = if self.eat('.') {
+            let start = self.pos;
+            let len = self.digits();
+            &self.source[start..start + len]
+        } else {
+            ""
+        };
+        if int.is_empty() && fraction.is_empty() {
+            return Err(self.error("a digit"));
+        }
+        let exponent_start = self.pos;
+        self.exponent()?;
+        let mut number = format!("{}{}", sign, if int.is_empty() { "0" } else { int });
+        if !fraction.is_empty() {
+            number = format!("{}.{}", number, fraction);
+        }
+        number.push_str(&self.source[exponent_start..self.pos]);
+        Ok(JsonKind::Number(number))
+    }
+
+    fn exponent(&mut self) -> Result<(), Located<ParseError>> {
+        if self.eat('e') || self.eat('E') {
+            if !self.eat('+') {
+                self.eat('-');
+            }
+            if self.digits() == 0 {
+                return Err(self.error("a digit"));
+            }
+        }
+        Ok(())
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn keyword(&mut self, word: &str, kind: JsonKind) -> Result<JsonKind, Located<ParseError>> {
+        if self.source[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(kind)
+        } else {
+            Err(self.error("a value"))
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), Located<ParseError>> {
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => {
+                    self.pos += 1;
+                    self.line += 1;
+                    self.line_start = self.pos;
+                }
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '/' if self.json5 && self.source[self.pos..].starts_with("//") => {
+                    while !matches!(self.peek(), Some('\n') | None) {
+                        self.bump();
+                    }
+                }
+                '/' if self.json5 && self.source[self.pos..].starts_with("/*") => {
+                    self.pos += 2;
+                    while !self.source[self.pos..].starts_with("*/") {
+                        match self.bump() {
+                            Some('\n') => {
+                                self.line += 1;
+                                self.line_start = self.pos;
+                            }
+                            Some(_) => {}
+                            None => return Err(self.error("`*/`")),
+                        }
+                    }
+                    self.pos += 2;
+                }
+                c if self.json5 && (c.is_whitespace() || c == '\u{feff}') => {
+                    self.pos += c.len_utf8()
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// An unquoted JSON5 object key.
+    fn identifier(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if is_identifier_start(c) || c.is_alphanumeric()) {
+            self.bump();
+        }
+        self.source[start..self.pos].into()
+    }
+
+    pub(crate) fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    pub(crate) fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    pub(crate) fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// An empty span at the current position. Line breaks only appear in
+    /// whitespace, which `skip_whitespace` counts.
+    pub(crate) fn here(&self) -> Span {
+        Span {
+            start: self.pos,
+            end: self.pos,
+            line: self.line,
+            column: self.source[self.line_start..self.pos].chars().count() + 1,
+            source: 0,
+        }
+    }
+
+    pub(crate) fn error(&self, expected: &str) -> Located<ParseError> {
+        let end = self.peek().map_or(self.pos, |c| self.pos + c.len_utf8());
+        let span = Span { end, ..self.here() };
+        Located::new((self.invalid)(format!("expected {}", expected)), Some(span))
+    }
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '$' || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_tracks_the_span_of_every_value() -> Result<(), Located<ParseError>> {
+        let json = parse("[1,\n  {\"a\": \"\\u00e9\\ud83d\\ude00\"}]")?;
+        let items = match &json.kind {
+            JsonKind::Array(items) => items,
+            _ => panic!("expected an array"),
+        };
+
+        assert_eq!((json.span.start, json.span.end), (0, 34));
+        assert_eq!((items[1].span.line, items[1].span.column), (2, 3));
+        assert_eq!(
+            items[1].to_string(),
+            "{\"a\":\"\u{e9}\u{1f600}\"}".to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_position_of_syntax_errors() {
+        let error = parse("[1,\n 2 3]").expect_err("missing comma");
+
+        assert!(matches!(error.error, ParseError::InvalidJson(_)));
+        assert_eq!(error.span.map(|s| (s.line, s.column)), Some((2, 4)));
+    }
+
+    #[test]
+    fn it_reads_json5() -> Result<(), Located<ParseError>> {
+        let json5 = parse_json5(
+            "// Comments, trailing commas and relaxed literals.\n\
+             ['$call', /* callee */ [\"$ref\", 'f'],\n\
+              {to: 'it\\'s', $n: +.5, 'x': 0x1F, y: 5., z: -1e3,},\n\
+              'a\\\n b\\x41\\v',]",
+        )?;
+        assert_eq!(
+            json5.to_string(),
+            r#"["$call",["$ref","f"],{"to":"it's","$n":0.5,"x":31,"y":5,"z":-1e3},"a bA\u000b"]"#
+        );
+
+        let error = parse_json5("{a: 1,\n b: 'open\n}").expect_err("unterminated string");
+        assert!(matches!(error.error, ParseError::InvalidJson5(_)));
+        assert_eq!(error.span.map(|s| s.line), Some(2));
+        assert!(parse("{a: 1}").is_err());
+
+        let error = parse_json5("['$add', 1, 2] /* oops").expect_err("unterminated comment");
+        assert!(matches!(&error.error, ParseError::InvalidJson5(m) if m == "expected `*/`"));
+        Ok(())
+    }
+}
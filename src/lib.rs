@@ -1,6 +1,19 @@
 pub mod ast;
+pub mod binary;
+pub mod datetime;
+pub mod decompile;
+pub mod emit;
 mod environment;
+pub mod forms;
 pub mod interpreter;
-mod jir;
+pub mod jir;
+pub mod json;
+pub mod monkey;
+pub mod query;
 pub mod repl;
+pub mod sequence;
+pub mod sexpr;
+pub mod span;
+pub mod typecheck;
 pub mod value;
+pub mod yaml;
@@ -1,15 +1,46 @@
+use std::io::Read;
+use std::process;
+
 use rustyline::Editor;
 
+use json_monkey_rs::decompile::decompile;
+use json_monkey_rs::jir::{Format, JirParser};
 use json_monkey_rs::repl::Repl;
 
+const USAGE: &str = "usage: json-monkey-rs [decompile [FILE]]";
+
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args.as_slice() {
+        [] => repl(),
+        ["decompile"] => decompile_source(None),
+        ["decompile", path] => decompile_source(Some(path)),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn repl() {
     let mut rl = Editor::<()>::new();
     let mut repl = Repl::new();
     loop {
         let readline = rl.readline(">> ");
         match readline {
             Ok(line) => {
-                println!("{:?}", repl.eval_str(&line));
+                let result = repl.eval_str(&line);
+                for warning in repl.warnings() {
+                    println!("type warning: {}", warning);
+                }
+                match result {
+                    Ok(value) => match repl.last_type() {
+                        Some(ty) => println!("{:?} : {}", value, ty),
+                        None => println!("{:?}", value),
+                    },
+                    Err(e) => println!("{}", repl.render(&e)),
+                }
             }
             Err(e) => {
                 println!("{:?}", e);
@@ -18,3 +49,29 @@ fn main() {
         }
     }
 }
+
+/// Prints the JIR program in the file, or JSON on standard input, as pseudo-code.
+/// The file's extension picks its format, JSON by default.
+fn decompile_source(path: Option<&str>) {
+    let format = path
+        .and_then(|path| Format::from_path(path.as_ref()))
+        .unwrap_or(Format::Json);
+    let source = match path {
+        Some(path) => std::fs::read_to_string(path),
+        None => {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source).map(|_| source)
+        }
+    };
+    let source = source.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    match JirParser::parse_as(&source, format) {
+        Ok(node) => println!("{}", decompile(&node)),
+        Err(e) => {
+            eprintln!("{}", e.render(&source));
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,785 @@
+//! A Monkey-like text syntax that compiles to the same `AstNode` trees as JIR.
+//!
+//! ```text
+//! let countdown = fn(n) {
+//!     while (n > 0) { n = n - 1 };
+//!     n
+//! };
+//! countdown(3) == 0 && !false
+//! ```
+//!
+//! Statements are separated by `;`. A block, or a whole program, with a single
+//! statement is that statement, and otherwise a `$do`. `let` defines in the current
+//! scope like `$let`, and `name = value` assigns like `$bind`.
+
+use std::rc::Rc;
+
+use crate::ast::{AstKind, AstNode, Ident, Param};
+use crate::json::MAX_DEPTH;
+use crate::span::{Located, Span};
+use crate::typecheck::Type;
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxError {
+    UnexpectedChar(char),
+    UnterminatedString,
+    InvalidNumber(String),
+    InvalidTypeAnnotation(String),
+    UnexpectedToken {
+        expected: String,
+        found: String,
+    },
+    /// Expressions nested more than `MAX_DEPTH` levels deep.
+    TooDeeplyNested,
+}
+
+type ParseResult<T> = Result<T, Located<SyntaxError>>;
+
+pub struct MonkeyParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How many expressions enclose the one being parsed.
+    depth: usize,
+}
+
+impl MonkeyParser {
+    pub fn parse(source: &str) -> ParseResult<AstNode> {
+        let mut parser = Self {
+            tokens: Lexer::new(source).tokenize()?,
+            pos: 0,
+            depth: 0,
+        };
+        let start = parser.peek().span;
+        let statements = parser.statements(&TokenKind::Eof)?;
+        parser.expect(TokenKind::Eof)?;
+        Ok(parser.sequence(statements, start))
+    }
+
+    fn statements(&mut self, end: &TokenKind) -> ParseResult<Vec<AstNode>> {
+        let mut statements = Vec::new();
+        while self.peek().kind != *end {
+            statements.push(self.statement()?);
+            if !self.eat(&TokenKind::Semicolon) && self.peek().kind != *end {
+                return Err(self.unexpected("`;`"));
+            }
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> ParseResult<AstNode> {
+        if self.peek().kind != TokenKind::Let {
+            return self.expression(Precedence::Lowest);
+        }
+        let start = self.next().span;
+        let ident = self.ident()?;
+        let ty = if self.eat(&TokenKind::Colon) {
+            let token = self.next();
+            match &token.kind {
+                TokenKind::Ident(name) => match Type::parse(name) {
+                    Some(ty) => Some(ty),
+                    None => {
+                        return Err(Located::new(
+                            SyntaxError::InvalidTypeAnnotation(name.clone()),
+                            Some(token.span),
+                        ))
+                    }
+                },
+                _ => return Err(self.unexpected_at(&token, "a type")),
+            }
+        } else {
+            None
+        };
+        self.expect(TokenKind::Assign)?;
+        let value = self.expression(Precedence::Lowest)?;
+        let span = self.span_from(start);
+        Ok(AstNode::new(
+            AstKind::Let(ident, ty, Box::new(value)),
+            Some(span),
+        ))
+    }
+
+    /// `{ statement; ... }`
+    fn block(&mut self) -> ParseResult<AstNode> {
+        let start = self.expect(TokenKind::LBrace)?.span;
+        let statements = self.statements(&TokenKind::RBrace)?;
+        self.expect(TokenKind::RBrace)?;
+        Ok(self.sequence(statements, start))
+    }
+
+    fn sequence(&self, mut statements: Vec<AstNode>, start: Span) -> AstNode {
+        if statements.len() == 1 {
+            return statements.remove(0);
+        }
+        AstNode::new(AstKind::Do(statements), Some(self.span_from(start)))
+    }
+
+    fn expression(&mut self, precedence: Precedence) -> ParseResult<AstNode> {
+        if self.depth == MAX_DEPTH {
+            return Err(Located::new(
+                SyntaxError::TooDeeplyNested,
+                Some(self.peek().span),
+            ));
+        }
+        self.depth += 1;
+        let expression = self.operations(precedence);
+        self.depth -= 1;
+        expression
+    }
+
+    /// An operand followed by the operators that bind tighter than `precedence`.
+    fn operations(&mut self, precedence: Precedence) -> ParseResult<AstNode> {
+        let mut lhs = self.prefix()?;
+        while precedence < Precedence::of(&self.peek().kind) {
+            lhs = self.infix(lhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn prefix(&mut self) -> ParseResult<AstNode> {
+        let token = self.next();
+        let start = token.span;
+        let kind = match token.kind {
+            TokenKind::Number(n) => AstKind::Literal(Value::Number(n)),
+            TokenKind::String(s) => AstKind::Literal(Value::String(s)),
+            TokenKind::True => AstKind::Literal(Value::Boolean(true)),
+            TokenKind::False => AstKind::Literal(Value::Boolean(false)),
+            TokenKind::Null => AstKind::Literal(Value::Null),
+            TokenKind::Ident(name) => AstKind::Ident(Ident(name)),
+            TokenKind::Bang => AstKind::Not(Box::new(self.expression(Precedence::Prefix)?)),
+            TokenKind::Minus => match self.expression(Precedence::Prefix)? {
+                AstNode {
+                    kind: AstKind::Literal(Value::Number(n)),
+                    ..
+                } => AstKind::Literal(Value::Number(-n)),
+                operand => AstKind::Sub(
+                    AstKind::Literal(Value::Number(0.0)).into(),
+                    Box::new(operand),
+                ),
+            },
+            TokenKind::LParen => {
+                let inner = self.expression(Precedence::Lowest)?;
+                self.expect(TokenKind::RParen)?;
+                return Ok(inner);
+            }
+            TokenKind::LBracket => {
+                AstKind::Array(self.list(TokenKind::RBracket, Self::any_expression)?)
+            }
+            TokenKind::If => {
+                let cond = self.condition()?;
+                let true_branch = self.block()?;
+                let false_branch = if self.eat(&TokenKind::Else) {
+                    Some(Box::new(if self.peek().kind == TokenKind::If {
+                        self.expression(Precedence::Lowest)?
+                    } else {
+                        self.block()?
+                    }))
+                } else {
+                    None
+                };
+                AstKind::If(Box::new(cond), Box::new(true_branch), false_branch)
+            }
+            TokenKind::While => {
+                let cond = self.condition()?;
+                AstKind::While(Box::new(cond), Box::new(self.block()?))
+            }
+            TokenKind::Fn => {
+                self.expect(TokenKind::LParen)?;
+                let params = self.list(TokenKind::RParen, |parser| {
+                    Ok(Param {
+                        ident: parser.ident()?,
+                        ty: None,
+                        default: None,
+                        rest: false,
+                    })
+                })?;
+                AstKind::Fn(params, Rc::new(self.block()?))
+            }
+            _ => return Err(self.unexpected_at(&token, "an expression")),
+        };
+        Ok(AstNode::new(kind, Some(self.span_from(start))))
+    }
+
+    fn infix(&mut self, lhs: AstNode) -> ParseResult<AstNode> {
+        let start = lhs.span.unwrap_or_else(|| self.peek().span);
+        let token = self.next();
+        let precedence = Precedence::of(&token.kind);
+        let kind = match token.kind {
+            TokenKind::LParen => {
+                let args = self.list(TokenKind::RParen, Self::any_expression)?;
+                AstKind::Call(Box::new(lhs), args, Vec::new())
+            }
+            TokenKind::Dot => match self.next() {
+                Token {
+                    kind: TokenKind::Ident(name),
+                    ..
+                } => AstKind::GetField(Box::new(lhs), name),
+                token => return Err(self.unexpected_at(&token, "a field name")),
+            },
+            TokenKind::Assign => {
+                let ident = match lhs.kind {
+                    AstKind::Ident(ident) => ident,
+                    _ => {
+                        return Err(Located::new(
+                            SyntaxError::UnexpectedToken {
+                                expected: "an identifier before `=`".into(),
+                                found: "an expression".into(),
+                            },
+                            lhs.span,
+                        ))
+                    }
+                };
+                // Right associative: `a = b = 1` assigns `b` first.
+                let value = self.expression(Precedence::Lowest)?;
+                AstKind::Bind(ident, Box::new(value))
+            }
+            kind => {
+                let op: fn(Box<AstNode>, Box<AstNode>) -> AstKind = match kind {
+                    TokenKind::Plus => AstKind::Add,
+                    TokenKind::Minus => AstKind::Sub,
+                    TokenKind::And => AstKind::And,
+                    TokenKind::Or => AstKind::Or,
+                    TokenKind::Eq => AstKind::Eq,
+                    TokenKind::NotEq => AstKind::NotEq,
+                    TokenKind::Lt => AstKind::Lt,
+                    TokenKind::Lte => AstKind::Lte,
+                    TokenKind::Gt => AstKind::Gt,
+                    _ => AstKind::Gte,
+                };
+                let rhs = self.expression(precedence)?;
+                op(Box::new(lhs), Box::new(rhs))
+            }
+        };
+        Ok(AstNode::new(kind, Some(self.span_from(start))))
+    }
+
+    /// `(expression)` after `if` and `while`.
+    fn condition(&mut self) -> ParseResult<AstNode> {
+        self.expect(TokenKind::LParen)?;
+        let cond = self.expression(Precedence::Lowest)?;
+        self.expect(TokenKind::RParen)?;
+        Ok(cond)
+    }
+
+    fn any_expression(&mut self) -> ParseResult<AstNode> {
+        self.expression(Precedence::Lowest)
+    }
+
+    /// Comma separated items up to `end`, after the opening token. A trailing comma
+    /// is allowed.
+    fn list<T>(
+        &mut self,
+        end: TokenKind,
+        item: impl Fn(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<Vec<T>> {
+        let mut items = Vec::new();
+        while !self.eat(&end) {
+            items.push(item(self)?);
+            if !self.eat(&TokenKind::Comma) {
+                self.expect(end)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn ident(&mut self) -> ParseResult<Ident> {
+        match self.next() {
+            Token {
+                kind: TokenKind::Ident(name),
+                ..
+            } => Ok(Ident(name)),
+            token => Err(self.unexpected_at(&token, "an identifier")),
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    /// The next token. The lexer ends every stream with `Eof`, which is never
+    /// consumed.
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek().kind == *kind {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> ParseResult<Token> {
+        if self.peek().kind == kind {
+            Ok(self.next())
+        } else {
+            Err(self.unexpected(&kind.to_string()))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Located<SyntaxError> {
+        self.unexpected_at(self.peek(), expected)
+    }
+
+    fn unexpected_at(&self, token: &Token, expected: &str) -> Located<SyntaxError> {
+        Located::new(
+            SyntaxError::UnexpectedToken {
+                expected: expected.into(),
+                found: token.kind.to_string(),
+            },
+            Some(token.span),
+        )
+    }
+
+    /// From the start of `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        let end = match self.pos {
+            0 => start.end,
+            pos => self.tokens[pos - 1].span.end,
+        };
+        Span {
+            end: end.max(start.end),
+            ..start
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    Lowest,
+    Assign,
+    Or,
+    And,
+    Equals,
+    Compare,
+    Sum,
+    Prefix,
+    Call,
+}
+
+impl Precedence {
+    /// How tightly a token binds as an infix operator.
+    fn of(kind: &TokenKind) -> Self {
+        match kind {
+            TokenKind::Assign => Self::Assign,
+            TokenKind::Or => Self::Or,
+            TokenKind::And => Self::And,
+            TokenKind::Eq | TokenKind::NotEq => Self::Equals,
+            TokenKind::Lt | TokenKind::Lte | TokenKind::Gt | TokenKind::Gte => Self::Compare,
+            TokenKind::Plus | TokenKind::Minus => Self::Sum,
+            TokenKind::LParen | TokenKind::Dot => Self::Call,
+            _ => Self::Lowest,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Number(f64),
+    String(String),
+    Ident(String),
+
+    Let,
+    Fn,
+    If,
+    Else,
+    While,
+    True,
+    False,
+    Null,
+
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Comma,
+    Semicolon,
+    Colon,
+    Dot,
+
+    Assign,
+    Plus,
+    Minus,
+    Bang,
+    Eq,
+    NotEq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    And,
+    Or,
+
+    Eof,
+}
+
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            TokenKind::Number(n) => return write!(f, "`{}`", n),
+            TokenKind::String(s) => return write!(f, "{:?}", s),
+            TokenKind::Ident(name) => return write!(f, "`{}`", name),
+            TokenKind::Let => "let",
+            TokenKind::Fn => "fn",
+            TokenKind::If => "if",
+            TokenKind::Else => "else",
+            TokenKind::While => "while",
+            TokenKind::True => "true",
+            TokenKind::False => "false",
+            TokenKind::Null => "null",
+            TokenKind::LParen => "(",
+            TokenKind::RParen => ")",
+            TokenKind::LBrace => "{",
+            TokenKind::RBrace => "}",
+            TokenKind::LBracket => "[",
+            TokenKind::RBracket => "]",
+            TokenKind::Comma => ",",
+            TokenKind::Semicolon => ";",
+            TokenKind::Colon => ":",
+            TokenKind::Dot => ".",
+            TokenKind::Assign => "=",
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Bang => "!",
+            TokenKind::Eq => "==",
+            TokenKind::NotEq => "!=",
+            TokenKind::Lt => "<",
+            TokenKind::Lte => "<=",
+            TokenKind::Gt => ">",
+            TokenKind::Gte => ">=",
+            TokenKind::And => "&&",
+            TokenKind::Or => "||",
+            TokenKind::Eof => return write!(f, "end of input"),
+        };
+        write!(f, "`{}`", text)
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            pos: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    fn tokenize(mut self) -> ParseResult<Vec<Token>> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_trivia();
+            let start = self.here();
+            let kind = match self.bump() {
+                None => {
+                    tokens.push(Token {
+                        kind: TokenKind::Eof,
+                        span: start,
+                    });
+                    return Ok(tokens);
+                }
+                Some(c) => self.token(c, start)?,
+            };
+            tokens.push(Token {
+                kind,
+                span: Span {
+                    end: self.pos,
+                    ..start
+                },
+            });
+        }
+    }
+
+    fn token(&mut self, c: char, start: Span) -> ParseResult<TokenKind> {
+        Ok(match c {
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '{' => TokenKind::LBrace,
+            '}' => TokenKind::RBrace,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            ',' => TokenKind::Comma,
+            ';' => TokenKind::Semicolon,
+            ':' => TokenKind::Colon,
+            '.' => TokenKind::Dot,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '=' if self.eat('=') => TokenKind::Eq,
+            '=' => TokenKind::Assign,
+            '!' if self.eat('=') => TokenKind::NotEq,
+            '!' => TokenKind::Bang,
+            '<' if self.eat('=') => TokenKind::Lte,
+            '<' => TokenKind::Lt,
+            '>' if self.eat('=') => TokenKind::Gte,
+            '>' => TokenKind::Gt,
+            '&' if self.eat('&') => TokenKind::And,
+            '|' if self.eat('|') => TokenKind::Or,
+            '"' => TokenKind::String(self.string(start)?),
+            '0'..='9' => {
+                self.take_while(|c| c.is_ascii_digit() || c == '.');
+                let text = &self.source[start.start..self.pos];
+                match text.parse::<f64>() {
+                    Ok(n) => TokenKind::Number(n),
+                    Err(_) => {
+                        return Err(Located::new(
+                            SyntaxError::InvalidNumber(text.into()),
+                            Some(Span {
+                                end: self.pos,
+                                ..start
+                            }),
+                        ))
+                    }
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                self.take_while(|c| c.is_alphanumeric() || c == '_');
+                match &self.source[start.start..self.pos] {
+                    "let" => TokenKind::Let,
+                    "fn" => TokenKind::Fn,
+                    "if" => TokenKind::If,
+                    "else" => TokenKind::Else,
+                    "while" => TokenKind::While,
+                    "true" => TokenKind::True,
+                    "false" => TokenKind::False,
+                    "null" => TokenKind::Null,
+                    name => TokenKind::Ident(name.into()),
+                }
+            }
+            c => {
+                return Err(Located::new(
+                    SyntaxError::UnexpectedChar(c),
+                    Some(Span {
+                        end: self.pos,
+                        ..start
+                    }),
+                ))
+            }
+        })
+    }
+
+    /// The rest of a string literal after its opening quote. Supports the `\"`,
+    /// `\\`, `\n` and `\t` escapes.
+    fn string(&mut self, start: Span) -> ParseResult<String> {
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c @ '"') | Some(c @ '\\') => s.push(c),
+                    _ => break,
+                },
+                Some('\n') | None => break,
+                Some(c) => s.push(c),
+            }
+        }
+        Err(Located::new(
+            SyntaxError::UnterminatedString,
+            Some(Span {
+                end: self.pos,
+                ..start
+            }),
+        ))
+    }
+
+    /// Whitespace and `//` comments.
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.peek() {
+                Some('\n') => {
+                    self.pos += 1;
+                    self.line += 1;
+                    self.line_start = self.pos;
+                }
+                Some(c) if c.is_whitespace() => self.pos += c.len_utf8(),
+                Some('/') if self.source[self.pos..].starts_with("//") => {
+                    self.take_while(|c| c != '\n');
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) {
+        while let Some(c) = self.peek() {
+            if !pred(c) {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn here(&self) -> Span {
+        Span {
+            start: self.pos,
+            end: self.pos,
+            line: self.line,
+            column: self.source[self.line_start..self.pos].chars().count() + 1,
+            source: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::jir::JirParser;
+
+    fn assert_equivalent(monkey: &str, jir: &str) {
+        let from_monkey = MonkeyParser::parse(monkey)
+            .unwrap_or_else(|e| panic!("{}\n{}", monkey, e.render(monkey)));
+        let from_jir = JirParser::parse(jir).unwrap_or_else(|e| panic!("{}", e.render(jir)));
+        assert_eq!(
+            format!("{:?}", from_monkey),
+            format!("{:?}", from_jir),
+            "{}",
+            monkey
+        );
+    }
+
+    #[test]
+    fn it_parses_the_same_trees_as_jir() {
+        let cases = [
+            ("1 + 2 - 3", r#"["$sub", ["$add", 1, 2], 3]"#),
+            ("1 + 2 + 3", r#"["$add", 1, 2, 3]"#),
+            (
+                r#"!true || x && "s" == null"#,
+                r#"["$or", ["$not", true], ["$and", ["$ref", "x"], ["$eq", "s", null]]]"#,
+            ),
+            (
+                "a < b != (c >= -2)",
+                r#"["$notEq", ["$lt", ["$ref", "a"], ["$ref", "b"]], ["$gte", ["$ref", "c"], -2]]"#,
+            ),
+            ("-x", r#"["$sub", 0, ["$ref", "x"]]"#),
+            (
+                "let x: number = 1; x = x + 1",
+                r#"["$do", ["$let", "x", "number", 1], ["$bind", "x", ["$add", ["$ref", "x"], 1]]]"#,
+            ),
+            (
+                "let x = 1; while (x != 0) { x = x - 1 }",
+                r#"["$do",
+                    ["$let", "x", 1],
+                    ["$while", ["$notEq", ["$ref", "x"], 0],
+                        ["$bind", "x", ["$sub", ["$ref", "x"], 1]]]]"#,
+            ),
+            (
+                "if (a) { 1 } else if (b) { 2; 3 } else { [4, 5,] }",
+                r#"["$if", ["$ref", "a"], 1,
+                    ["$if", ["$ref", "b"], ["$do", 2, 3], ["$array", 4, 5]]]"#,
+            ),
+            (
+                "let add = fn(a, b) { a + b }; add(1, 2).total",
+                r#"["$do",
+                    ["$let", "add", ["$fn", ["a", "b"], ["$add", ["$ref", "a"], ["$ref", "b"]]]],
+                    ["$field", ["$call", ["$ref", "add"], 1, 2], "total"]]"#,
+            ),
+            (
+                "// comments are ignored\nf()(\"a\\\"b\")",
+                r#"["$call", ["$call", ["$ref", "f"]], "a\"b"]"#,
+            ),
+        ];
+        for (monkey, jir) in cases.iter() {
+            assert_equivalent(monkey, jir);
+        }
+    }
+
+    #[test]
+    fn it_evaluates_monkey_programs() -> Result<(), Located<SyntaxError>> {
+        let node = MonkeyParser::parse(
+            "let countdown = fn(n) {
+                 while (n > 0) { n = n - 1 };
+                 n
+             };
+             let sign = fn(n) { if (n < 0) { -1 } else if (n == 0) { 0 } else { 1 } };
+             [countdown(3) == 0 && !false, sign(-5), sign(countdown(2))]",
+        )?;
+        assert_eq!(
+            Interpreter::new().eval(&node).ok(),
+            Some(Value::Array(vec![
+                Value::Boolean(true),
+                Value::Number(-1.0),
+                Value::Number(0.0),
+            ]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_syntax_errors_with_positions() {
+        let source = "let x = 1;\nwhile (x { x = x - 1 }";
+        let error = MonkeyParser::parse(source).expect_err("missing `)`");
+        assert_eq!(
+            error.error,
+            SyntaxError::UnexpectedToken {
+                expected: "`)`".into(),
+                found: "`{`".into()
+            }
+        );
+        assert_eq!(error.span.map(|s| (s.line, s.column)), Some((2, 10)));
+
+        let error = MonkeyParser::parse("1 = 2").expect_err("not assignable");
+        assert_eq!(error.span.map(|s| (s.start, s.end)), Some((0, 1)));
+        assert!(matches!(
+            MonkeyParser::parse("\"open").map_err(|e| e.error),
+            Err(SyntaxError::UnterminatedString)
+        ));
+        assert!(matches!(
+            MonkeyParser::parse("1 # 2").map_err(|e| e.error),
+            Err(SyntaxError::UnexpectedChar('#'))
+        ));
+        for deep in [
+            "(".repeat(100_000),
+            "!".repeat(100_000),
+            "-".repeat(100_000),
+        ] {
+            assert!(matches!(
+                MonkeyParser::parse(&deep).map_err(|e| e.error),
+                Err(SyntaxError::TooDeeplyNested)
+            ));
+        }
+        assert!(MonkeyParser::parse(&format!("{}1{}", "(".repeat(100), ")".repeat(100))).is_ok());
+    }
+}
@@ -0,0 +1,554 @@
+//! The path/filter language of `$query`.
+//!
+//! A query is a chain of steps applied to a value, each step turning every current
+//! match into zero or more new matches:
+//!
+//! - `.name` or `["name"]` selects an object key (or struct field),
+//! - `.*` or `[*]` selects every element of an array or value of an object,
+//! - `[1]`, `[-1]` select an array element, counting from the end when negative,
+//! - `[1:3]`, `[:-1]` select a slice, with Python-style bounds,
+//! - `..` selects the current value and all of its descendants, so `..id` finds
+//!   every `id` key at any depth,
+//! - `[?(@.qty > 1 && @.status == "open")]` keeps array elements (or object values)
+//!   for which the predicate holds. `@` is the candidate and may be followed by
+//!   steps; a bare `@.field` tests that the field exists and isn't `null`/`false`.
+//!
+//! The query may start with `$` or `.`; `.` alone (or an empty query) is the value
+//! itself.
+
+use std::cmp::Ordering;
+
+use crate::interpreter::EvalError;
+use crate::value::Value;
+
+#[derive(Debug, PartialEq)]
+pub struct Query(Vec<Step>);
+
+#[derive(Debug, PartialEq)]
+enum Step {
+    Key(String),
+    Wildcard,
+    Index(i64),
+    Slice(Option<i64>, Option<i64>),
+    Descendants,
+    Filter(Predicate),
+}
+
+#[derive(Debug, PartialEq)]
+enum Predicate {
+    Or(Box<Predicate>, Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+    Exists(Query),
+    Compare(Operand, CompareOp, Operand),
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    Current(Query),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Query, EvalError> {
+        let mut parser = Parser {
+            source,
+            chars: source.char_indices().collect(),
+            pos: 0,
+        };
+        parser.eat('$');
+        if parser.rest() == "." {
+            parser.pos += 1;
+        }
+        let query = parser.steps()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("a step"));
+        }
+        Ok(query)
+    }
+
+    /// Every match, in document order.
+    pub fn evaluate(&self, value: &Value) -> Vec<Value> {
+        self.matches(value).into_iter().cloned().collect()
+    }
+
+    /// Matches are borrowed from `value`, so that `..` doesn't copy every subtree.
+    fn matches<'v>(&self, value: &'v Value) -> Vec<&'v Value> {
+        let mut matches = vec![value];
+        for step in &self.0 {
+            let mut next = Vec::new();
+            for value in matches {
+                step.apply(value, &mut next);
+            }
+            matches = next;
+        }
+        matches
+    }
+}
+
+impl Step {
+    fn apply<'v>(&self, value: &'v Value, found: &mut Vec<&'v Value>) {
+        match (self, value) {
+            (Step::Key(key), Value::Object(entries)) => found.extend(entries.get(key)),
+            (Step::Key(key), Value::Struct(instance)) => found.extend(instance.get(key)),
+            (Step::Wildcard, _) => found.extend(children(value)),
+            (Step::Index(index), Value::Array(items)) => {
+                found.extend(resolve_index(*index, items.len()).and_then(|i| items.get(i)))
+            }
+            (Step::Slice(start, end), Value::Array(items)) => {
+                let len = items.len();
+                let start = start.map_or(0, |i| clamp_index(i, len));
+                let end = end.map_or(len, |i| clamp_index(i, len));
+                found.extend(items.get(start..end.max(start)).unwrap_or_default())
+            }
+            (Step::Descendants, _) => collect_descendants(value, found),
+            (Step::Filter(predicate), _) => {
+                found.extend(children(value).filter(|child| predicate.holds(child)))
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Predicate {
+    fn holds(&self, candidate: &Value) -> bool {
+        match self {
+            Predicate::Or(lhs, rhs) => lhs.holds(candidate) || rhs.holds(candidate),
+            Predicate::And(lhs, rhs) => lhs.holds(candidate) && rhs.holds(candidate),
+            Predicate::Not(inner) => !inner.holds(candidate),
+            Predicate::Exists(query) => query
+                .matches(candidate)
+                .first()
+                .is_some_and(|v| !matches!(v, Value::Null | Value::Boolean(false))),
+            Predicate::Compare(lhs, op, rhs) => {
+                match (lhs.resolve(candidate), rhs.resolve(candidate)) {
+                    (Some(lhs), Some(rhs)) => op.accepts(lhs, rhs),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+impl Operand {
+    /// The first match of a relative query, or the literal.
+    fn resolve<'a>(&'a self, candidate: &'a Value) -> Option<&'a Value> {
+        match self {
+            Operand::Current(query) => query.matches(candidate).into_iter().next(),
+            Operand::Literal(value) => Some(value),
+        }
+    }
+}
+
+impl CompareOp {
+    fn accepts(self, lhs: &Value, rhs: &Value) -> bool {
+        let ordering = match (lhs, rhs) {
+            (Value::Number(l), Value::Number(r)) => l.partial_cmp(r),
+            (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+            (Value::DateTime(l), Value::DateTime(r)) => Some(l.cmp(r)),
+            _ => None,
+        };
+        match self {
+            CompareOp::Eq => lhs == rhs,
+            CompareOp::NotEq => lhs != rhs,
+            CompareOp::Lt => ordering.is_some_and(Ordering::is_lt),
+            CompareOp::Lte => ordering.is_some_and(Ordering::is_le),
+            CompareOp::Gt => ordering.is_some_and(Ordering::is_gt),
+            CompareOp::Gte => ordering.is_some_and(Ordering::is_ge),
+        }
+    }
+}
+
+fn children(value: &Value) -> Box<dyn Iterator<Item = &Value> + '_> {
+    match value {
+        Value::Array(items) => Box::new(items.iter()),
+        Value::Object(entries) => Box::new(entries.values()),
+        Value::Struct(instance) => Box::new(instance.fields.iter().map(|(_, v)| v)),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn collect_descendants<'v>(value: &'v Value, found: &mut Vec<&'v Value>) {
+    found.push(value);
+    for child in children(value) {
+        collect_descendants(child, found);
+    }
+}
+
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    if index < 0 {
+        len.checked_sub(index.unsigned_abs() as usize)
+    } else {
+        Some(index as usize)
+    }
+}
+
+fn clamp_index(index: i64, len: usize) -> usize {
+    resolve_index(index, len).unwrap_or(0).min(len)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn steps(&mut self) -> Result<Query, EvalError> {
+        let mut steps = Vec::new();
+        loop {
+            match self.peek() {
+                Some('.') if self.rest().starts_with("..") => {
+                    self.pos += 2;
+                    steps.push(Step::Descendants);
+                    match self.peek() {
+                        Some('[') => {}
+                        Some('*') => {
+                            self.pos += 1;
+                            steps.push(Step::Wildcard);
+                        }
+                        Some(c) if is_name_char(c) => steps.push(Step::Key(self.name())),
+                        _ => {}
+                    }
+                }
+                Some('.') => {
+                    self.pos += 1;
+                    if self.eat('*') {
+                        steps.push(Step::Wildcard);
+                    } else {
+                        let name = self.name();
+                        if name.is_empty() {
+                            return Err(self.error("a key"));
+                        }
+                        steps.push(Step::Key(name));
+                    }
+                }
+                Some('[') => {
+                    self.pos += 1;
+                    steps.push(self.selector()?);
+                    self.skip_whitespace();
+                    self.expect(']')?;
+                }
+                _ => return Ok(Query(steps)),
+            }
+        }
+    }
+
+    fn selector(&mut self) -> Result<Step, EvalError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                Ok(Step::Wildcard)
+            }
+            Some('"') | Some('\'') => Ok(Step::Key(self.string()?)),
+            Some('?') => {
+                self.pos += 1;
+                self.skip_whitespace();
+                self.expect('(')?;
+                let predicate = self.or()?;
+                self.skip_whitespace();
+                self.expect(')')?;
+                Ok(Step::Filter(predicate))
+            }
+            _ => {
+                let start = self.integer()?;
+                self.skip_whitespace();
+                if !self.eat(':') {
+                    return start
+                        .map(Step::Index)
+                        .ok_or_else(|| self.error("a selector"));
+                }
+                self.skip_whitespace();
+                Ok(Step::Slice(start, self.integer()?))
+            }
+        }
+    }
+
+    fn or(&mut self) -> Result<Predicate, EvalError> {
+        let mut lhs = self.and()?;
+        while self.eat_token("||") {
+            lhs = Predicate::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Predicate, EvalError> {
+        let mut lhs = self.unary()?;
+        while self.eat_token("&&") {
+            lhs = Predicate::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Predicate, EvalError> {
+        self.skip_whitespace();
+        if self.rest().starts_with('!') && !self.rest().starts_with("!=") {
+            self.pos += 1;
+            return Ok(Predicate::Not(Box::new(self.unary()?)));
+        }
+        if self.eat('(') {
+            let inner = self.or()?;
+            self.skip_whitespace();
+            self.expect(')')?;
+            return Ok(inner);
+        }
+        let lhs = self.operand()?;
+        let op = [
+            ("==", CompareOp::Eq),
+            ("!=", CompareOp::NotEq),
+            ("<=", CompareOp::Lte),
+            (">=", CompareOp::Gte),
+            ("<", CompareOp::Lt),
+            (">", CompareOp::Gt),
+        ]
+        .iter()
+        .find(|(token, _)| self.eat_token(token));
+        match (lhs, op) {
+            (lhs, Some((_, op))) => Ok(Predicate::Compare(lhs, *op, self.operand()?)),
+            (Operand::Current(query), None) => Ok(Predicate::Exists(query)),
+            (Operand::Literal(_), None) => Err(self.error("a comparison")),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, EvalError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('@') => {
+                self.pos += 1;
+                Ok(Operand::Current(self.steps()?))
+            }
+            Some('"') | Some('\'') => Ok(Operand::Literal(Value::String(self.string()?))),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+                {
+                    self.pos += 1;
+                }
+                let number = self.slice(start, self.pos);
+                number
+                    .parse()
+                    .map(|n| Operand::Literal(Value::Number(n)))
+                    .map_err(|_| self.error_at(start, "a number"))
+            }
+            _ => match self.name().as_str() {
+                "true" => Ok(Operand::Literal(Value::Boolean(true))),
+                "false" => Ok(Operand::Literal(Value::Boolean(false))),
+                "null" => Ok(Operand::Literal(Value::Null)),
+                _ => Err(self.error("an operand")),
+            },
+        }
+    }
+
+    fn integer(&mut self) -> Result<Option<i64>, EvalError> {
+        let start = self.pos;
+        self.eat('-');
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        match self.slice(start, self.pos) {
+            "" => Ok(None),
+            digits => digits
+                .parse()
+                .map(Some)
+                .map_err(|_| self.error_at(start, "an integer")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, EvalError> {
+        let start = self.pos;
+        let quote = self.peek().expect("caller saw a quote");
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error_at(start, "a closing quote")),
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    s.extend(self.peek());
+                    self.pos += 1;
+                }
+                Some(c) => {
+                    s.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(is_name_char) {
+            self.pos += 1;
+        }
+        self.slice(start, self.pos).to_string()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|(_, c)| *c)
+    }
+
+    fn offset(&self, pos: usize) -> usize {
+        self.chars.get(pos).map_or(self.source.len(), |(i, _)| *i)
+    }
+
+    fn slice(&self, start: usize, end: usize) -> &str {
+        &self.source[self.offset(start)..self.offset(end)]
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.offset(self.pos)..]
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_token(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.chars().count();
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), EvalError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(match c {
+                ']' => "`]`",
+                ')' => "`)`",
+                _ => "`(`",
+            }))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, expected: &'static str) -> EvalError {
+        self.error_at(self.pos, expected)
+    }
+
+    fn error_at(&self, pos: usize, expected: &'static str) -> EvalError {
+        EvalError::InvalidQuery {
+            query: self.source.to_string(),
+            position: self.offset(pos),
+            expected,
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jir::JirParser;
+
+    fn data(json: &str) -> Value {
+        match JirParser::parse_json(&format!(r#"["$quote", {}]"#, json)).map(|node| node.kind) {
+            Ok(crate::ast::AstKind::Literal(value)) => value,
+            other => panic!("not a literal: {:?}", other),
+        }
+    }
+
+    fn query(source: &str, json: &str) -> Result<Vec<Value>, EvalError> {
+        Ok(Query::parse(source)?.evaluate(&data(json)))
+    }
+
+    #[test]
+    fn it_selects_keys_indices_and_slices() -> Result<(), EvalError> {
+        let doc = r#"{"items": [{"id": 1}, {"id": 2}, {"id": 3}], "a b": true}"#;
+
+        assert_eq!(query(".items[0].id", doc)?, vec![Value::Number(1.0)]);
+        assert_eq!(query("$.items[-1].id", doc)?, vec![Value::Number(3.0)]);
+        assert_eq!(
+            query(".items[1:].id", doc)?,
+            vec![Value::Number(2.0), Value::Number(3.0)]
+        );
+        assert_eq!(
+            query(".items[:-1].id", doc)?,
+            vec![Value::Number(1.0), Value::Number(2.0)]
+        );
+        assert_eq!(query(r#"["a b"]"#, doc)?, vec![Value::Boolean(true)]);
+        assert_eq!(query(".missing.id", doc)?, vec![]);
+        assert_eq!(query(".", doc)?, vec![data(doc)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_descends_recursively() -> Result<(), EvalError> {
+        let doc = r#"{"id": 1, "children": [{"id": 2, "children": [{"id": 3}]}]}"#;
+
+        assert_eq!(
+            query("..id", doc)?,
+            vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)]
+        );
+        assert_eq!(query("..children[0].id", doc)?.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_filters_with_predicates() -> Result<(), EvalError> {
+        let doc = r#"[
+            {"sku": "a", "qty": 1, "status": "open"},
+            {"sku": "b", "qty": 5, "status": "open", "gift": true},
+            {"sku": "c", "qty": 9, "status": "closed"}
+        ]"#;
+
+        let skus = |source| -> Result<Vec<Value>, EvalError> { query(source, doc) };
+        assert_eq!(
+            skus(r#"[?(@.qty > 1 && @.status == "open")].sku"#)?,
+            vec![Value::String("b".into())]
+        );
+        assert_eq!(
+            skus(r#"[?(@.qty < 2 || !(@.status != 'closed'))].sku"#)?,
+            vec![Value::String("a".into()), Value::String("c".into())]
+        );
+        assert_eq!(skus("[?(@.gift)].sku")?, vec![Value::String("b".into())]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_position_of_syntax_errors() {
+        assert!(matches!(
+            Query::parse(".items[?(@.qty >)]"),
+            Err(EvalError::InvalidQuery { position: 16, .. })
+        ));
+        assert!(matches!(
+            Query::parse(".items[0"),
+            Err(EvalError::InvalidQuery { position: 8, .. })
+        ));
+    }
+}
@@ -1,38 +1,254 @@
 use crate::interpreter::{EvalError, Interpreter};
 use crate::jir::{JirParser, ParseError};
+use crate::span::{Located, Span};
+use crate::typecheck::{Type, TypeChecker, TypeError};
 use crate::value::Value;
 
+/// Evaluates inputs one after another in the same environment. Inputs with type
+/// errors that are certain to happen are rejected; other type errors are kept as
+/// warnings and the input runs anyway.
 pub struct Repl {
     interpreter: Interpreter,
+    checker: TypeChecker,
+    definite: TypeChecker,
+    last_type: Option<Type>,
+    warnings: Vec<TypeError>,
+    /// Every input so far. Spans point into them by `Span::source`, as a function
+    /// may fail on a later line than it was defined on.
+    inputs: Vec<String>,
 }
 
 impl Repl {
     pub fn new() -> Self {
         Self {
             interpreter: Interpreter::new(),
+            checker: TypeChecker::new(),
+            definite: TypeChecker::definite(),
+            last_type: None,
+            warnings: Vec::new(),
+            inputs: Vec::new(),
         }
     }
 
     pub fn eval_str(&mut self, s: &str) -> Result<Value, ReplError> {
-        let node = JirParser::parse_json(s)?;
-        Ok(self.interpreter.eval(&node)?)
+        self.warnings.clear();
+        let mut parser = JirParser::default();
+        parser.set_source(self.inputs.len());
+        self.inputs.push(s.to_string());
+        let node = parser.read(s)?;
+        self.definite.check(&node)?;
+        let (inference, warnings) = self.checker.infer_all(&node);
+        self.last_type = Some(inference.ty);
+        self.warnings = warnings;
+        Ok(self.interpreter.eval_located(&node)?)
+    }
+
+    /// The inferred type of the last input that passed the type checker.
+    pub fn last_type(&self) -> Option<&Type> {
+        self.last_type.as_ref()
+    }
+
+    /// A report for the user, pointing into the input the error was raised in.
+    pub fn render(&self, error: &ReplError) -> String {
+        let source = error.span().and_then(|span| self.inputs.get(span.source));
+        error.render(source.map_or("", String::as_str))
+    }
+
+    /// The type errors of the last input that didn't stop it from running, as they
+    /// may not happen.
+    pub fn warnings(&self) -> &[TypeError] {
+        &self.warnings
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[derive(Debug)]
 pub enum ReplError {
-    ParseError(ParseError),
-    EvalError(EvalError),
+    ParseError(Located<ParseError>),
+    TypeErrors(Vec<TypeError>),
+    EvalError(Box<Located<EvalError>>),
+}
+
+impl ReplError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::ParseError(e) => e.span,
+            Self::TypeErrors(_) => None,
+            Self::EvalError(e) => e.span,
+        }
+    }
+
+    /// A report for the user, pointing into `source` where the error has a span.
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            Self::ParseError(e) => e.render(source),
+            Self::TypeErrors(errors) => errors
+                .iter()
+                .map(|e| format!("type error: {}", e))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Self::EvalError(e) => e.render(source),
+        }
+    }
 }
 
-impl From<ParseError> for ReplError {
-    fn from(e: ParseError) -> Self {
+impl From<Located<ParseError>> for ReplError {
+    fn from(e: Located<ParseError>) -> Self {
         Self::ParseError(e)
     }
 }
 
-impl From<EvalError> for ReplError {
-    fn from(e: EvalError) -> Self {
+impl From<Vec<TypeError>> for ReplError {
+    fn from(e: Vec<TypeError>) -> Self {
+        Self::TypeErrors(e)
+    }
+}
+
+impl From<Box<Located<EvalError>>> for ReplError {
+    fn from(e: Box<Located<EvalError>>) -> Self {
         Self::EvalError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rejects_ill_typed_input_before_evaluation() {
+        let mut repl = Repl::new();
+
+        assert!(repl.eval_str(r#"["$let", "n", "number", 1]"#).is_ok());
+        assert!(matches!(
+            repl.eval_str(r#"["$bind", "n", "two"]"#),
+            Err(ReplError::TypeErrors(_))
+        ));
+        assert_eq!(
+            repl.eval_str(r#"["$ref", "n"]"#).ok(),
+            Some(Value::Number(1.0))
+        );
+    }
+
+    #[test]
+    fn it_forgets_the_bindings_of_rejected_input() {
+        let mut repl = Repl::new();
+
+        assert!(matches!(
+            repl.eval_str(r#"["$do", ["$let", "y", "number", 1], ["$sub", "a", 1]]"#),
+            Err(ReplError::TypeErrors(_))
+        ));
+        assert!(repl.eval_str(r#"["$bind", "y", "s"]"#).is_ok());
+        assert_eq!(
+            repl.eval_str(r#"["$ref", "y"]"#).ok(),
+            Some(Value::String("s".into()))
+        );
+    }
+
+    #[test]
+    fn it_runs_input_whose_type_errors_may_not_happen() {
+        let mut repl = Repl::new();
+
+        assert_eq!(
+            repl.eval_str(r#"["$if", false, ["$sub", null, 1]]"#).ok(),
+            Some(Value::Null)
+        );
+        assert_eq!(
+            repl.warnings()
+                .iter()
+                .map(TypeError::to_string)
+                .collect::<Vec<_>>(),
+            ["cannot apply $sub to null, number"]
+        );
+
+        let dec = r#"["$let", "dec", ["$fn", ["x"],
+            ["$if", ["$eq", ["$ref", "x"], null], 0, ["$sub", ["$ref", "x"], 1]]]]"#;
+        assert!(repl.eval_str(dec).is_ok());
+        assert!(repl.warnings().is_empty());
+        assert_eq!(
+            repl.eval_str(r#"["$call", ["$ref", "dec"], null]"#).ok(),
+            Some(Value::Number(0.0))
+        );
+        assert_eq!(repl.warnings().len(), 1);
+    }
+
+    #[test]
+    fn it_reports_the_inferred_type_of_each_input() {
+        let mut repl = Repl::new();
+
+        assert!(repl
+            .eval_str(r#"["$bind", "dec", ["$fn", ["x"], ["$sub", ["$ref", "x"], 1]]]"#)
+            .is_ok());
+        assert_eq!(repl.last_type(), Some(&Type::Null));
+        assert!(repl.eval_str(r#"["$ref", "dec"]"#).is_ok());
+        assert_eq!(
+            repl.last_type().map(Type::to_string),
+            Some("(number) -> number".into())
+        );
+    }
+
+    #[test]
+    fn it_points_at_the_failing_expression() {
+        let mut repl = Repl::new();
+        let source = "[\"$add\", 1,\n  [\"$ref\", \"missing\"]]";
+        let error = repl.eval_str(source).expect_err("missing is unbound");
+
+        assert_eq!(
+            error.render(source),
+            [
+                r#"error: UndefinedIdent(Ident("missing"))"#,
+                " --> 2:3",
+                "  |",
+                r#"2 |   ["$ref", "missing"]]"#,
+                "  |   ^^^^^^^^^^^^^^^^^^^",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn it_points_at_parse_errors() {
+        let mut repl = Repl::new();
+        let source = r#"["$if", true, ["$sub", 1]]"#;
+        let error = repl.eval_str(source).expect_err("$sub needs two operands");
+
+        assert_eq!(
+            error.render(source),
+            [
+                "error: NotEnoughArgs { actual: 1, expected_min: 2 }",
+                " --> 1:15",
+                "  |",
+                r#"1 | ["$if", true, ["$sub", 1]]"#,
+                "  |               ^^^^^^^^^^^",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn it_points_into_the_input_a_function_was_defined_in() {
+        let mut repl = Repl::new();
+        let definition = r#"["$let", "f", ["$fn", ["x"], ["$do", 1, 2, 3, 4, 5, 6, 7, ["$assert", false, "boom"]]]]"#;
+        assert!(repl.eval_str(definition).is_ok());
+        let call = r#"["$call", ["$ref", "f"], 1]"#;
+        let error = repl.eval_str(call).expect_err("the assertion fails");
+
+        assert_eq!(
+            repl.render(&error),
+            [
+                r#"error: AssertionFailed { message: "boom", expression: "false", operands: [Boolean(false)] }"#,
+                " --> 1:59",
+                "  |",
+                &format!("1 | {}", definition),
+                &format!("  | {}{}", " ".repeat(58), "^".repeat(26)),
+            ]
+            .join("\n")
+        );
+        // Rendered against the wrong input, only the position is shown.
+        assert!(error.render(call).ends_with(" --> 1:59"));
+    }
+}
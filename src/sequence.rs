@@ -0,0 +1,456 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::{AstKind, AstNode};
+use crate::environment::{self, Environment, Object};
+use crate::interpreter::{EvalError, Interpreter};
+use crate::value::{Function, Value};
+
+/// A lazily produced stream of values. Elements are computed on demand by `next`,
+/// so a sequence may be unbounded. Sequences are stateful: every consumer advances
+/// the same cursor.
+#[derive(Clone)]
+pub struct Sequence(Rc<RefCell<Source>>);
+
+enum Source {
+    Items(std::vec::IntoIter<Value>),
+    /// Elements are computed from their index rather than by adding up `step`,
+    /// which would drift for fractional steps.
+    Range {
+        start: f64,
+        step: f64,
+        end: Option<f64>,
+        index: u64,
+    },
+    Take {
+        inner: Sequence,
+        remaining: usize,
+    },
+    TakeWhile {
+        inner: Sequence,
+        predicate: Rc<Function>,
+        index: usize,
+        done: bool,
+    },
+    Map {
+        inner: Sequence,
+        callback: Rc<Function>,
+        index: usize,
+    },
+    Filter {
+        inner: Sequence,
+        callback: Rc<Function>,
+        index: usize,
+    },
+    Generator(Generator),
+}
+
+impl Sequence {
+    fn new(source: Source) -> Self {
+        Self(Rc::new(RefCell::new(source)))
+    }
+
+    pub fn from_items(items: Vec<Value>) -> Self {
+        Self::new(Source::Items(items.into_iter()))
+    }
+
+    /// Counts from `start` by `step`, stopping before `end` when one is given.
+    pub fn range(start: f64, end: Option<f64>, step: f64) -> Self {
+        Self::new(Source::Range {
+            start,
+            step,
+            end,
+            index: 0,
+        })
+    }
+
+    pub fn take(self, count: usize) -> Self {
+        Self::new(Source::Take {
+            inner: self,
+            remaining: count,
+        })
+    }
+
+    pub fn take_while(self, predicate: Rc<Function>) -> Self {
+        Self::new(Source::TakeWhile {
+            inner: self,
+            predicate,
+            index: 0,
+            done: false,
+        })
+    }
+
+    pub fn map(self, callback: Rc<Function>) -> Self {
+        Self::new(Source::Map {
+            inner: self,
+            callback,
+            index: 0,
+        })
+    }
+
+    pub fn filter(self, callback: Rc<Function>) -> Self {
+        Self::new(Source::Filter {
+            inner: self,
+            callback,
+            index: 0,
+        })
+    }
+
+    /// Starts a generator that runs `body` in `env` up to each `$yield`.
+    pub(crate) fn generator(env: Rc<RefCell<Environment>>, body: &AstNode) -> Self {
+        Self::new(Source::Generator(Generator {
+            env,
+            frames: vec![Frame::Exec(Rc::new(Stmt::new(body)))],
+        }))
+    }
+
+    pub fn next(&self, interpreter: &mut Interpreter) -> Result<Option<Value>, EvalError> {
+        let mut source = self
+            .0
+            .try_borrow_mut()
+            .map_err(|_| EvalError::SequenceAlreadyRunning)?;
+        match &mut *source {
+            Source::Items(items) => Ok(items.next()),
+            Source::Range {
+                start,
+                step,
+                end,
+                index,
+            } => {
+                let current = *start + *step * *index as f64;
+                let exhausted = match end {
+                    Some(end) if *step >= 0.0 => current >= *end,
+                    Some(end) => current <= *end,
+                    None => false,
+                };
+                if exhausted {
+                    return Ok(None);
+                }
+                *index += 1;
+                Ok(Some(Value::Number(current)))
+            }
+            Source::Take { inner, remaining } => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                *remaining -= 1;
+                inner.next(interpreter)
+            }
+            Source::TakeWhile {
+                inner,
+                predicate,
+                index,
+                done,
+            } => {
+                if *done {
+                    return Ok(None);
+                }
+                match inner.next(interpreter)? {
+                    Some(item) => {
+                        let keep = interpreter
+                            .call_callback(predicate, *index, vec![item.clone()])?
+                            .to_boolean()?;
+                        *index += 1;
+                        *done = !keep;
+                        Ok(if keep { Some(item) } else { None })
+                    }
+                    None => Ok(None),
+                }
+            }
+            Source::Map {
+                inner,
+                callback,
+                index,
+            } => match inner.next(interpreter)? {
+                Some(item) => {
+                    let mapped = interpreter.call_callback(callback, *index, vec![item])?;
+                    *index += 1;
+                    Ok(Some(mapped))
+                }
+                None => Ok(None),
+            },
+            Source::Filter {
+                inner,
+                callback,
+                index,
+            } => {
+                while let Some(item) = inner.next(interpreter)? {
+                    let keep = interpreter
+                        .call_callback(callback, *index, vec![item.clone()])?
+                        .to_boolean()?;
+                    *index += 1;
+                    if keep {
+                        return Ok(Some(item));
+                    }
+                }
+                Ok(None)
+            }
+            Source::Generator(generator) => generator.resume(interpreter),
+        }
+    }
+
+    /// Identifies the sequence for `Value::total_cmp`; clones share an id.
+    pub(crate) fn id(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    /// Adds the objects the sequence refers to, for `environment::Heap`. `None` if
+    /// it's running and can't be looked into.
+    pub(crate) fn trace(&self, children: &mut Vec<Object>) -> Option<()> {
+        let source = self.0.try_borrow().ok()?;
+        match &*source {
+            Source::Items(items) => items
+                .as_slice()
+                .iter()
+                .for_each(|item| environment::value_children(item, children)),
+            Source::Range { .. } => {}
+            Source::Take { inner, .. } => children.push(Object::Sequence(inner.clone())),
+            Source::TakeWhile {
+                inner, predicate, ..
+            } => {
+                children.push(Object::Sequence(inner.clone()));
+                children.push(Object::Function(predicate.clone()));
+            }
+            Source::Map {
+                inner, callback, ..
+            }
+            | Source::Filter {
+                inner, callback, ..
+            } => {
+                children.push(Object::Sequence(inner.clone()));
+                children.push(Object::Function(callback.clone()));
+            }
+            Source::Generator(generator) => children.push(Object::Env(generator.env.clone())),
+        }
+        Some(())
+    }
+
+    pub fn collect(&self, interpreter: &mut Interpreter) -> Result<Vec<Value>, EvalError> {
+        let mut items = Vec::new();
+        while let Some(item) = self.next(interpreter)? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+impl PartialEq for Sequence {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sequence")
+    }
+}
+
+/// The suspended state of a `$gen` body.
+///
+/// Statements that may contain `$yield` (`$do`, `$if`, `$while`) are driven by an
+/// explicit frame stack instead of `Interpreter::eval`, so execution can stop at a
+/// `$yield` and pick up from the same place on the next call. Anything else is an
+/// ordinary expression and is evaluated in one go, which is why `$yield` may only
+/// appear in statement position.
+struct Generator {
+    env: Rc<RefCell<Environment>>,
+    frames: Vec<Frame>,
+}
+
+/// A `$gen` body split into the statements the generator drives. Blocks and loop
+/// bodies are shared with the frames running them, so a loop doesn't copy its body
+/// on every iteration.
+enum Stmt {
+    Yield(AstNode),
+    Do(Rc<[Rc<Stmt>]>),
+    If(AstNode, Rc<Stmt>, Option<Rc<Stmt>>),
+    While(Rc<Loop>),
+    Expr(AstNode),
+}
+
+struct Loop {
+    cond: AstNode,
+    body: Rc<Stmt>,
+}
+
+impl Stmt {
+    fn new(node: &AstNode) -> Self {
+        match &node.kind {
+            AstKind::Yield(arg) => Stmt::Yield((**arg).clone()),
+            AstKind::Do(stmts) => {
+                Stmt::Do(stmts.iter().map(|stmt| Rc::new(Stmt::new(stmt))).collect())
+            }
+            AstKind::If(cond, true_branch, false_branch) => Stmt::If(
+                (**cond).clone(),
+                Rc::new(Stmt::new(true_branch)),
+                false_branch.as_ref().map(|fb| Rc::new(Stmt::new(fb))),
+            ),
+            AstKind::While(cond, body) => Stmt::While(Rc::new(Loop {
+                cond: (**cond).clone(),
+                body: Rc::new(Stmt::new(body)),
+            })),
+            _ => Stmt::Expr(node.clone()),
+        }
+    }
+}
+
+enum Frame {
+    Exec(Rc<Stmt>),
+    /// A block and the index of its next statement.
+    Do(Rc<[Rc<Stmt>]>, usize),
+    While(Rc<Loop>),
+}
+
+impl Generator {
+    /// Runs to the next `$yield`. A generator that raised an error is finished.
+    fn resume(&mut self, interpreter: &mut Interpreter) -> Result<Option<Value>, EvalError> {
+        let result = self.run(interpreter);
+        if result.is_err() {
+            self.frames.clear();
+        }
+        result
+    }
+
+    fn run(&mut self, interpreter: &mut Interpreter) -> Result<Option<Value>, EvalError> {
+        while let Some(frame) = self.frames.pop() {
+            match frame {
+                Frame::Exec(stmt) => match &*stmt {
+                    Stmt::Yield(arg) => {
+                        return interpreter.eval_in(&self.env, arg).map(Some);
+                    }
+                    Stmt::Do(stmts) => self.frames.push(Frame::Do(stmts.clone(), 0)),
+                    Stmt::If(cond, true_branch, false_branch) => {
+                        if interpreter.eval_in(&self.env, cond)?.to_boolean()? {
+                            self.frames.push(Frame::Exec(true_branch.clone()));
+                        } else if let Some(fb) = false_branch {
+                            self.frames.push(Frame::Exec(fb.clone()));
+                        }
+                    }
+                    Stmt::While(looped) => self.frames.push(Frame::While(looped.clone())),
+                    Stmt::Expr(node) => {
+                        interpreter.eval_in(&self.env, node)?;
+                    }
+                },
+                Frame::Do(stmts, index) => {
+                    if let Some(stmt) = stmts.get(index).cloned() {
+                        self.frames.push(Frame::Do(stmts, index + 1));
+                        self.frames.push(Frame::Exec(stmt));
+                    }
+                }
+                Frame::While(looped) => {
+                    if interpreter.eval_in(&self.env, &looped.cond)?.to_boolean()? {
+                        let body = looped.body.clone();
+                        self.frames.push(Frame::While(looped));
+                        self.frames.push(Frame::Exec(body));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jir::JirParser;
+
+    fn eval_sequence(i: &mut Interpreter, json: &str) -> Result<Sequence, EvalError> {
+        i.eval(&JirParser::parse_json(json).expect("valid JIR"))?
+            .to_sequence()
+    }
+
+    fn numbers(items: &[f64]) -> Vec<Value> {
+        items.iter().copied().map(Value::Number).collect()
+    }
+
+    #[test]
+    fn it_counts_ranges_without_drifting() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+
+        let tenths = Sequence::range(0.0, Some(1.0), 0.1).collect(&mut i)?;
+        assert_eq!(
+            tenths,
+            (0..10)
+                .map(|k| Value::Number(k as f64 * 0.1))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Sequence::range(3.0, Some(0.0), -1.0).collect(&mut i)?,
+            numbers(&[3.0, 2.0, 1.0])
+        );
+        assert_eq!(
+            Sequence::range(0.5, Some(2.0), 1.0).collect(&mut i)?,
+            numbers(&[0.5, 1.5])
+        );
+        assert!(Sequence::range(0.0, Some(0.0), 1.0)
+            .collect(&mut i)?
+            .is_empty());
+        assert!(Sequence::range(5.0, Some(0.0), 1.0)
+            .collect(&mut i)?
+            .is_empty());
+        assert!(Sequence::range(0.0, Some(1.0), -1.0)
+            .collect(&mut i)?
+            .is_empty());
+        assert_eq!(
+            Sequence::range(1e16, None, 1.0).take(3).collect(&mut i)?,
+            numbers(&[1e16, 1e16 + 1.0, 1e16 + 2.0])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_resumes_generators_inside_blocks_and_loops() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        let sequence = eval_sequence(
+            &mut i,
+            r#"["$call", ["$gen", [], ["$do",
+                ["$let", "n", 0],
+                ["$while", ["$lt", ["$ref", "n"], 3], ["$do",
+                    ["$bind", "n", ["$add", ["$ref", "n"], 1]],
+                    ["$if", ["$eq", ["$ref", "n"], 2],
+                        ["$do", ["$yield", "two"], ["$do", ["$yield", "still two"]]],
+                        ["$yield", ["$ref", "n"]]]]],
+                ["$yield", "done"]
+            ]]]"#,
+        )?;
+
+        assert_eq!(sequence.next(&mut i)?, Some(Value::Number(1.0)));
+        assert_eq!(sequence.next(&mut i)?, Some(Value::String("two".into())));
+        // Other code may run between resumptions.
+        eval_sequence(&mut i, r#"["$range", 0, 1]"#)?;
+        assert_eq!(
+            sequence.next(&mut i)?,
+            Some(Value::String("still two".into()))
+        );
+        assert_eq!(sequence.next(&mut i)?, Some(Value::Number(3.0)));
+        assert_eq!(sequence.next(&mut i)?, Some(Value::String("done".into())));
+        assert_eq!(sequence.next(&mut i)?, None);
+        assert_eq!(sequence.next(&mut i)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn it_finishes_a_generator_that_raised_an_error() -> Result<(), EvalError> {
+        let mut i = Interpreter::new();
+        let sequence = eval_sequence(
+            &mut i,
+            r#"["$call", ["$gen", [], ["$do",
+                ["$yield", 1],
+                ["$sub", "one", 1],
+                ["$yield", 2]
+            ]]]"#,
+        )?;
+
+        assert_eq!(sequence.next(&mut i)?, Some(Value::Number(1.0)));
+        assert!(sequence.next(&mut i).is_err());
+        assert_eq!(sequence.next(&mut i)?, None);
+        Ok(())
+    }
+}
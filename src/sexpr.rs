@@ -0,0 +1,339 @@
+//! An S-expression syntax for JIR, read into the same `Json` trees as the JSON
+//! syntax.
+//!
+//! ```text
+//! ; Increments x.
+//! (bind x
+//!   (add (ref x) 1))
+//! ```
+//!
+//! - `(name operand...)` is the form `["$name", operand...]`. A head that isn't a
+//!   bare symbol is kept as written, so `("name" 1)` is `["name", 1]`.
+//! - `[item...]` is an array, for data such as parameter lists: `(fn [x y] ...)`.
+//! - `{key value...}` is an object, as in `(call (ref f) 1 {by 2})`.
+//! - A bare symbol is a string, and strings that aren't symbols are written in JSON
+//!   syntax. Numbers, `true`, `false` and `null` are written as in JSON.
+//! - `;` starts a comment that runs to the end of the line.
+//!
+//! `to_sexpr` writes any JSON back in this syntax. Reading what it writes gives the
+//! same JSON, numbers as written and object entries in order.
+
+use crate::jir::ParseError;
+use crate::json::{Json, JsonKind, Reader, MAX_DEPTH};
+use crate::span::{Located, Span};
+
+/// Reads a single S-expression, surrounded by optional whitespace and comments.
+pub fn parse(source: &str) -> Result<Json, Located<ParseError>> {
+    let mut reader = Reader::new(source, ParseError::InvalidSExpr);
+    let value = reader.datum(false)?;
+    reader.skip_trivia();
+    if reader.pos < source.len() {
+        return Err(reader.error("end of input"));
+    }
+    Ok(value)
+}
+
+/// Writes JSON as an S-expression on one line.
+pub fn to_sexpr(json: &Json) -> String {
+    let mut out = String::new();
+    write(json, &mut out);
+    out
+}
+
+fn write(json: &Json, out: &mut String) {
+    match &json.kind {
+        JsonKind::Null => out.push_str("null"),
+        JsonKind::Bool(b) => out.push_str(&b.to_string()),
+        JsonKind::Number(n) => out.push_str(n),
+        JsonKind::String(s) => write_string(s, out),
+        JsonKind::Array(items) => {
+            let form = items
+                .split_first()
+                .and_then(|(head, operands)| Some((form_name(head)?, operands)));
+            match form {
+                Some((name, operands)) => {
+                    out.push('(');
+                    out.push_str(name);
+                    for operand in operands {
+                        out.push(' ');
+                        write(operand, out);
+                    }
+                    out.push(')');
+                }
+                None => {
+                    out.push('[');
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            out.push(' ');
+                        }
+                        write(item, out);
+                    }
+                    out.push(']');
+                }
+            }
+        }
+        JsonKind::Object(entries) => {
+            out.push('{');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write_string(key, out);
+                out.push(' ');
+                write(value, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// The symbol a form head is written as: `"$name"` is `name`.
+fn form_name(head: &Json) -> Option<&str> {
+    head.as_str()?
+        .strip_prefix('$')
+        .filter(|name| is_symbol(name))
+}
+
+fn write_string(s: &str, out: &mut String) {
+    if is_symbol(s) {
+        out.push_str(s);
+    } else {
+        out.push_str(&serde_json::Value::String(s.into()).to_string());
+    }
+}
+
+/// Whether a string can be written bare, and read back as the same string.
+fn is_symbol(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars().all(is_symbol_char)
+        && !starts_number(s)
+        && !matches!(s, "true" | "false" | "null")
+}
+
+fn is_symbol_char(c: char) -> bool {
+    !c.is_whitespace() && !c.is_control() && !"()[]{}\";".contains(c)
+}
+
+fn starts_number(s: &str) -> bool {
+    let s = s.strip_prefix('-').unwrap_or(s);
+    s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a value. `head` is whether it is first in a `(...)` form, where a bare
+    /// symbol names the form.
+    fn datum(&mut self, head: bool) -> Result<Json, Located<ParseError>> {
+        self.skip_trivia();
+        let start = self.here();
+        let kind = match self.peek() {
+            Some('(') | Some('[') | Some('{') if self.depth == MAX_DEPTH => {
+                return Err(self.error(&format!("at most {} levels of nesting", MAX_DEPTH)));
+            }
+            Some('(') => self.nested(Self::form)?,
+            Some('[') => self.nested(Self::list)?,
+            Some('{') => self.nested(Self::table)?,
+            Some('"') => JsonKind::String(self.string()?),
+            Some(_) if starts_number(&self.source[self.pos..]) => {
+                let number = self.number()?;
+                if self.peek().is_some_and(is_symbol_char) {
+                    return Err(self.error("a delimiter after the number"));
+                }
+                number
+            }
+            Some(c) if is_symbol_char(c) => self.symbol(head),
+            _ => return Err(self.error("a value")),
+        };
+        Ok(Json {
+            kind,
+            span: Span {
+                end: self.pos,
+                ..start
+            },
+        })
+    }
+
+    fn form(&mut self) -> Result<JsonKind, Located<ParseError>> {
+        self.items(')').map(JsonKind::Array)
+    }
+
+    fn list(&mut self) -> Result<JsonKind, Located<ParseError>> {
+        self.items(']').map(JsonKind::Array)
+    }
+
+    /// Values up to `close`, after the opening bracket. Only the first value of a
+    /// form is a head.
+    fn items(&mut self, close: char) -> Result<Vec<Json>, Located<ParseError>> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.eat(close) {
+                return Ok(items);
+            }
+            if matches!(self.peek(), None | Some(')') | Some(']') | Some('}')) {
+                return Err(self.error(&format!("`{}`", close)));
+            }
+            let head = close == ')' && items.is_empty();
+            items.push(self.datum(head)?);
+        }
+    }
+
+    fn table(&mut self) -> Result<JsonKind, Located<ParseError>> {
+        self.bump();
+        let mut entries = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.eat('}') {
+                return Ok(JsonKind::Object(entries));
+            }
+            if matches!(self.peek(), None | Some(')') | Some(']')) {
+                return Err(self.error("`}`"));
+            }
+            let key = self.datum(false)?;
+            let key = match key.kind {
+                JsonKind::String(name) => name,
+                _ => {
+                    return Err(Located::new(
+                        ParseError::InvalidSExpr("expected a string key".into()),
+                        Some(key.span),
+                    ))
+                }
+            };
+            self.skip_trivia();
+            if self.peek() == Some('}') {
+                return Err(self.error("a value"));
+            }
+            entries.push((key, self.datum(false)?));
+        }
+    }
+
+    fn symbol(&mut self, head: bool) -> JsonKind {
+        let start = self.pos;
+        while self.peek().is_some_and(is_symbol_char) {
+            self.bump();
+        }
+        match &self.source[start..self.pos] {
+            "true" => JsonKind::Bool(true),
+            "false" => JsonKind::Bool(false),
+            "null" => JsonKind::Null,
+            name if head => JsonKind::String(format!("${}", name)),
+            name => JsonKind::String(name.into()),
+        }
+    }
+
+    /// Whitespace and `;` comments.
+    fn skip_trivia(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => {
+                    self.pos += 1;
+                    self.line += 1;
+                    self.line_start = self.pos;
+                }
+                ';' => {
+                    while !matches!(self.peek(), Some('\n') | None) {
+                        self.bump();
+                    }
+                }
+                c if c.is_whitespace() => self.pos += c.len_utf8(),
+                _ => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde_json::Value as JsonValue;
+
+    use super::*;
+    use crate::jir::JirParser;
+    use crate::json;
+
+    #[test]
+    fn it_reads_forms_like_their_json() -> Result<(), Located<ParseError>> {
+        let source = r#"
+            ; A counter with a default step.
+            (do
+              (let inc (fn [n [by number 1]]   ; `by` is optional
+                (add (ref n) (ref by))))
+              (call (ref inc) -2.50 {by 3 "the end" null})
+              ("plain" $ref "two words" [true]))"#;
+        let json = r#"["$do",
+            ["$let", "inc", ["$fn", ["n", ["by", "number", 1]],
+                ["$add", ["$ref", "n"], ["$ref", "by"]]]],
+            ["$call", ["$ref", "inc"], -2.50, {"by": 3, "the end": null}],
+            ["plain", "$ref", "two words", [true]]]"#;
+
+        assert_eq!(parse(source)?.to_string(), json::parse(json)?.to_string());
+        assert_eq!(
+            format!(
+                "{:?}",
+                JirParser::parse_sexpr("(let inc (fn [n] (add (ref n) 1)))")?
+            ),
+            format!(
+                "{:?}",
+                JirParser::parse(r#"["$let", "inc", ["$fn", ["n"], ["$add", ["$ref", "n"], 1]]]"#)?
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_symbols_bare_and_other_strings_quoted() -> Result<(), Located<ParseError>> {
+        let json = json::parse(
+            r#"["$if", ["$eq", ["$ref", "a b"], "1"], ["$quote", ["x", "$y", "", "-1", "true"]], {"k": []}]"#,
+        )?;
+        assert_eq!(
+            to_sexpr(&json),
+            r#"(if (eq (ref "a b") "1") (quote [x $y "" "-1" "true"]) {k []})"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_position_of_syntax_errors() {
+        let error = parse("(add 1\n  (ref x]").expect_err("mismatched bracket");
+        assert!(matches!(error.error, ParseError::InvalidSExpr(_)));
+        assert_eq!(error.span.map(|s| (s.line, s.column)), Some((2, 9)));
+
+        let error = parse("{1 2}").expect_err("number key");
+        assert_eq!(error.span.map(|s| (s.start, s.end)), Some((1, 2)));
+        assert!(parse("(add 1x 2)").is_err());
+        assert!(parse("(add 1) 2").is_err());
+    }
+
+    fn arbitrary_json() -> impl Strategy<Value = JsonValue> {
+        let leaf = prop_oneof![
+            Just(JsonValue::Null),
+            any::<bool>().prop_map(JsonValue::from),
+            any::<i64>().prop_map(JsonValue::from),
+            any::<f64>()
+                .prop_filter("finite", |n| n.is_finite())
+                .prop_map(JsonValue::from),
+            any::<String>().prop_map(JsonValue::from),
+            "\\$?[a-z-]{0,3}[0-9]?".prop_map(JsonValue::from),
+        ];
+        leaf.prop_recursive(4, 64, 6, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..6).prop_map(JsonValue::Array),
+                prop::collection::vec(("[a-z ]{0,3}", inner), 0..4)
+                    .prop_map(|entries| JsonValue::Object(entries.into_iter().collect())),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn it_round_trips_json_losslessly(value in arbitrary_json()) {
+            let json = json::parse(&value.to_string())
+                .map_err(|e| TestCaseError::fail(format!("{:?}", e)))?;
+            let sexpr = to_sexpr(&json);
+            let read = parse(&sexpr)
+                .map_err(|e| TestCaseError::fail(format!("{:?} in {}", e, sexpr)))?;
+            prop_assert_eq!(read.to_string(), json.to_string());
+            prop_assert_eq!(to_sexpr(&read), sexpr);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt::{self, Debug};
+
+/// A range of source text. `start` and `end` are byte offsets, `line` and `column`
+/// are the 1-based position of `start`, with columns counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    /// Which of several sources the span is in, as numbered by the caller that
+    /// parsed them (see `JirParser::set_source`). 0 by default.
+    pub source: usize,
+}
+
+impl Span {
+    /// The source line holding the start of the span, underlined with carets up to
+    /// the end of the span or of the line, whichever comes first. `None` when the
+    /// span doesn't fit `source`, as when it was taken from another one.
+    pub fn snippet(&self, source: &str) -> Option<String> {
+        let line_start = source.get(..self.start)?.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source
+            .get(self.start..)?
+            .find('\n')
+            .map_or(source.len(), |i| self.start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let indent = source[line_start..self.start].chars().count();
+        let width = source
+            .get(self.start..self.end.min(line_end).max(self.start))?
+            .chars()
+            .count()
+            .max(1);
+        let gutter = " ".repeat(self.line.to_string().len());
+        Some(format!(
+            "{gutter} |\n{line} | {text}\n{gutter} | {pad}{carets}",
+            gutter = gutter,
+            line = self.line,
+            text = text,
+            pad = " ".repeat(indent),
+            carets = "^".repeat(width),
+        ))
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// An error and the source range it was raised at, when known.
+#[derive(Debug)]
+pub struct Located<E> {
+    pub error: E,
+    pub span: Option<Span>,
+}
+
+impl<E> Located<E> {
+    pub fn new(error: E, span: Option<Span>) -> Self {
+        Self { error, span }
+    }
+
+    /// Attaches `span` unless a more precise one is already known.
+    pub fn or_at(mut self, span: Option<Span>) -> Self {
+        self.span = self.span.or(span);
+        self
+    }
+}
+
+impl<E: Debug> Located<E> {
+    /// The error followed by its position and a caret-annotated snippet of `source`.
+    pub fn render(&self, source: &str) -> String {
+        self.render_as("error", source)
+    }
+
+    /// Like `render`, with another label in place of `error`. The snippet is left
+    /// out when the span doesn't fit `source`.
+    pub fn render_as(&self, label: &str, source: &str) -> String {
+        let header = format!("{}: {:?}", label, self.error);
+        match self.span {
+            Some(span) => match span.snippet(source) {
+                Some(snippet) => format!("{}\n --> {}\n{}", header, span, snippet),
+                None => format!("{}\n --> {}", header, span),
+            },
+            None => header,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_underlines_the_span_on_its_line() {
+        let source = "[\"$do\",\n  [\"$sub\", 1]]";
+        let span = Span {
+            start: 10,
+            end: 21,
+            line: 2,
+            column: 3,
+            source: 0,
+        };
+        let error = Located::new("NotEnoughArgs", Some(span));
+
+        assert_eq!(
+            error.render(source),
+            "error: \"NotEnoughArgs\"\n --> 2:3\n  |\n2 |   [\"$sub\", 1]]\n  |   ^^^^^^^^^^^"
+        );
+        assert_eq!(
+            error.render("[\"é\"]"),
+            "error: \"NotEnoughArgs\"\n --> 2:3"
+        );
+    }
+}
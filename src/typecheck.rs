@@ -0,0 +1,1986 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{AstKind, AstNode, Ident, MatchArm, NodeId, Param, Pattern};
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// Statically unknown; compatible with every other type.
+    Any,
+    Null,
+    Number,
+    String,
+    Boolean,
+    DateTime,
+    Array(Box<Type>),
+    Sequence(Box<Type>),
+    Function(Vec<Type>, Box<Type>),
+    /// A JSON object from `$quote`, whose values may have any type.
+    Object,
+    /// A value of the `$struct` or `$enum` with this name.
+    Named(String),
+    /// A type variable introduced by inference, e.g. for an unannotated parameter.
+    Var(u32),
+}
+
+impl Type {
+    /// Parses a type annotation such as `number`, `array<string>` or
+    /// `(number, number) -> boolean`. A bare `array` or `sequence` has `any` elements,
+    /// and capitalized names refer to `$struct` and `$enum` types.
+    pub fn parse(s: &str) -> Option<Type> {
+        let s = s.trim();
+        if let Some(rest) = s.strip_prefix('(') {
+            let close = Self::matching_paren(rest)?;
+            let ret = rest[close + 1..].trim().strip_prefix("->")?;
+            let params = Self::split_top_level(&rest[..close])
+                .into_iter()
+                .map(Type::parse)
+                .collect::<Option<Vec<Type>>>()?;
+            return Some(Type::Function(params, Box::new(Type::parse(ret)?)));
+        }
+        if let Some(inner) = s.strip_suffix('>') {
+            let (name, element) = inner.split_once('<')?;
+            let element = Box::new(Type::parse(element)?);
+            return match name.trim() {
+                "array" => Some(Type::Array(element)),
+                "sequence" => Some(Type::Sequence(element)),
+                _ => None,
+            };
+        }
+        match s {
+            "any" => Some(Type::Any),
+            "null" => Some(Type::Null),
+            "number" => Some(Type::Number),
+            "string" => Some(Type::String),
+            "boolean" => Some(Type::Boolean),
+            "datetime" => Some(Type::DateTime),
+            "array" => Some(Type::Array(Box::new(Type::Any))),
+            "sequence" => Some(Type::Sequence(Box::new(Type::Any))),
+            "object" => Some(Type::Object),
+            name if name.starts_with(|c: char| c.is_ascii_uppercase()) => {
+                Some(Type::Named(name.into()))
+            }
+            _ => None,
+        }
+    }
+
+    fn matching_paren(s: &str) -> Option<usize> {
+        let mut depth = 0;
+        for (i, c) in Self::brackets(s) {
+            match c {
+                '(' | '<' => depth += 1,
+                ')' if depth == 0 => return Some(i),
+                ')' | '>' => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// The characters of `s` with their offsets, leaving out the `>` of each `->`
+    /// so that it isn't taken for a closing bracket.
+    fn brackets(s: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+        s.char_indices()
+            .filter(move |&(i, c)| !(c == '>' && s[..i].ends_with('-')))
+    }
+
+    fn split_top_level(s: &str) -> Vec<&str> {
+        if s.trim().is_empty() {
+            return Vec::new();
+        }
+        let mut parts = Vec::new();
+        let mut depth = 0;
+        let mut start = 0;
+        for (i, c) in Self::brackets(s) {
+            match c {
+                '(' | '<' => depth += 1,
+                ')' | '>' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(&s[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(&s[start..]);
+        parts
+    }
+
+    pub fn of_value(value: &Value) -> Type {
+        match value {
+            Value::Null => Type::Null,
+            Value::Number(_) => Type::Number,
+            Value::String(_) => Type::String,
+            Value::Boolean(_) => Type::Boolean,
+            Value::DateTime(_) => Type::DateTime,
+            Value::Array(items) => {
+                Type::Array(Box::new(Self::join_all(items.iter().map(Type::of_value))))
+            }
+            Value::Object(_) => Type::Object,
+            Value::Function(f) => Type::Function(
+                f.params
+                    .iter()
+                    .map(|p| p.ty.clone().unwrap_or(Type::Any))
+                    .collect(),
+                Box::new(Type::Any),
+            ),
+            Value::Sequence(_) => Type::Sequence(Box::new(Type::Any)),
+            Value::Struct(instance) => Type::Named(instance.name.clone()),
+            Value::Variant(variant) => Type::Named(variant.enum_name.clone()),
+        }
+    }
+
+    /// The most precise type covering both `self` and `other`.
+    fn join(&self, other: &Type) -> Type {
+        match (self, other) {
+            (Type::Array(a), Type::Array(b)) => Type::Array(Box::new(a.join(b))),
+            (Type::Sequence(a), Type::Sequence(b)) => Type::Sequence(Box::new(a.join(b))),
+            (a, b) if a == b => a.clone(),
+            _ => Type::Any,
+        }
+    }
+
+    fn join_all(mut types: impl Iterator<Item = Type>) -> Type {
+        match types.next() {
+            Some(first) => types.fold(first, |acc, t| acc.join(&t)),
+            None => Type::Any,
+        }
+    }
+
+    fn free_vars(&self, vars: &mut HashSet<u32>) {
+        match self {
+            Type::Var(v) => {
+                vars.insert(*v);
+            }
+            Type::Array(element) | Type::Sequence(element) => element.free_vars(vars),
+            Type::Function(params, ret) => {
+                params.iter().for_each(|p| p.free_vars(vars));
+                ret.free_vars(vars);
+            }
+            _ => {}
+        }
+    }
+
+    fn substitute(&self, mapping: &HashMap<u32, Type>) -> Type {
+        match self {
+            Type::Var(v) => mapping.get(v).cloned().unwrap_or(Type::Var(*v)),
+            Type::Array(element) => Type::Array(Box::new(element.substitute(mapping))),
+            Type::Sequence(element) => Type::Sequence(Box::new(element.substitute(mapping))),
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| p.substitute(mapping)).collect(),
+                Box::new(ret.substitute(mapping)),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Null => write!(f, "null"),
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::DateTime => write!(f, "datetime"),
+            Type::Array(element) => write!(f, "array<{}>", element),
+            Type::Sequence(element) => write!(f, "sequence<{}>", element),
+            Type::Object => write!(f, "object"),
+            Type::Function(params, ret) => {
+                let params = params.iter().map(Type::to_string).collect::<Vec<_>>();
+                write!(f, "({}) -> {}", params.join(", "), ret)
+            }
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Var(v) => write!(f, "t{}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    /// A value of type `actual` was bound to `ident`, which is declared as `expected`.
+    BindingMismatch {
+        ident: Ident,
+        expected: Type,
+        actual: Type,
+    },
+    ArgumentMismatch {
+        index: usize,
+        expected: Type,
+        actual: Type,
+    },
+    /// A call to `function` (`<fn>` when it isn't called by name) with the wrong
+    /// number of arguments. `param` is the first parameter left without one, when
+    /// its name is known.
+    ArgumentCountMismatch {
+        function: String,
+        param: Option<String>,
+        actual: usize,
+        expected: usize,
+    },
+    /// `operation` (a form name such as `$sub`) can't be applied to `operands`.
+    UnexpectedTypeForOperation {
+        operation: &'static str,
+        operands: Vec<Type>,
+    },
+    NotCallable(Type),
+    UndefinedStruct(String),
+    MissingField {
+        struct_name: String,
+        field: String,
+    },
+    UnknownField {
+        struct_name: String,
+        field: String,
+    },
+    FieldMismatch {
+        struct_name: String,
+        field: String,
+        expected: Type,
+        actual: Type,
+    },
+    UndefinedEnum(String),
+    UnknownVariant {
+        enum_name: String,
+        variant: String,
+    },
+    PatternArityMismatch {
+        variant: String,
+        actual: usize,
+        expected: usize,
+    },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::BindingMismatch {
+                ident,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "cannot bind {} to `{}` declared as {}",
+                actual, ident.0, expected
+            ),
+            TypeError::ArgumentMismatch {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "argument {} expects {} but got {}",
+                index, expected, actual
+            ),
+            TypeError::ArgumentCountMismatch {
+                function,
+                param,
+                actual,
+                expected,
+            } => {
+                write!(
+                    f,
+                    "`{}` expects {} arguments but got {}",
+                    function, expected, actual
+                )?;
+                match param {
+                    Some(param) => write!(f, ", missing `{}`", param),
+                    None => Ok(()),
+                }
+            }
+            TypeError::UnexpectedTypeForOperation {
+                operation,
+                operands,
+            } => {
+                let operands = operands.iter().map(Type::to_string).collect::<Vec<_>>();
+                write!(f, "cannot apply {} to {}", operation, operands.join(", "))
+            }
+            TypeError::NotCallable(ty) => write!(f, "{} is not callable", ty),
+            TypeError::UndefinedStruct(name) => write!(f, "struct {} is not declared", name),
+            TypeError::MissingField { struct_name, field } => {
+                write!(f, "{} requires field `{}`", struct_name, field)
+            }
+            TypeError::UnknownField { struct_name, field } => {
+                write!(f, "{} has no field `{}`", struct_name, field)
+            }
+            TypeError::FieldMismatch {
+                struct_name,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "field `{}` of {} expects {} but got {}",
+                field, struct_name, expected, actual
+            ),
+            TypeError::UndefinedEnum(name) => write!(f, "enum {} is not declared", name),
+            TypeError::UnknownVariant { enum_name, variant } => {
+                write!(f, "{} has no variant `{}`", enum_name, variant)
+            }
+            TypeError::PatternArityMismatch {
+                variant,
+                actual,
+                expected,
+            } => write!(
+                f,
+                "pattern binds {} values but {} carries {}",
+                actual, variant, expected
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Binding {
+    ty: Type,
+    /// Set for `$let` bindings and parameters with an annotation; later `$bind`s
+    /// must agree with it.
+    declared: bool,
+    /// Type variables generalized at a function binding, so every reference to it
+    /// gets its own copy (`["$let", "id", ["$fn", ["x"], ["$ref", "x"]]]` can be
+    /// called with a number and with a string).
+    quantified: Vec<u32>,
+    /// Parameter names of the `$fn` or `$gen` bound, for errors about its calls.
+    params: Vec<String>,
+}
+
+impl Binding {
+    fn new(ty: Type, declared: bool) -> Self {
+        Self {
+            ty,
+            declared,
+            quantified: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+}
+
+/// Names a called function in errors, with its parameter names when known.
+struct Callee {
+    name: String,
+    params: Vec<String>,
+}
+
+impl Callee {
+    fn anonymous() -> Self {
+        Self {
+            name: "<fn>".into(),
+            params: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct StructField {
+    name: String,
+    ty: Type,
+    has_default: bool,
+}
+
+/// A type variable whose resolution must satisfy an operation, checked once the
+/// whole program has been seen.
+#[derive(Clone)]
+struct Deferred {
+    ty: Type,
+    operation: &'static str,
+    operands: Vec<Type>,
+    allowed: fn(&Type) -> bool,
+}
+
+/// The result of `TypeChecker::infer`.
+pub struct Inference {
+    pub ty: Type,
+    /// Every binding introduced by the program (`$let`, `$bind` and parameters), in
+    /// source order.
+    pub bindings: Vec<(Ident, Type)>,
+    types: HashMap<NodeId, Type>,
+}
+
+impl Inference {
+    /// The inferred type of `node`, which must be part of the tree passed to `infer`
+    /// (or a clone of it).
+    pub fn type_of(&self, node: &AstNode) -> Option<&Type> {
+        self.types.get(&node.id)
+    }
+}
+
+/// Infers types for a program and reports operations that are certain to fail in
+/// `Interpreter::eval`.
+///
+/// Unannotated parameters and bindings get type variables that are solved by
+/// unification, so `["$fn", ["x"], ["$sub", ["$ref", "x"], 1]]` is inferred as
+/// `(number) -> number`. `any` (the type of identifiers the checker hasn't seen, and
+/// of explicit `any` annotations) unifies with everything.
+///
+/// Bindings are remembered between calls, so a single checker can follow a REPL
+/// session.
+///
+/// Inferred types can be wrong about a program that runs fine: a parameter used as a
+/// number in one branch may be passed `null` when that branch doesn't run. A checker
+/// made with `TypeChecker::definite` only reports errors that are certain.
+pub struct TypeChecker {
+    scopes: Vec<HashMap<Ident, Binding>>,
+    substitution: HashMap<u32, Type>,
+    next_var: u32,
+    /// Element types of the enclosing `$gen` bodies.
+    yields: Vec<Type>,
+    deferred: Vec<Deferred>,
+    errors: Vec<TypeError>,
+    types: HashMap<NodeId, Type>,
+    bindings: Vec<(Ident, Type)>,
+    structs: HashMap<String, Vec<StructField>>,
+    /// Variant names and the types of the values they carry, per `$enum`.
+    enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    definite: bool,
+    /// How many enclosing nodes may not run their child being checked, like the
+    /// branches of an `$if` or a function body.
+    guarded: usize,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            substitution: HashMap::new(),
+            next_var: 0,
+            yields: Vec::new(),
+            deferred: Vec::new(),
+            errors: Vec::new(),
+            types: HashMap::new(),
+            bindings: Vec::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            definite: false,
+            guarded: 0,
+        }
+    }
+
+    /// A checker that only reports errors certain to happen when the program runs.
+    /// Unannotated parameters are `any`, so nothing is inferred from how function
+    /// bodies use them, and code that may not run (branches, loop bodies, function
+    /// bodies and the like) is checked without reporting errors in it.
+    pub fn definite() -> Self {
+        Self {
+            definite: true,
+            ..Self::new()
+        }
+    }
+
+    /// Returns the type of `ast`, or every type error found in it.
+    pub fn check(&mut self, ast: &AstNode) -> Result<Type, Vec<TypeError>> {
+        self.infer(ast).map(|inference| inference.ty)
+    }
+
+    /// Infers the type of `ast` and of every node and binding in it, or returns every
+    /// type error found in it. A program with errors leaves the checker as it was,
+    /// since it won't run.
+    pub fn infer(&mut self, ast: &AstNode) -> Result<Inference, Vec<TypeError>> {
+        let snapshot = (
+            self.scopes.clone(),
+            self.substitution.clone(),
+            self.structs.clone(),
+            self.enums.clone(),
+        );
+        let (inference, errors) = self.infer_all(ast);
+        if errors.is_empty() {
+            Ok(inference)
+        } else {
+            self.yields.clear();
+            (self.scopes, self.substitution, self.structs, self.enums) = snapshot;
+            Err(errors)
+        }
+    }
+
+    /// Like `infer`, but keeps the program's bindings even when it has type errors,
+    /// which are returned alongside. For programs that run regardless.
+    pub fn infer_all(&mut self, ast: &AstNode) -> (Inference, Vec<TypeError>) {
+        let ty = self.check_node(ast);
+        for deferred in std::mem::take(&mut self.deferred) {
+            let ty = self.resolve(&deferred.ty);
+            if !matches!(ty, Type::Var(_)) && !(deferred.allowed)(&ty) {
+                let operands = deferred.operands.iter().map(|t| self.resolve(t)).collect();
+                self.report(TypeError::UnexpectedTypeForOperation {
+                    operation: deferred.operation,
+                    operands,
+                });
+            }
+        }
+        let types = std::mem::take(&mut self.types);
+        let bindings = std::mem::take(&mut self.bindings);
+        let inference = Inference {
+            ty: self.resolve(&ty),
+            bindings: bindings
+                .into_iter()
+                .map(|(ident, ty)| (ident, self.resolve(&ty)))
+                .collect(),
+            types: types
+                .into_iter()
+                .map(|(node, ty)| (node, self.resolve(&ty)))
+                .collect(),
+        };
+        (inference, std::mem::take(&mut self.errors))
+    }
+
+    fn check_node(&mut self, ast: &AstNode) -> Type {
+        let ty = self.check_kind(ast);
+        self.types.insert(ast.id, ty.clone());
+        ty
+    }
+
+    /// Checks code that may not run, like the branches of an `$if`.
+    fn check_guarded(&mut self, ast: &AstNode) -> Type {
+        self.guarded += 1;
+        let ty = self.check_node(ast);
+        self.guarded -= 1;
+        ty
+    }
+
+    /// Whether errors found now are reported: a definite checker skips those in code
+    /// that may not run.
+    fn reporting(&self) -> bool {
+        !self.definite || self.guarded == 0
+    }
+
+    fn report(&mut self, error: TypeError) {
+        if self.reporting() {
+            self.errors.push(error);
+        }
+    }
+
+    fn check_kind(&mut self, ast: &AstNode) -> Type {
+        match &ast.kind {
+            AstKind::Literal(value) => Type::of_value(value),
+            AstKind::Ident(ident) => self.lookup(ident).unwrap_or(Type::Any),
+            AstKind::Add(lhs, rhs) => {
+                let lt = self.check_node(lhs);
+                let rt = self.check_node(rhs);
+                self.expect_same("$add", lt, rt, |t| {
+                    matches!(t, Type::Number | Type::String | Type::Any)
+                })
+            }
+            AstKind::Sub(lhs, rhs) => {
+                self.expect_operands("$sub", &[lhs, rhs], &Type::Number);
+                Type::Number
+            }
+            AstKind::And(lhs, rhs) | AstKind::Or(lhs, rhs) => {
+                // Both sides are always evaluated.
+                self.check_node(lhs);
+                self.check_node(rhs);
+                Type::Boolean
+            }
+            AstKind::Not(arg) => {
+                self.check_node(arg);
+                Type::Boolean
+            }
+            AstKind::EqAll(operands) => {
+                for operand in operands {
+                    self.check_node(operand);
+                }
+                Type::Boolean
+            }
+            AstKind::Eq(lhs, rhs) | AstKind::NotEq(lhs, rhs) => {
+                self.check_node(lhs);
+                self.check_node(rhs);
+                Type::Boolean
+            }
+            AstKind::Lt(lhs, rhs)
+            | AstKind::Lte(lhs, rhs)
+            | AstKind::Gt(lhs, rhs)
+            | AstKind::Gte(lhs, rhs) => {
+                let lt = self.check_node(lhs);
+                let rt = self.check_node(rhs);
+                self.expect_same(Self::comparison_name(ast), lt, rt, |t| {
+                    matches!(t, Type::Number | Type::String | Type::DateTime | Type::Any)
+                });
+                Type::Boolean
+            }
+            AstKind::If(cond, true_branch, false_branch) => {
+                self.check_node(cond);
+                let tt = self.check_guarded(true_branch);
+                match false_branch {
+                    Some(fb) => {
+                        let ft = self.check_guarded(fb);
+                        self.join(&tt, &ft)
+                    }
+                    None => self.resolve(&tt).join(&Type::Null),
+                }
+            }
+            AstKind::While(cond, body) => {
+                self.check_node(cond);
+                self.check_guarded(body);
+                Type::Null
+            }
+            AstKind::Bind(ident, value) => {
+                let ty = self.check_node(value);
+                self.assign(ident, ty, value);
+                Type::Null
+            }
+            AstKind::Let(ident, annotation, value) => {
+                let ty = self.check_node(value);
+                let binding = match annotation {
+                    Some(expected) => {
+                        if self.unify(&ty, expected).is_err() {
+                            self.report(TypeError::BindingMismatch {
+                                ident: ident.clone(),
+                                expected: expected.clone(),
+                                actual: self.resolve(&ty),
+                            });
+                        }
+                        Binding::new(expected.clone(), true)
+                    }
+                    None => self.generalize(ty, value),
+                };
+                self.define(ident, binding);
+                Type::Null
+            }
+            AstKind::Do(stmts) => stmts
+                .iter()
+                .map(|stmt| self.check_node(stmt))
+                .last()
+                .unwrap_or(Type::Null),
+            AstKind::Array(items) => {
+                let mut element = None;
+                for item in items {
+                    let ty = self.check_node(item);
+                    element = Some(match element {
+                        Some(element) => self.join(&element, &ty),
+                        None => ty,
+                    });
+                }
+                let element = element.unwrap_or_else(|| self.fresh());
+                Type::Array(Box::new(element))
+            }
+            AstKind::Object(entries) => {
+                for (_, value) in entries {
+                    self.check_node(value);
+                }
+                Type::Object
+            }
+            AstKind::Fn(params, body) => {
+                let (param_types, ret) = self.check_function(params, body);
+                Self::function_type(params, param_types, ret)
+            }
+            AstKind::Gen(params, body) => {
+                let element = self.fresh();
+                self.yields.push(element);
+                let (param_types, _) = self.check_function(params, body);
+                let element = self.yields.pop().expect("pushed above");
+                let ret = Type::Sequence(Box::new(element));
+                Self::function_type(params, param_types, ret)
+            }
+            AstKind::Yield(arg) => {
+                let ty = self.check_node(arg);
+                if let Some(element) = self.yields.last().cloned() {
+                    let joined = self.join(&element, &ty);
+                    let top = self.yields.last_mut().expect("checked above");
+                    *top = joined;
+                }
+                Type::Null
+            }
+            AstKind::Call(callee, args, named) => {
+                let names = self.callee(callee);
+                let callee = self.check_node(callee);
+                let args = args
+                    .iter()
+                    .map(|arg| self.check_node(arg))
+                    .collect::<Vec<_>>();
+                if named.is_empty() {
+                    return self.check_call(&callee, &args, &names);
+                }
+                // Function types don't record parameter names.
+                for (_, arg) in named {
+                    self.check_node(arg);
+                }
+                Type::Any
+            }
+            AstKind::Map(items, callback) => {
+                let (collection, ret) = self.check_callback("$map", items, callback);
+                match self.resolve(&collection) {
+                    Type::Sequence(_) => Type::Sequence(Box::new(ret)),
+                    _ => Type::Array(Box::new(ret)),
+                }
+            }
+            AstKind::Filter(items, callback) => {
+                let (collection, _) = self.check_callback("$filter", items, callback);
+                collection
+            }
+            AstKind::Reduce(items, callback, init) => {
+                let init = self.check_node(init);
+                let items_ty = self.check_node(items);
+                let callback_ty = self.check_node(callback);
+                let element = self.element_of("$reduce", &items_ty);
+                let ret = self.check_callback_call(&callback_ty, vec![init.clone(), element]);
+                self.join(&init, &ret)
+            }
+            AstKind::Some(items, callback) => {
+                self.check_callback("$some", items, callback);
+                Type::Boolean
+            }
+            AstKind::Every(items, callback) => {
+                self.check_callback("$every", items, callback);
+                Type::Boolean
+            }
+            AstKind::Sort(items, key) => {
+                let element = match key {
+                    Some(key) => self.check_callback("$sort", items, key).0,
+                    None => self.check_node(items),
+                };
+                Type::Array(Box::new(self.element_of("$sort", &element)))
+            }
+            AstKind::GroupBy(items, key) => {
+                self.check_callback("$groupBy", items, key);
+                Type::Array(Box::new(Type::Array(Box::new(Type::Any))))
+            }
+            AstKind::Unique(items) => {
+                let items = self.check_node(items);
+                Type::Array(Box::new(self.element_of("$unique", &items)))
+            }
+            AstKind::Union(lhs, rhs) => {
+                let (lhs, rhs) = (self.check_node(lhs), self.check_node(rhs));
+                let lhs = self.element_of("$union", &lhs);
+                let rhs = self.element_of("$union", &rhs);
+                Type::Array(Box::new(self.join(&lhs, &rhs)))
+            }
+            AstKind::Intersect(lhs, rhs) | AstKind::Difference(lhs, rhs) => {
+                let operation = match ast.kind {
+                    AstKind::Intersect(..) => "$intersect",
+                    _ => "$difference",
+                };
+                let (lhs, rhs) = (self.check_node(lhs), self.check_node(rhs));
+                self.element_of(operation, &rhs);
+                Type::Array(Box::new(self.element_of(operation, &lhs)))
+            }
+            AstKind::Range(start, end, step) => {
+                let bounds = std::iter::once(start).chain(end).chain(step);
+                for bound in bounds {
+                    self.expect_operands("$range", &[bound], &Type::Number);
+                }
+                Type::Sequence(Box::new(Type::Number))
+            }
+            AstKind::Take(items, count) => {
+                let items_ty = self.check_node(items);
+                self.expect_operands("$take", &[count], &Type::Number);
+                Type::Sequence(Box::new(self.element_of("$take", &items_ty)))
+            }
+            AstKind::TakeWhile(items, predicate) => {
+                let (collection, _) = self.check_callback("$takeWhile", items, predicate);
+                Type::Sequence(Box::new(self.element_of("$takeWhile", &collection)))
+            }
+            AstKind::Collect(items) => {
+                let items_ty = self.check_node(items);
+                Type::Array(Box::new(self.element_of("$collect", &items_ty)))
+            }
+            AstKind::StructDecl(name, fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|field| {
+                        let ty = field.ty.clone().unwrap_or(Type::Any);
+                        if let Some(default) = &field.default {
+                            let actual = self.check_node(default);
+                            self.expect_field(name, &field.name, &ty, &actual);
+                        }
+                        StructField {
+                            name: field.name.clone(),
+                            ty,
+                            has_default: field.default.is_some(),
+                        }
+                    })
+                    .collect();
+                self.structs.insert(name.clone(), fields);
+                Type::Null
+            }
+            AstKind::New(name, values) => {
+                self.check_field_values(name, values);
+                if let Some(fields) = self.structs.get(name) {
+                    let missing = fields
+                        .iter()
+                        .filter(|f| !f.has_default && !values.iter().any(|(n, _)| n == &f.name))
+                        .map(|f| TypeError::MissingField {
+                            struct_name: name.clone(),
+                            field: f.name.clone(),
+                        })
+                        .collect::<Vec<_>>();
+                    for error in missing {
+                        self.report(error);
+                    }
+                }
+                Type::Named(name.clone())
+            }
+            AstKind::GetField(target, field) => {
+                let ty = self.check_node(target);
+                match self.resolve(&ty) {
+                    Type::Named(name) => self.field_type(&name, field).unwrap_or(Type::Any),
+                    Type::Any | Type::Var(_) => Type::Any,
+                    other => self.unexpected("$field", vec![other]),
+                }
+            }
+            AstKind::With(target, values) => {
+                let ty = self.check_node(target);
+                match self.resolve(&ty) {
+                    Type::Named(name) => {
+                        self.check_field_values(&name, values);
+                        Type::Named(name)
+                    }
+                    Type::Any | Type::Var(_) => {
+                        for (_, value) in values {
+                            self.check_node(value);
+                        }
+                        ty
+                    }
+                    other => self.unexpected("$with", vec![other]),
+                }
+            }
+            AstKind::Assert(cond, message, _) => {
+                self.check_node(cond);
+                if let Some(message) = message {
+                    self.check_guarded(message);
+                }
+                Type::Null
+            }
+            AstKind::Coalesce(args) => {
+                let mut result = None;
+                for (index, arg) in args.iter().enumerate() {
+                    // Later arguments only run when the ones before are null.
+                    let ty = match index {
+                        0 => self.check_node(arg),
+                        _ => self.check_guarded(arg),
+                    };
+                    if self.resolve(&ty) == Type::Null {
+                        continue;
+                    }
+                    result = Some(match result {
+                        Some(result) => self.join(&result, &ty),
+                        None => ty,
+                    });
+                }
+                result.unwrap_or(Type::Null)
+            }
+            AstKind::SafeGet(target, keys) => {
+                self.check_node(target);
+                for key in keys {
+                    self.check_node(key);
+                }
+                Type::Any
+            }
+            AstKind::Defined(_) => Type::Boolean,
+            AstKind::GetIn(target, path, strict) => {
+                self.check_node(target);
+                self.check_path("$getIn", path);
+                if let Some(strict) = strict {
+                    self.check_node(strict);
+                }
+                Type::Any
+            }
+            AstKind::Query(target, query) => {
+                self.check_node(target);
+                self.expect_operands("$query", &[query], &Type::String);
+                Type::Array(Box::new(Type::Any))
+            }
+            AstKind::SetIn(target, path, value) => {
+                let ty = self.check_node(target);
+                self.check_path("$setIn", path);
+                self.check_node(value);
+                self.updated_container(&ty)
+            }
+            AstKind::UpdateIn(target, path, callback) => {
+                let ty = self.check_node(target);
+                self.check_path("$updateIn", path);
+                let callback = self.check_node(callback);
+                self.check_call(&callback, &[Type::Any], &Callee::anonymous());
+                self.updated_container(&ty)
+            }
+            AstKind::EnumDecl(name, variants) => {
+                let variants = variants
+                    .iter()
+                    .map(|variant| {
+                        let fields = variant
+                            .fields
+                            .iter()
+                            .map(|field| field.ty.clone().unwrap_or(Type::Any))
+                            .collect();
+                        (variant.name.clone(), fields)
+                    })
+                    .collect();
+                self.enums.insert(name.clone(), variants);
+                Type::Null
+            }
+            AstKind::NewVariant(enum_name, tag, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.check_node(arg))
+                    .collect::<Vec<_>>();
+                if let Some(fields) = self.variant_fields(enum_name, tag) {
+                    let signature = Type::Function(fields, Box::new(Type::Null));
+                    let names = Callee {
+                        name: format!("{}.{}", enum_name, tag),
+                        params: Vec::new(),
+                    };
+                    self.check_call(&signature, &args, &names);
+                }
+                Type::Named(enum_name.clone())
+            }
+            AstKind::Tag(value) => {
+                let ty = self.check_node(value);
+                match self.resolve(&ty) {
+                    Type::Named(_) | Type::Any | Type::Var(_) => Type::String,
+                    other => self.unexpected("$tag", vec![other]),
+                }
+            }
+            AstKind::Match(scrutinee, arms) => {
+                let ty = self.check_node(scrutinee);
+                let enum_name = match self.resolve(&ty) {
+                    Type::Named(name) if self.enums.contains_key(&name) => Some(name),
+                    Type::Var(_) => {
+                        let name = self.enum_of_arms(arms);
+                        if let Some(name) = &name {
+                            self.unify(&ty, &Type::Named(name.clone()))
+                                .expect("an unbound variable unifies with a named type");
+                        }
+                        name
+                    }
+                    _ => None,
+                };
+                let results = arms
+                    .iter()
+                    .map(|arm| self.check_match_arm(enum_name.as_deref(), arm))
+                    .collect::<Vec<_>>();
+                let mut results = results.into_iter();
+                let first = results.next().unwrap_or(Type::Any);
+                results.fold(first, |acc, ty| self.join(&acc, &ty))
+            }
+            AstKind::Now => Type::DateTime,
+            AstKind::DateTime(arg) => {
+                self.expect_operands("$dateTime", &[arg], &Type::String);
+                Type::DateTime
+            }
+            AstKind::FormatDateTime(arg, pattern) => {
+                self.expect_operands("$formatDateTime", &[arg], &Type::DateTime);
+                if let Some(pattern) = pattern {
+                    self.expect_operands("$formatDateTime", &[pattern], &Type::String);
+                }
+                Type::String
+            }
+            AstKind::DateAdd(arg, amount, _) => {
+                self.expect_operands("$dateAdd", &[arg], &Type::DateTime);
+                self.expect_operands("$dateAdd", &[amount], &Type::Number);
+                Type::DateTime
+            }
+            AstKind::DateDiff(lhs, rhs, _) => {
+                self.expect_operands("$dateDiff", &[lhs, rhs], &Type::DateTime);
+                Type::Number
+            }
+            AstKind::DatePart(arg, _) => {
+                self.expect_operands("$datePart", &[arg], &Type::DateTime);
+                Type::Number
+            }
+            AstKind::Custom(_, args) => {
+                for arg in args {
+                    self.check_node(arg);
+                }
+                Type::Any
+            }
+            AstKind::Error => Type::Any,
+        }
+    }
+
+    /// Checks field values given to `$new` or `$with` against the declaration.
+    fn check_field_values(&mut self, struct_name: &str, values: &[(String, AstNode)]) {
+        let declared = self.structs.contains_key(struct_name);
+        if !declared {
+            self.report(TypeError::UndefinedStruct(struct_name.to_string()));
+        }
+        for (field, value) in values {
+            let actual = self.check_node(value);
+            if !declared {
+                continue;
+            }
+            if let Some(expected) = self.field_type(struct_name, field) {
+                self.expect_field(struct_name, field, &expected, &actual);
+            }
+        }
+    }
+
+    /// The declared type of `field`, reporting an error if the struct has no such field.
+    fn field_type(&mut self, struct_name: &str, field: &str) -> Option<Type> {
+        let ty = self
+            .structs
+            .get(struct_name)?
+            .iter()
+            .find(|f| f.name == field)
+            .map(|f| f.ty.clone());
+        if ty.is_none() {
+            self.report(TypeError::UnknownField {
+                struct_name: struct_name.to_string(),
+                field: field.to_string(),
+            });
+        }
+        ty
+    }
+
+    fn expect_field(&mut self, struct_name: &str, field: &str, expected: &Type, actual: &Type) {
+        if self.unify(actual, expected).is_err() {
+            self.report(TypeError::FieldMismatch {
+                struct_name: struct_name.to_string(),
+                field: field.to_string(),
+                expected: expected.clone(),
+                actual: self.resolve(actual),
+            });
+        }
+    }
+
+    /// Paths are an array of keys and indices or a JSON Pointer string.
+    fn check_path(&mut self, operation: &'static str, path: &AstNode) {
+        let ty = self.check_node(path);
+        match self.resolve(&ty) {
+            Type::Array(_) | Type::String | Type::Any | Type::Var(_) => {}
+            other => {
+                self.unexpected(operation, vec![other]);
+            }
+        }
+    }
+
+    /// `$setIn`/`$updateIn` keep the container kind, but not its element types.
+    fn updated_container(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Object => Type::Object,
+            Type::Array(_) => Type::Array(Box::new(Type::Any)),
+            _ => Type::Any,
+        }
+    }
+
+    /// The types of the values carried by `tag`, reporting an error if the enum or
+    /// the variant isn't declared.
+    fn variant_fields(&mut self, enum_name: &str, tag: &str) -> Option<Vec<Type>> {
+        let Some(variants) = self.enums.get(enum_name) else {
+            self.report(TypeError::UndefinedEnum(enum_name.to_string()));
+            return None;
+        };
+        let fields = variants
+            .iter()
+            .find(|(name, _)| name == tag)
+            .map(|(_, fields)| fields.clone());
+        if fields.is_none() {
+            self.report(TypeError::UnknownVariant {
+                enum_name: enum_name.to_string(),
+                variant: tag.to_string(),
+            });
+        }
+        fields
+    }
+
+    /// The only declared enum with a variant for every arm's pattern. Patterns don't
+    /// name their enum, so a scrutinee of unknown type is assumed to be of that one.
+    fn enum_of_arms(&self, arms: &[MatchArm]) -> Option<String> {
+        let tags = arms
+            .iter()
+            .filter_map(|arm| match &arm.pattern {
+                Pattern::Variant(tag, _) => Some(tag),
+                Pattern::Wildcard => None,
+            })
+            .collect::<Vec<_>>();
+        if tags.is_empty() {
+            return None;
+        }
+        let mut candidates = self.enums.iter().filter(|(_, variants)| {
+            tags.iter()
+                .all(|tag| variants.iter().any(|(name, _)| &name == tag))
+        });
+        match (candidates.next(), candidates.next()) {
+            (Some((name, _)), None) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Checks an arm's body with its pattern's bindings in scope. Bindings are typed
+    /// from the variant when the scrutinee's enum is known, and `any` otherwise.
+    fn check_match_arm(&mut self, enum_name: Option<&str>, arm: &MatchArm) -> Type {
+        let mut scope = HashMap::new();
+        if let Pattern::Variant(tag, bindings) = &arm.pattern {
+            let fields = match enum_name {
+                Some(enum_name) => self.variant_fields(enum_name, tag),
+                None => None,
+            };
+            if let Some(fields) = &fields {
+                if !bindings.is_empty() && bindings.len() != fields.len() {
+                    self.report(TypeError::PatternArityMismatch {
+                        variant: tag.clone(),
+                        actual: bindings.len(),
+                        expected: fields.len(),
+                    });
+                }
+            }
+            for (index, binding) in bindings.iter().enumerate() {
+                if let Some(ident) = binding {
+                    let ty = fields
+                        .as_ref()
+                        .and_then(|fields| fields.get(index).cloned())
+                        .unwrap_or(Type::Any);
+                    self.bindings.push((ident.clone(), ty.clone()));
+                    scope.insert(ident.clone(), Binding::new(ty, false));
+                }
+            }
+        }
+        self.scopes.push(scope);
+        let ty = self.check_guarded(&arm.body);
+        self.scopes.pop();
+        ty
+    }
+
+    fn check_function(&mut self, params: &[Param], body: &AstNode) -> (Vec<Type>, Type) {
+        let param_types = params
+            .iter()
+            .map(|p| match &p.ty {
+                Some(ty) if p.rest => Type::Array(Box::new(ty.clone())),
+                Some(ty) => ty.clone(),
+                None if self.definite => Type::Any,
+                None if p.rest => Type::Array(Box::new(self.fresh())),
+                None => self.fresh(),
+            })
+            .collect::<Vec<_>>();
+        // Defaults see the parameters before them, so parameters are added one by one.
+        self.guarded += 1;
+        self.scopes.push(HashMap::new());
+        for (param, ty) in params.iter().zip(&param_types) {
+            if let Some(default) = &param.default {
+                let actual = self.check_node(default);
+                if self.unify(&actual, ty).is_err() {
+                    self.report(TypeError::BindingMismatch {
+                        ident: param.ident.clone(),
+                        expected: self.resolve(ty),
+                        actual: self.resolve(&actual),
+                    });
+                }
+            }
+            self.bindings.push((param.ident.clone(), ty.clone()));
+            self.scopes.last_mut().expect("pushed above").insert(
+                param.ident.clone(),
+                Binding::new(ty.clone(), param.ty.is_some()),
+            );
+        }
+        let ret = self.check_node(body);
+        self.scopes.pop();
+        self.guarded -= 1;
+        (param_types, ret)
+    }
+
+    /// Function types have a fixed arity, so functions with default or rest
+    /// parameters are `any` and their calls are only checked at runtime.
+    fn function_type(params: &[Param], param_types: Vec<Type>, ret: Type) -> Type {
+        if params.iter().any(|p| p.rest || p.default.is_some()) {
+            Type::Any
+        } else {
+            Type::Function(param_types, Box::new(ret))
+        }
+    }
+
+    /// The name and parameter names of a function called as `callee`, known when it
+    /// is a `$fn` literal or a reference to one bound by `$let` or `$bind`.
+    fn callee(&self, callee: &AstNode) -> Callee {
+        match &callee.kind {
+            AstKind::Ident(ident) => Callee {
+                name: ident.0.clone(),
+                params: self
+                    .scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.get(ident))
+                    .map(|binding| binding.params.clone())
+                    .unwrap_or_default(),
+            },
+            AstKind::Fn(params, _) | AstKind::Gen(params, _) => Callee {
+                params: Self::param_names(params),
+                ..Callee::anonymous()
+            },
+            _ => Callee::anonymous(),
+        }
+    }
+
+    fn param_names(params: &[Param]) -> Vec<String> {
+        params.iter().map(|p| p.ident.0.clone()).collect()
+    }
+
+    fn check_call(&mut self, callee: &Type, args: &[Type], names: &Callee) -> Type {
+        match self.resolve(callee) {
+            Type::Any => Type::Any,
+            Type::Var(_) => {
+                let ret = self.fresh();
+                let signature = Type::Function(args.to_vec(), Box::new(ret.clone()));
+                // Fails the occurs check when the callee is among its own arguments,
+                // as in `f(f)`. That can run fine, but has no finite type.
+                match self.unify(callee, &signature) {
+                    Ok(()) => ret,
+                    Err(_) => Type::Any,
+                }
+            }
+            Type::Function(params, ret) => {
+                if params.len() != args.len() {
+                    self.report(TypeError::ArgumentCountMismatch {
+                        function: names.name.clone(),
+                        param: names.params.get(args.len()).cloned(),
+                        actual: args.len(),
+                        expected: params.len(),
+                    });
+                }
+                for (index, (param, arg)) in params.iter().zip(args).enumerate() {
+                    if self.unify(arg, param).is_err() {
+                        self.report(TypeError::ArgumentMismatch {
+                            index,
+                            expected: self.resolve(param),
+                            actual: self.resolve(arg),
+                        });
+                    }
+                }
+                *ret
+            }
+            other => {
+                self.report(TypeError::NotCallable(other));
+                Type::Any
+            }
+        }
+    }
+
+    /// Checks a collection form whose callback takes an element and its index.
+    /// Returns the collection type and the callback's return type.
+    fn check_callback(
+        &mut self,
+        operation: &'static str,
+        items: &AstNode,
+        callback: &AstNode,
+    ) -> (Type, Type) {
+        let items_ty = self.check_node(items);
+        let callback_ty = self.check_node(callback);
+        let element = self.element_of(operation, &items_ty);
+        let ret = self.check_callback_call(&callback_ty, vec![element]);
+        (items_ty, ret)
+    }
+
+    /// Like `Interpreter::call_callback`, the trailing index argument is only passed
+    /// when the callback declares a parameter for it.
+    fn check_callback_call(&mut self, callback: &Type, mut args: Vec<Type>) -> Type {
+        if let Type::Function(params, _) = self.resolve(callback) {
+            args.push(Type::Number);
+            args.truncate(params.len());
+        }
+        self.check_call(callback, &args, &Callee::anonymous())
+    }
+
+    fn element_of(&mut self, operation: &'static str, collection: &Type) -> Type {
+        match self.resolve(collection) {
+            Type::Array(element) | Type::Sequence(element) => *element,
+            Type::Any => Type::Any,
+            Type::Var(_) => {
+                let element = self.fresh();
+                let array = Type::Array(Box::new(element.clone()));
+                self.unify(collection, &array)
+                    .expect("an unbound variable unifies with a type it doesn't occur in");
+                element
+            }
+            other => self.unexpected(operation, vec![other]),
+        }
+    }
+
+    fn expect_operands(&mut self, operation: &'static str, operands: &[&AstNode], expected: &Type) {
+        let types = operands
+            .iter()
+            .map(|operand| self.check_node(operand))
+            .collect::<Vec<_>>();
+        let mismatched = types
+            .iter()
+            .filter(|ty| self.unify(ty, expected).is_err())
+            .count();
+        if mismatched > 0 {
+            let operands = types.iter().map(|ty| self.resolve(ty)).collect();
+            self.unexpected(operation, operands);
+        }
+    }
+
+    /// Requires both operands of `operation` to have the same type, which `allowed`
+    /// accepts. Returns that type.
+    fn expect_same(
+        &mut self,
+        operation: &'static str,
+        lt: Type,
+        rt: Type,
+        allowed: fn(&Type) -> bool,
+    ) -> Type {
+        let operands = vec![lt.clone(), rt.clone()];
+        if self.unify(&lt, &rt).is_err() {
+            let operands = operands.iter().map(|ty| self.resolve(ty)).collect();
+            return self.unexpected(operation, operands);
+        }
+        // After unification the operands only differ when one of them is `any`.
+        let ty = match self.resolve(&lt) {
+            Type::Any => self.resolve(&rt),
+            ty => ty,
+        };
+        match ty {
+            Type::Var(_) if !self.reporting() => ty,
+            Type::Var(_) => {
+                self.deferred.push(Deferred {
+                    ty: ty.clone(),
+                    operation,
+                    operands,
+                    allowed,
+                });
+                ty
+            }
+            ty if allowed(&ty) => ty,
+            _ => {
+                let operands = operands.iter().map(|ty| self.resolve(ty)).collect();
+                self.unexpected(operation, operands)
+            }
+        }
+    }
+
+    fn unexpected(&mut self, operation: &'static str, operands: Vec<Type>) -> Type {
+        self.report(TypeError::UnexpectedTypeForOperation {
+            operation,
+            operands,
+        });
+        Type::Any
+    }
+
+    fn comparison_name(ast: &AstNode) -> &'static str {
+        match ast.kind {
+            AstKind::Lt(..) => "$lt",
+            AstKind::Lte(..) => "$lte",
+            AstKind::Gt(..) => "$gt",
+            _ => "$gte",
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.next_var += 1;
+        Type::Var(self.next_var - 1)
+    }
+
+    /// Applies the current substitution to `ty`.
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(v) => match self.substitution.get(v) {
+                Some(bound) => self.resolve(bound),
+                None => ty.clone(),
+            },
+            Type::Array(element) => Type::Array(Box::new(self.resolve(element))),
+            Type::Sequence(element) => Type::Sequence(Box::new(self.resolve(element))),
+            Type::Function(params, ret) => Type::Function(
+                params.iter().map(|p| self.resolve(p)).collect(),
+                Box::new(self.resolve(ret)),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    fn unify(&mut self, a: &Type, b: &Type) -> Result<(), ()> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Any, _) | (_, Type::Any) => Ok(()),
+            (Type::Var(x), Type::Var(y)) if x == y => Ok(()),
+            (Type::Var(v), other) | (other, Type::Var(v)) => {
+                let mut vars = HashSet::new();
+                other.free_vars(&mut vars);
+                if vars.contains(&v) {
+                    return Err(());
+                }
+                self.substitution.insert(v, other);
+                Ok(())
+            }
+            (Type::Array(x), Type::Array(y)) | (Type::Sequence(x), Type::Sequence(y)) => {
+                self.unify(&x, &y)
+            }
+            (Type::Function(xp, xr), Type::Function(yp, yr)) if xp.len() == yp.len() => {
+                for (x, y) in xp.iter().zip(&yp) {
+                    self.unify(x, y)?;
+                }
+                self.unify(&xr, &yr)
+            }
+            (x, y) if x == y => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    /// Unifies `a` and `b` when possible; otherwise the values may be either, which
+    /// is `any`. Used where the interpreter allows differing types, like the two
+    /// branches of an `$if`.
+    ///
+    /// A definite checker doesn't narrow `any`: the values may still be anything.
+    fn join(&mut self, a: &Type, b: &Type) -> Type {
+        if self.definite && (self.resolve(a) == Type::Any || self.resolve(b) == Type::Any) {
+            return Type::Any;
+        }
+        let saved = self.substitution.clone();
+        if self.unify(a, b).is_ok() {
+            self.resolve(a)
+        } else {
+            self.substitution = saved;
+            Type::Any
+        }
+    }
+
+    /// Quantifies the type variables of a function bound by `$let`/`$bind` that don't
+    /// appear in any enclosing binding.
+    fn generalize(&self, ty: Type, value: &AstNode) -> Binding {
+        let ty = self.resolve(&ty);
+        let params = match &value.kind {
+            AstKind::Fn(params, _) | AstKind::Gen(params, _) => Self::param_names(params),
+            _ => return Binding::new(ty, false),
+        };
+        let mut free = HashSet::new();
+        ty.free_vars(&mut free);
+        let mut in_scope = HashSet::new();
+        for binding in self.scopes.iter().flat_map(|scope| scope.values()) {
+            self.resolve(&binding.ty).free_vars(&mut in_scope);
+        }
+        let mut quantified = free.difference(&in_scope).copied().collect::<Vec<_>>();
+        quantified.sort_unstable();
+        Binding {
+            ty,
+            declared: false,
+            quantified,
+            params,
+        }
+    }
+
+    fn lookup(&mut self, ident: &Ident) -> Option<Type> {
+        let binding = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(ident))?;
+        let (ty, quantified) = (binding.ty.clone(), binding.quantified.clone());
+        let mapping = quantified
+            .into_iter()
+            .map(|v| (v, self.fresh()))
+            .collect::<HashMap<_, _>>();
+        // Constraints on the generalized variables apply to every instance.
+        let instantiated = self
+            .deferred
+            .iter()
+            .filter(|d| matches!(self.resolve(&d.ty), Type::Var(v) if mapping.contains_key(&v)))
+            .map(|d| Deferred {
+                ty: self.resolve(&d.ty).substitute(&mapping),
+                operands: d
+                    .operands
+                    .iter()
+                    .map(|t| self.resolve(t).substitute(&mapping))
+                    .collect(),
+                ..d.clone()
+            })
+            .collect::<Vec<_>>();
+        self.deferred.extend(instantiated);
+        Some(self.resolve(&ty).substitute(&mapping))
+    }
+
+    fn define(&mut self, ident: &Ident, binding: Binding) {
+        self.bindings.push((ident.clone(), binding.ty.clone()));
+        self.scopes
+            .last_mut()
+            .expect("global scope")
+            .insert(ident.clone(), binding);
+    }
+
+    /// Mirrors `Environment::assign`: updates the nearest binding, or creates one in
+    /// the innermost scope.
+    fn assign(&mut self, ident: &Ident, ty: Type, value: &AstNode) {
+        let existing = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(ident))
+            .map(|binding| (binding.ty.clone(), binding.declared));
+        match existing {
+            Some((declared_ty, true)) => {
+                if self.unify(&ty, &declared_ty).is_err() {
+                    self.report(TypeError::BindingMismatch {
+                        ident: ident.clone(),
+                        expected: self.resolve(&declared_ty),
+                        actual: self.resolve(&ty),
+                    });
+                }
+            }
+            Some((previous, false)) => {
+                let joined = self.join(&previous, &ty);
+                self.bindings.push((ident.clone(), joined.clone()));
+                let binding = self
+                    .scopes
+                    .iter_mut()
+                    .rev()
+                    .find_map(|scope| scope.get_mut(ident))
+                    .expect("found above");
+                *binding = Binding::new(joined, false);
+            }
+            None => {
+                let binding = self.generalize(ty, value);
+                self.define(ident, binding);
+            }
+        }
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jir::JirParser;
+
+    fn check(checker: &mut TypeChecker, json: &str) -> Result<Type, Vec<TypeError>> {
+        checker.check(&JirParser::parse_json(json).expect("valid JIR"))
+    }
+
+    #[test]
+    fn it_parses_type_annotations() {
+        assert_eq!(Type::parse("number"), Some(Type::Number));
+        assert_eq!(
+            Type::parse("array<array<string>>"),
+            Some(Type::Array(Box::new(Type::Array(Box::new(Type::String)))))
+        );
+        assert_eq!(
+            Type::parse("(number, array) -> boolean"),
+            Some(Type::Function(
+                vec![Type::Number, Type::Array(Box::new(Type::Any))],
+                Box::new(Type::Boolean)
+            ))
+        );
+        assert_eq!(
+            Type::parse("() -> null").map(|t| t.to_string()),
+            Some("() -> null".into())
+        );
+        assert_eq!(Type::parse("num"), None);
+
+        let callback = Type::Function(vec![Type::Number], Box::new(Type::Number));
+        assert_eq!(
+            Type::parse("((number) -> number) -> number"),
+            Some(Type::Function(
+                vec![callback.clone()],
+                Box::new(Type::Number)
+            ))
+        );
+        assert_eq!(
+            Type::parse("array<(number) -> number>"),
+            Some(Type::Array(Box::new(callback)))
+        );
+        assert_eq!(
+            Type::parse("(array<number>, (number) -> boolean) -> array<number>")
+                .map(|t| t.to_string()),
+            Some("(array<number>, (number) -> boolean) -> array<number>".into())
+        );
+    }
+
+    #[test]
+    fn it_reports_operations_on_wrong_types() {
+        let mut checker = TypeChecker::new();
+
+        assert_eq!(
+            check(&mut checker, r#"["$sub", "a", 1]"#),
+            Err(vec![TypeError::UnexpectedTypeForOperation {
+                operation: "$sub",
+                operands: vec![Type::String, Type::Number],
+            }])
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$add", 1, "a"]"#),
+            Err(vec![TypeError::UnexpectedTypeForOperation {
+                operation: "$add",
+                operands: vec![Type::Number, Type::String],
+            }])
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$add", "a", "b"]"#),
+            Ok(Type::String)
+        );
+    }
+
+    #[test]
+    fn it_checks_annotated_bindings_across_calls() {
+        let mut checker = TypeChecker::new();
+
+        assert_eq!(
+            check(&mut checker, r#"["$let", "x", "number", 1]"#),
+            Ok(Type::Null)
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$bind", "x", "one"]"#),
+            Err(vec![TypeError::BindingMismatch {
+                ident: Ident("x".into()),
+                expected: Type::Number,
+                actual: Type::String,
+            }])
+        );
+        assert_eq!(check(&mut checker, r#"["$ref", "x"]"#), Ok(Type::Number));
+        assert!(check(&mut checker, r#"["$let", "s", "string", ["$ref", "x"]]"#).is_err());
+    }
+
+    #[test]
+    fn it_checks_annotated_function_params() {
+        let mut checker = TypeChecker::new();
+
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$let", "inc", ["$fn", [["n", "number"]], ["$add", ["$ref", "n"], 1]]]"#
+            ),
+            Ok(Type::Null)
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$ref", "inc"]"#),
+            Ok(Type::Function(vec![Type::Number], Box::new(Type::Number)))
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$call", ["$ref", "inc"], "one"]"#),
+            Err(vec![TypeError::ArgumentMismatch {
+                index: 0,
+                expected: Type::Number,
+                actual: Type::String,
+            }])
+        );
+        let errors =
+            check(&mut checker, r#"["$call", ["$ref", "inc"]]"#).expect_err("n is missing");
+        assert_eq!(
+            errors,
+            vec![TypeError::ArgumentCountMismatch {
+                function: "inc".into(),
+                param: Some("n".into()),
+                actual: 0,
+                expected: 1,
+            }]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "`inc` expects 1 arguments but got 0, missing `n`"
+        );
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$map", ["$array", "a", "b"], ["$ref", "inc"]]"#
+            ),
+            Err(vec![TypeError::ArgumentMismatch {
+                index: 0,
+                expected: Type::Number,
+                actual: Type::String,
+            }])
+        );
+    }
+
+    #[test]
+    fn it_infers_unannotated_functions() -> Result<(), Vec<TypeError>> {
+        let mut checker = TypeChecker::new();
+
+        let ast = JirParser::parse_json(
+            r#"["$fn", ["x", "y"], ["$if", ["$lt", ["$ref", "x"], 0], ["$ref", "y"], ["$sub", ["$ref", "x"], 1]]]"#,
+        )
+        .expect("valid JIR");
+        let inference = checker.infer(&ast)?;
+        assert_eq!(inference.ty.to_string(), "(number, number) -> number");
+        assert_eq!(
+            inference.bindings,
+            vec![
+                (Ident("x".into()), Type::Number),
+                (Ident("y".into()), Type::Number)
+            ]
+        );
+        // Types are found by node, wherever the tree has moved since.
+        let ast = Box::new(ast);
+        match &ast.kind {
+            AstKind::Fn(_, body) => {
+                assert_eq!(inference.type_of(body), Some(&Type::Number));
+            }
+            other => panic!("unexpected node: {:?}", other),
+        }
+
+        check(
+            &mut checker,
+            r#"["$let", "twice", ["$fn", ["f", "v"], ["$call", ["$ref", "f"], ["$call", ["$ref", "f"], ["$ref", "v"]]]]]"#,
+        )?;
+        match check(&mut checker, r#"["$ref", "twice"]"#)? {
+            Type::Function(params, ret) => match params.as_slice() {
+                [Type::Function(f_params, f_ret), v] => {
+                    assert!(matches!(v, Type::Var(_)));
+                    assert_eq!(f_params, &vec![v.clone()]);
+                    assert_eq!(**f_ret, *v);
+                    assert_eq!(*ret, *v);
+                }
+                other => panic!("unexpected params: {:?}", other),
+            },
+            other => panic!("unexpected type: {}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_instantiates_generalized_functions_per_use() {
+        let mut checker = TypeChecker::new();
+
+        assert!(check(
+            &mut checker,
+            r#"["$let", "id", ["$fn", ["x"], ["$ref", "x"]]]"#
+        )
+        .is_ok());
+        assert_eq!(
+            check(&mut checker, r#"["$call", ["$ref", "id"], 1]"#),
+            Ok(Type::Number)
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$call", ["$ref", "id"], "a"]"#),
+            Ok(Type::String)
+        );
+    }
+
+    #[test]
+    fn it_accepts_self_application() {
+        let mut checker = TypeChecker::new();
+
+        assert!(check(
+            &mut checker,
+            r#"["$fn", ["f"], ["$call", ["$ref", "f"], ["$ref", "f"]]]"#
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn it_flags_certain_failures_found_by_inference() {
+        let mut checker = TypeChecker::new();
+
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$do",
+                    ["$let", "inc", ["$fn", ["n"], ["$add", ["$ref", "n"], 1]]],
+                    ["$call", ["$ref", "inc"], "one"]
+                ]"#
+            ),
+            Err(vec![TypeError::ArgumentMismatch {
+                index: 0,
+                expected: Type::Number,
+                actual: Type::String,
+            }])
+        );
+
+        // `$add` is only known to be invalid once `a` is resolved by the call below.
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$do",
+                    ["$let", "pair", ["$fn", ["a", "b"], ["$add", ["$ref", "a"], ["$ref", "b"]]]],
+                    ["$let", "flag", ["$fn", ["a"], ["$call", ["$ref", "pair"], ["$ref", "a"], true]]],
+                    ["$call", ["$ref", "flag"], true]
+                ]"#
+            ),
+            Err(vec![TypeError::UnexpectedTypeForOperation {
+                operation: "$add",
+                operands: vec![Type::Boolean, Type::Boolean],
+            }])
+        );
+    }
+
+    #[test]
+    fn it_checks_default_and_rest_params() {
+        let mut checker = TypeChecker::new();
+
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$fn", [["n", "number", 1], "...rest"], ["$collect", ["$ref", "rest"]]]"#
+            ),
+            Ok(Type::Any)
+        );
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$fn", [["n", "number", "one"]], ["$ref", "n"]]"#
+            ),
+            Err(vec![TypeError::BindingMismatch {
+                ident: Ident("n".into()),
+                expected: Type::Number,
+                actual: Type::String,
+            }])
+        );
+        // A rest parameter's annotation is the type of its elements.
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$fn", [["...xs", "number"]], ["$sub", ["$collect", ["$ref", "xs"]], 1]]"#
+            ),
+            Err(vec![TypeError::UnexpectedTypeForOperation {
+                operation: "$sub",
+                operands: vec![Type::Array(Box::new(Type::Number)), Type::Number],
+            }])
+        );
+    }
+
+    #[test]
+    fn it_checks_struct_fields() {
+        let mut checker = TypeChecker::new();
+
+        assert!(check(
+            &mut checker,
+            r#"["$struct", "Order", ["id", ["qty", "number", 1]]]"#
+        )
+        .is_ok());
+        assert_eq!(
+            check(&mut checker, r#"["$new", "Order", {"id": 1}]"#),
+            Ok(Type::Named("Order".into()))
+        );
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$field", ["$new", "Order", {"id": 1}], "qty"]"#
+            ),
+            Ok(Type::Number)
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$new", "Order", {"qty": "many"}]"#),
+            Err(vec![
+                TypeError::FieldMismatch {
+                    struct_name: "Order".into(),
+                    field: "qty".into(),
+                    expected: Type::Number,
+                    actual: Type::String,
+                },
+                TypeError::MissingField {
+                    struct_name: "Order".into(),
+                    field: "id".into(),
+                }
+            ])
+        );
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$let", "o", "Order", ["$with", ["$new", "Order", {"id": 1}], {"price": 3}]]"#
+            ),
+            Err(vec![TypeError::UnknownField {
+                struct_name: "Order".into(),
+                field: "price".into(),
+            }])
+        );
+    }
+
+    #[test]
+    fn it_checks_enum_variants_and_matches() {
+        let mut checker = TypeChecker::new();
+
+        assert!(check(
+            &mut checker,
+            r#"["$enum", "Decision", ["Approved", ["Rejected", ["reason", "string"]]]]"#
+        )
+        .is_ok());
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$match", ["$variant", "Decision", "Rejected", "late"],
+                    ["Approved", "ok"],
+                    [["Rejected", "why"], ["$ref", "why"]]]"#
+            ),
+            Ok(Type::String)
+        );
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$tag", ["$variant", "Decision", "Approved"]]"#
+            ),
+            Ok(Type::String)
+        );
+        assert_eq!(
+            check(&mut checker, r#"["$variant", "Decision", "Rejected", 1]"#),
+            Err(vec![TypeError::ArgumentMismatch {
+                index: 0,
+                expected: Type::String,
+                actual: Type::Number,
+            }])
+        );
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$match", ["$variant", "Decision", "Approved"],
+                    ["Pending", 1],
+                    [["Rejected", "a", "b"], 2]]"#
+            ),
+            Err(vec![
+                TypeError::UnknownVariant {
+                    enum_name: "Decision".into(),
+                    variant: "Pending".into(),
+                },
+                TypeError::PatternArityMismatch {
+                    variant: "Rejected".into(),
+                    actual: 2,
+                    expected: 1,
+                }
+            ])
+        );
+
+        assert!(check(
+            &mut checker,
+            r#"["$enum", "Appeal", [["Rejected", "reason"]]]"#
+        )
+        .is_ok());
+        assert!(check(
+            &mut checker,
+            r#"["$let", "describe", ["$fn", ["d"], ["$match", ["$ref", "d"], ["Approved", "ok"], ["_", "no"]]]]"#
+        )
+        .is_ok());
+        assert_eq!(
+            check(
+                &mut checker,
+                r#"["$call", ["$ref", "describe"], ["$variant", "Appeal", "Rejected", "late"]]"#
+            ),
+            Err(vec![TypeError::ArgumentMismatch {
+                index: 0,
+                expected: Type::Named("Decision".into()),
+                actual: Type::Named("Appeal".into()),
+            }])
+        );
+    }
+
+    #[test]
+    fn it_reports_every_error_in_a_program() {
+        let mut checker = TypeChecker::new();
+
+        let errors = check(
+            &mut checker,
+            r#"["$do", ["$sub", "a", 1], ["$while", true, ["$dateAdd", 1, 2, "days"]]]"#,
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "cannot apply $sub to string, number");
+        assert_eq!(errors[1].to_string(), "cannot apply $dateAdd to number");
+    }
+
+    #[test]
+    fn it_reports_only_certain_errors_when_definite() {
+        let mut checker = TypeChecker::definite();
+
+        assert!(check(&mut checker, r#"["$if", false, ["$sub", null, 1]]"#).is_ok());
+        assert!(check(
+            &mut checker,
+            r#"["$let", "dec", ["$fn", ["x"], ["$if", ["$ref", "x"], ["$sub", ["$ref", "x"], 1], "none"]]]"#,
+        )
+        .is_ok());
+        assert!(check(
+            &mut checker,
+            r#"["$sub", ["$call", ["$ref", "dec"], 5], 1]"#
+        )
+        .is_ok());
+
+        let errors = check(
+            &mut checker,
+            r#"["$do", ["$if", true, 1, 2], ["$sub", null, 1]]"#,
+        )
+        .expect_err("$sub always runs");
+        assert_eq!(errors[0].to_string(), "cannot apply $sub to null, number");
+        let errors = check(
+            &mut checker,
+            r#"["$call", ["$fn", [["x", "number"]], ["$ref", "x"]], "one"]"#,
+        )
+        .expect_err("the parameter is annotated");
+        assert!(matches!(errors[0], TypeError::ArgumentMismatch { .. }));
+        let errors = check(&mut checker, r#"["$and", false, ["$sub", null, 1]]"#)
+            .expect_err("$and evaluates both sides");
+        assert_eq!(errors[0].to_string(), "cannot apply $sub to null, number");
+    }
+}
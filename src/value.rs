@@ -1,7 +1,262 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::ast::{AstNode, Param};
+use crate::environment::Environment;
+use crate::sequence::Sequence;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Null,
     Number(f64),
     String(String),
     Boolean(bool),
+    /// Compared by instant, so the same moment in different offsets is equal.
+    DateTime(DateTime<FixedOffset>),
+    Array(Vec<Value>),
+    /// A JSON object, produced by `$quote`. Keys are kept sorted.
+    Object(BTreeMap<String, Value>),
+    Function(Rc<Function>),
+    Sequence(Sequence),
+    Struct(StructValue),
+    Variant(VariantValue),
+}
+
+impl Value {
+    /// A total ordering over all values, used by `$sort`, `$groupBy` and the set
+    /// operations.
+    ///
+    /// Values of different kinds are ordered by kind: null, booleans, numbers,
+    /// strings, date-times, arrays, objects, structs, enum variants, functions and
+    /// sequences. Within a kind:
+    ///
+    /// - `false` comes before `true`,
+    /// - numbers are ordered numerically, `-0` equals `0`, and NaN equals itself and
+    ///   comes after every other number,
+    /// - strings are ordered by their UTF-8 bytes,
+    /// - date-times are ordered by instant, like `==`,
+    /// - arrays are ordered lexicographically, objects as sorted `(key, value)`
+    ///   lists, structs by name then fields, variants by enum, tag then values,
+    /// - functions and sequences are ordered by identity, which is only stable for
+    ///   the lifetime of the values.
+    ///
+    /// This agrees with `==` except that NaN is equal to itself.
+    pub fn total_cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::Number(a), Value::Number(b)) => a
+                .partial_cmp(b)
+                .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan())),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::DateTime(a), Value::DateTime(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) => cmp_all(a, b),
+            (Value::Object(a), Value::Object(b)) => a
+                .iter()
+                .map(|(key, value)| (key, Key(value)))
+                .cmp(b.iter().map(|(key, value)| (key, Key(value)))),
+            (Value::Struct(a), Value::Struct(b)) => a.name.cmp(&b.name).then_with(|| {
+                a.fields
+                    .iter()
+                    .map(|(name, value)| (name, Key(value)))
+                    .cmp(b.fields.iter().map(|(name, value)| (name, Key(value))))
+            }),
+            (Value::Variant(a), Value::Variant(b)) => a
+                .enum_name
+                .cmp(&b.enum_name)
+                .then_with(|| a.tag.cmp(&b.tag))
+                .then_with(|| cmp_all(&a.values, &b.values)),
+            (Value::Function(a), Value::Function(b)) => Rc::as_ptr(a).cmp(&Rc::as_ptr(b)),
+            (Value::Sequence(a), Value::Sequence(b)) => a.id().cmp(&b.id()),
+            _ => self.kind_rank().cmp(&other.kind_rank()),
+        }
+    }
+
+    fn kind_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::DateTime(_) => 4,
+            Value::Array(_) => 5,
+            Value::Object(_) => 6,
+            Value::Struct(_) => 7,
+            Value::Variant(_) => 8,
+            Value::Function(_) => 9,
+            Value::Sequence(_) => 10,
+        }
+    }
+}
+
+fn cmp_all(a: &[Value], b: &[Value]) -> Ordering {
+    a.iter().map(Key).cmp(b.iter().map(Key))
+}
+
+/// Borrows a `Value` as a key for `HashMap`, `HashSet` or `BTreeMap`.
+///
+/// `Value` can't be `Eq` or `Hash` itself because NaN isn't equal to itself. Keys
+/// compare with `Value::total_cmp` instead, and hash consistently with it.
+#[derive(Debug, Clone, Copy)]
+pub struct Key<'a>(pub &'a Value);
+
+impl PartialEq for Key<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.total_cmp(other.0) == Ordering::Equal
+    }
+}
+
+impl Eq for Key<'_> {}
+
+impl PartialOrd for Key<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(other.0)
+    }
+}
+
+impl Hash for Key<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let value = self.0;
+        value.kind_rank().hash(state);
+        match value {
+            Value::Null => {}
+            Value::Boolean(b) => b.hash(state),
+            // Equal numbers must hash alike, so -0 hashes as 0 and every NaN the same.
+            Value::Number(n) if *n == 0.0 => 0u64.hash(state),
+            Value::Number(n) if n.is_nan() => f64::NAN.to_bits().hash(state),
+            Value::Number(n) => n.to_bits().hash(state),
+            Value::String(s) => s.hash(state),
+            Value::DateTime(dt) => (dt.timestamp(), dt.timestamp_subsec_nanos()).hash(state),
+            Value::Array(items) => items.iter().map(Key).for_each(|key| key.hash(state)),
+            Value::Object(entries) => entries.iter().for_each(|(key, value)| {
+                key.hash(state);
+                Key(value).hash(state);
+            }),
+            Value::Struct(instance) => {
+                instance.name.hash(state);
+                instance.fields.iter().for_each(|(name, value)| {
+                    name.hash(state);
+                    Key(value).hash(state);
+                });
+            }
+            Value::Variant(variant) => {
+                variant.enum_name.hash(state);
+                variant.tag.hash(state);
+                variant
+                    .values
+                    .iter()
+                    .map(Key)
+                    .for_each(|key| key.hash(state));
+            }
+            Value::Function(f) => Rc::as_ptr(f).hash(state),
+            Value::Sequence(seq) => seq.id().hash(state),
+        }
+    }
+}
+
+/// An instance of a `$struct`. Fields are kept in declaration order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructValue {
+    pub name: String,
+    pub fields: Vec<(String, Value)>,
+}
+
+impl StructValue {
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value)
+    }
+}
+
+/// A value of an `$enum`, tagged with the variant that constructed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantValue {
+    pub enum_name: String,
+    pub tag: String,
+    pub values: Vec<Value>,
+}
+
+/// A closure created by `$fn` or `$gen`. Functions are only equal to themselves.
+pub struct Function {
+    /// The identifier a function literal was bound to by `$bind`/`$let`, used in
+    /// error messages.
+    pub name: Option<String>,
+    pub params: Vec<Param>,
+    pub body: Rc<AstNode>,
+    /// Calling a generator function returns a `Sequence` that runs the body lazily.
+    pub generator: bool,
+    pub(crate) env: Rc<RefCell<Environment>>,
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("params", &self.params)
+            .field("generator", &self.generator)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn it_orders_values_across_kinds() {
+        let mut values = vec![
+            Value::Array(vec![Value::Number(1.0)]),
+            Value::String("b".into()),
+            Value::Number(f64::NAN),
+            Value::Boolean(true),
+            Value::Number(-1.0),
+            Value::Null,
+            Value::String("a".into()),
+            Value::Array(vec![]),
+            Value::Boolean(false),
+        ];
+        values.sort_by(Value::total_cmp);
+
+        assert_eq!(
+            format!("{:?}", values),
+            "[Null, Boolean(false), Boolean(true), Number(-1.0), Number(NaN), \
+             String(\"a\"), String(\"b\"), Array([]), Array([Number(1.0)])]"
+        );
+    }
+
+    #[test]
+    fn it_hashes_keys_consistently_with_ordering() {
+        let values = [
+            Value::Number(0.0),
+            Value::Number(-0.0),
+            Value::Number(f64::NAN),
+            Value::Number(f64::NAN),
+            Value::Array(vec![Value::Null]),
+            Value::Array(vec![Value::Null]),
+        ];
+        let keys = values.iter().map(Key).collect::<HashSet<_>>();
+        assert_eq!(keys.len(), 3);
+    }
 }
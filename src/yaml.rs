@@ -0,0 +1,301 @@
+//! Reads YAML into the same `Json` trees as the JSON syntax, keeping the position
+//! of every value for error messages.
+//!
+//! ```yaml
+//! # Increments x.
+//! - $bind
+//! - x
+//! - [$add, [$ref, x], 1]
+//! ```
+//!
+//! Plain scalars are resolved with the YAML core schema, so `1`, `true` and `null`
+//! are not strings unless quoted. Mapping keys must be scalars. Anchors and aliases
+//! are expanded. Only the first document of a stream is read.
+
+use std::collections::HashMap;
+
+use yaml_rust::parser::{MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle, TokenType};
+use yaml_rust::{Event, Yaml};
+
+use crate::jir::ParseError;
+use crate::json::{Json, JsonKind, MAX_DEPTH};
+use crate::span::{Located, Span};
+
+/// Reads the first document of a YAML stream.
+pub fn parse(source: &str) -> Result<Json, Located<ParseError>> {
+    let mut builder = Builder {
+        source,
+        offsets: source
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(source.len()))
+            .collect(),
+        open: Vec::new(),
+        anchors: HashMap::new(),
+        document: None,
+        error: None,
+    };
+    // Events are pulled one at a time rather than with `Parser::load`, which
+    // recurses for every level of nesting before the builder can refuse it.
+    let mut parser = Parser::new(source.chars());
+    while builder.error.is_none() && builder.document.is_none() {
+        let (event, mark) = parser.next().map_err(|e| {
+            // The message without the position it ends with.
+            let message = e.to_string();
+            let info = message
+                .rsplit_once(" at line ")
+                .map_or(&*message, |(info, _)| info);
+            let span = builder.span(e.marker(), 1);
+            Located::new(ParseError::InvalidYaml(info.into()), Some(span))
+        })?;
+        if event == Event::StreamEnd {
+            break;
+        }
+        builder.on_event(event, mark);
+    }
+    if let Some(error) = builder.error {
+        return Err(error);
+    }
+    builder.document.ok_or_else(|| {
+        Located::new(
+            ParseError::InvalidYaml("expected a document".into()),
+            Some(Span {
+                start: 0,
+                end: 0,
+                line: 1,
+                column: 1,
+                source: 0,
+            }),
+        )
+    })
+}
+
+/// Builds values from parser events.
+struct Builder<'a> {
+    source: &'a str,
+    /// The byte offset of each character, since markers count characters.
+    offsets: Vec<usize>,
+    /// Sequences and mappings whose end hasn't been seen yet.
+    open: Vec<Open>,
+    anchors: HashMap<usize, Json>,
+    document: Option<Json>,
+    /// The first problem found, after which no more events are read.
+    error: Option<Located<ParseError>>,
+}
+
+enum Open {
+    Sequence {
+        start: Span,
+        anchor: usize,
+        items: Vec<Json>,
+    },
+    Mapping {
+        start: Span,
+        anchor: usize,
+        entries: Vec<(String, Json)>,
+        key: Option<String>,
+    },
+}
+
+impl<'a> MarkedEventReceiver for Builder<'a> {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        if self.error.is_some() || self.document.is_some() {
+            return;
+        }
+        match event {
+            Event::Scalar(value, style, anchor, tag) => {
+                let quotes = match style {
+                    TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted => 2,
+                    _ => 0,
+                };
+                let span = self.span(&mark, value.chars().count() + quotes);
+                if let Some(Open::Mapping {
+                    key: key @ None, ..
+                }) = self.open.last_mut()
+                {
+                    *key = Some(value);
+                    return;
+                }
+                let kind = resolve(value, style, tag);
+                self.finish(Json { kind, span }, anchor);
+            }
+            Event::SequenceStart(anchor) => self.start(
+                &mark,
+                Open::Sequence {
+                    start: self.span(&mark, 0),
+                    anchor,
+                    items: Vec::new(),
+                },
+            ),
+            Event::MappingStart(anchor) => self.start(
+                &mark,
+                Open::Mapping {
+                    start: self.span(&mark, 0),
+                    anchor,
+                    entries: Vec::new(),
+                    key: None,
+                },
+            ),
+            Event::SequenceEnd | Event::MappingEnd => {
+                let end = self.span(&mark, 0).end;
+                let (kind, start, anchor) = match self.open.pop() {
+                    Some(Open::Sequence {
+                        start,
+                        anchor,
+                        items,
+                    }) => (JsonKind::Array(items), start, anchor),
+                    Some(Open::Mapping {
+                        start,
+                        anchor,
+                        entries,
+                        ..
+                    }) => (JsonKind::Object(entries), start, anchor),
+                    None => return,
+                };
+                let span = Span {
+                    end: end.max(start.end),
+                    ..start
+                };
+                self.finish(Json { kind, span }, anchor);
+            }
+            Event::Alias(id) => match self.anchors.get(&id).cloned() {
+                Some(value) => self.finish(value, 0),
+                None => self.fail("an anchor defined before its alias", &mark),
+            },
+            _ => {}
+        }
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// The span of `len` characters from `mark`.
+    fn span(&self, mark: &Marker, len: usize) -> Span {
+        let offset = |index: usize| {
+            self.offsets
+                .get(index)
+                .copied()
+                .unwrap_or(self.source.len())
+        };
+        Span {
+            start: offset(mark.index()),
+            end: offset(mark.index() + len),
+            line: mark.line(),
+            column: mark.col() + 1,
+            source: 0,
+        }
+    }
+
+    fn start(&mut self, mark: &Marker, open: Open) {
+        if let Some(Open::Mapping { key: None, .. }) = self.open.last() {
+            return self.fail("a scalar key", mark);
+        }
+        if self.open.len() == MAX_DEPTH {
+            return self.fail(&format!("at most {} levels of nesting", MAX_DEPTH), mark);
+        }
+        self.open.push(open);
+    }
+
+    /// Adds a complete value to the sequence or mapping it's in, or makes it the
+    /// document.
+    fn finish(&mut self, value: Json, anchor: usize) {
+        if anchor != 0 {
+            self.anchors.insert(anchor, value.clone());
+        }
+        match self.open.last_mut() {
+            Some(Open::Sequence { items, .. }) => items.push(value),
+            Some(Open::Mapping { entries, key, .. }) => match key.take() {
+                Some(key) => entries.push((key, value)),
+                None => {
+                    self.error = Some(Located::new(
+                        ParseError::InvalidYaml("expected a scalar key".into()),
+                        Some(value.span),
+                    ))
+                }
+            },
+            None => self.document = Some(value),
+        }
+    }
+
+    fn fail(&mut self, expected: &str, mark: &Marker) {
+        self.error = Some(Located::new(
+            ParseError::InvalidYaml(format!("expected {}", expected)),
+            Some(self.span(mark, 1)),
+        ));
+    }
+}
+
+/// The JSON value of a scalar. Numbers are written in JSON syntax where possible.
+fn resolve(value: String, style: TScalarStyle, tag: Option<TokenType>) -> JsonKind {
+    let explicit_string = matches!(
+        &tag,
+        Some(TokenType::Tag(handle, suffix)) if handle == "!!" && suffix == "str"
+    );
+    if style != TScalarStyle::Plain || explicit_string {
+        return JsonKind::String(value);
+    }
+    match Yaml::from_str(&value) {
+        Yaml::Null => JsonKind::Null,
+        Yaml::Boolean(b) => JsonKind::Bool(b),
+        Yaml::Integer(n) => JsonKind::Number(n.to_string()),
+        Yaml::Real(n) => JsonKind::Number(n.trim_start_matches('+').into()),
+        _ => JsonKind::String(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jir::{Format, JirParser};
+
+    #[test]
+    fn it_reads_yaml_like_its_json() -> Result<(), Located<ParseError>> {
+        let yaml = r#"
+# Defaults are anchored and reused.
+- $do
+- - $let
+  - point
+  - - $new
+    - Point
+    - &origin {x: 0, "y": -1.5e3}
+- [$call, [$ref, f], ~, true, '1', 0x1F, *origin]
+- |
+  two
+  lines
+"#;
+        let json = r#"["$do",
+            ["$let", "point", ["$new", "Point", {"x": 0, "y": -1.5e3}]],
+            ["$call", ["$ref", "f"], null, true, "1", 31, {"x": 0, "y": -1.5e3}],
+            "two\nlines\n"]"#;
+
+        assert_eq!(
+            parse(yaml)?.to_string(),
+            crate::json::parse(json)?.to_string()
+        );
+        assert_eq!(
+            format!("{:?}", JirParser::parse_as(yaml, Format::Yaml)?),
+            format!("{:?}", JirParser::parse(json)?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_errors_at_yaml_positions() {
+        let source = "- $add\n- 1\n- [$ref, 1]\n";
+        let error = JirParser::parse_as(source, Format::Yaml).expect_err("not an ident");
+        assert!(matches!(error.error, ParseError::IdentExpected));
+        assert_eq!(error.span.map(|s| (s.line, s.column)), Some((3, 10)));
+
+        let error = parse("a: [1, 2\nb: 3").expect_err("unclosed sequence");
+        assert!(matches!(error.error, ParseError::InvalidYaml(_)));
+        assert_eq!(error.span.map(|s| s.line), Some(2));
+
+        let error = parse("? [1]\n: 2").expect_err("sequence key");
+        assert_eq!(error.span.map(|s| (s.line, s.column)), Some((1, 3)));
+
+        let deep = format!("{}1", "- ".repeat(10_000));
+        let error = parse(&deep).expect_err("too deep");
+        assert!(matches!(error.error, ParseError::InvalidYaml(_)));
+        assert_eq!(error.span.map(|s| s.column), Some(2 * MAX_DEPTH + 1));
+    }
+}